@@ -1,4 +1,240 @@
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+
+use flate2::{Compression, Crc};
+
+/// Placeholders substituted by http server (`AssetVar` variants),
+/// texts of negotiated language are `{{t:key}}`
+const TEMPLATE_VARS: &[(&str, &str)] = &[
+    ("device_name", "DeviceName"),
+    ("mac", "Mac"),
+    ("version", "Version"),
+    ("hostname", "Hostname"),
+    ("lang", "Lang"),
+];
+
+/// Built-in portal pages (path, file in `src/`)
+const BUILTIN_ASSETS: &[(&str, &str)] = &[
+    ("/", "panel.html"),
+    ("/done.html", "success.html"),
+    ("/favicon.ico", "favicon.ico"),
+];
+
+/// Admin pages served on station network (`WmReturn::start_admin`)
+const ADMIN_ASSETS: &[(&str, &str)] = &[("/", "admin.html"), ("/favicon.ico", "favicon.ico")];
+
 fn main() {
     // This tells Cargo to rerun build script if WM_CONN env var changes
     println!("cargo:rerun-if-env-changed=WM_CONN");
+    println!("cargo:rerun-if-env-changed=WM_ASSETS_DIR");
+
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    let src_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+
+    for (name, table) in [("builtin", BUILTIN_ASSETS), ("admin", ADMIN_ASSETS)] {
+        let assets: Vec<(String, PathBuf)> = table
+            .iter()
+            .map(|(path, file)| (path.to_string(), src_dir.join(file)))
+            .collect();
+        write_table(&out_dir, name, &assets);
+    }
+
+    // branding: every file from WM_ASSETS_DIR, `index.html` is served as `/`
+    let mut custom = Vec::new();
+    if let Ok(dir) = std::env::var("WM_ASSETS_DIR") {
+        let dir = PathBuf::from(dir);
+        println!("cargo:rerun-if-changed={}", dir.display());
+        collect_files(&dir, &dir, &mut custom);
+        custom.sort();
+    }
+    write_table(&out_dir, "custom", &custom);
+}
+
+fn collect_files(root: &Path, dir: &Path, out: &mut Vec<(String, PathBuf)>) {
+    let entries = std::fs::read_dir(dir)
+        .unwrap_or_else(|e| panic!("WM_ASSETS_DIR: cannot read {}: {e}", dir.display()));
+    for entry in entries {
+        let path = entry.unwrap().path();
+        println!("cargo:rerun-if-changed={}", path.display());
+        if path.is_dir() {
+            collect_files(root, &path, out);
+            continue;
+        }
+
+        let relative = path.strip_prefix(root).unwrap();
+        let mut url = String::new();
+        for part in relative.components() {
+            url.push('/');
+            url.push_str(&part.as_os_str().to_string_lossy());
+        }
+        if let Some(dir_url) = url.strip_suffix("/index.html") {
+            url = format!("{dir_url}/");
+        }
+        out.push((url, path));
+    }
+}
+
+/// Writes `{name}_assets.rs` with `&[StaticAsset]` (compressed bodies in separate files)
+fn write_table(out_dir: &Path, name: &str, assets: &[(String, PathBuf)]) {
+    let mut table = String::from("&[\n");
+    for (i, (url, file)) in assets.iter().enumerate() {
+        println!("cargo:rerun-if-changed={}", file.display());
+        let data = std::fs::read(file)
+            .unwrap_or_else(|e| panic!("cannot read asset {}: {e}", file.display()));
+        let ext = file
+            .extension()
+            .map(|e| e.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        let (content_type, compress, text) = content_type(&ext);
+
+        let segments = split_template(&data);
+        let body = if text && segments.iter().any(|s| matches!(s, Segment::Var(_))) {
+            let mut parts = String::new();
+            for (j, segment) in segments.iter().enumerate() {
+                match segment {
+                    Segment::Text(text) => {
+                        let bin = write_bin(
+                            out_dir,
+                            &format!("{name}_{i}_{j}.deflate"),
+                            &deflate_sync(text),
+                        );
+                        let mut crc = Crc::new();
+                        crc.update(text);
+                        _ = write!(
+                            parts,
+                            "TemplatePart::Deflate {{ data: include_bytes!({bin:?}), len: {}, crc: {:#010x} }}, ",
+                            text.len(),
+                            crc.sum()
+                        );
+                    }
+                    Segment::Var(var) => _ = write!(parts, "TemplatePart::Var(AssetVar::{var}), "),
+                }
+            }
+            format!("AssetBody::GzipTemplate(&[{parts}])")
+        } else {
+            let gzipped = compress
+                .then(|| gzip(&data))
+                .filter(|gz| gz.len() < data.len());
+            match gzipped {
+                Some(gz) => {
+                    let bin = write_bin(out_dir, &format!("{name}_{i}.gz"), &gz);
+                    format!("AssetBody::Gzip(include_bytes!({bin:?}))")
+                }
+                None => format!(
+                    "AssetBody::Raw(include_bytes!({:?}))",
+                    file.display().to_string()
+                ),
+            }
+        };
+
+        _ = writeln!(
+            table,
+            "    StaticAsset {{ path: {url:?}, content_type: {content_type:?}, body: {body} }},"
+        );
+    }
+    table.push(']');
+
+    std::fs::write(out_dir.join(format!("{name}_assets.rs")), table).unwrap();
+}
+
+/// Content type, if compression helps, if placeholders are substituted
+fn content_type(ext: &str) -> (&'static str, bool, bool) {
+    match ext {
+        "html" | "htm" => ("text/html; charset=utf-8", true, true),
+        "css" => ("text/css", true, true),
+        "js" | "mjs" => ("text/javascript", true, true),
+        "json" => ("application/json", true, true),
+        "txt" => ("text/plain; charset=utf-8", true, true),
+        "svg" => ("image/svg+xml", true, true),
+        "ico" => ("image/x-icon", true, false),
+        "wasm" => ("application/wasm", true, false),
+        "png" => ("image/png", false, false),
+        "jpg" | "jpeg" => ("image/jpeg", false, false),
+        "gif" => ("image/gif", false, false),
+        "webp" => ("image/webp", false, false),
+        "woff" => ("font/woff", false, false),
+        "woff2" => ("font/woff2", false, false),
+        _ => ("application/octet-stream", true, false),
+    }
+}
+
+enum Segment<'a> {
+    Text(&'a [u8]),
+
+    /// `AssetVar` variant
+    Var(String),
+}
+
+fn split_template(data: &[u8]) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut pos = 0;
+    while pos < data.len() {
+        let var = data[pos..]
+            .strip_prefix(b"{{")
+            .and_then(|rest| {
+                rest.windows(2)
+                    .position(|w| w == b"}}")
+                    .map(|end| &rest[..end])
+            })
+            .and_then(|name| {
+                Some((
+                    name.len() + 4,
+                    template_var(std::str::from_utf8(name).ok()?)?,
+                ))
+            });
+        match var {
+            Some((placeholder_len, var)) => {
+                if start < pos {
+                    segments.push(Segment::Text(&data[start..pos]));
+                }
+                segments.push(Segment::Var(var));
+                pos += placeholder_len;
+                start = pos;
+            }
+            None => pos += 1,
+        }
+    }
+    if start < data.len() {
+        segments.push(Segment::Text(&data[start..]));
+    }
+    segments
+}
+
+/// `AssetVar` for placeholder name, `None` if it isnt placeholder
+fn template_var(name: &str) -> Option<String> {
+    if let Some(key) = name.strip_prefix("t:") {
+        let valid = !key.is_empty()
+            && key
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_');
+        return valid.then(|| format!("Text({key:?})"));
+    }
+    TEMPLATE_VARS
+        .iter()
+        .find(|(placeholder, _)| *placeholder == name)
+        .map(|(_, var)| var.to_string())
+}
+
+/// Raw deflate ending with sync flush (byte aligned, not final),
+/// so segments and stored blocks can be concatenated at runtime
+fn deflate_sync(data: &[u8]) -> Vec<u8> {
+    let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data).unwrap();
+    // flush emits sync flush, finish would add final block
+    encoder.flush().unwrap();
+    encoder.get_ref().clone()
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn write_bin(out_dir: &Path, name: &str, data: &[u8]) -> String {
+    let path = out_dir.join(name);
+    std::fs::write(&path, data).unwrap();
+    path.display().to_string()
 }
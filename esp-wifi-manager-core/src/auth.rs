@@ -0,0 +1,204 @@
+use alloc::{string::String, vec::Vec};
+use sha2::{Digest, Sha256};
+
+/// Basic auth user and password can be sent (user without `:`, both non-empty)
+pub fn basic_credentials_valid(user: &str, password: &str) -> bool {
+    !user.is_empty() && !user.contains(':') && !password.is_empty()
+}
+
+/// Bearer token can be sent (non-empty, without whitespace)
+pub fn token_valid(token: &str) -> bool {
+    !token.is_empty() && !token.contains([' ', '\r', '\n'])
+}
+
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut inner = hmac_inner(key);
+    inner.update(message);
+    hmac_finalize(key, inner)
+}
+
+/// Hasher of HMAC inner message (message is added with `update`)
+pub fn hmac_inner(key: &[u8]) -> Sha256 {
+    let mut inner = Sha256::new();
+    inner.update(hmac_key_block(key).map(|b| b ^ 0x36));
+    inner
+}
+
+pub fn hmac_finalize(key: &[u8], inner: Sha256) -> [u8; 32] {
+    let mut outer = Sha256::new();
+    outer.update(hmac_key_block(key).map(|b| b ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+fn hmac_key_block(key: &[u8]) -> [u8; 64] {
+    let mut block = [0u8; 64];
+    if key.len() > block.len() {
+        block[..32].copy_from_slice(&sha256(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    block
+}
+
+/// Compares without early exit (time doesnt depend on position of first difference)
+pub fn constant_time_eq(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    let diff = a.iter().zip(b).fold(0u8, |diff, (a, b)| diff | (a ^ b));
+    core::hint::black_box(diff) == 0
+}
+
+/// Lowercase RFC 4648 base32 without padding
+pub fn base32(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+    let mut out = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for &b in data {
+        buffer = (buffer << 8) | b as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(ALPHABET[(buffer >> bits) as usize & 31] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(ALPHABET[(buffer << (5 - bits)) as usize & 31] as char);
+    }
+    out
+}
+
+/// Standard base64 (padding optional), `None` if invalid
+pub fn base64_decode(data: &str) -> Option<Vec<u8>> {
+    fn value(c: u8) -> Option<u32> {
+        Some(match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        } as u32)
+    }
+
+    let data = data.trim_end_matches('=').as_bytes();
+    if data.len() % 4 == 1 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(data.len() * 3 / 4);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for &c in data {
+        buffer = (buffer << 6) | value(c)?;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> [u8; 32] {
+        let mut out = [0u8; 32];
+        for (i, byte) in out.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn hmac_rfc4231_vectors() {
+        assert_eq!(
+            hmac_sha256(&[0x0b; 20], b"Hi There"),
+            hex("b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7")
+        );
+        assert_eq!(
+            hmac_sha256(b"Jefe", b"what do ya want for nothing?"),
+            hex("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843")
+        );
+        // key longer than block is hashed first
+        assert_eq!(
+            hmac_sha256(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            ),
+            hex("60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54")
+        );
+    }
+
+    #[test]
+    fn hmac_streamed_equals_one_shot() {
+        let mut inner = hmac_inner(b"Jefe");
+        inner.update(b"what do ya ");
+        inner.update(b"want for nothing?");
+        assert_eq!(
+            hmac_finalize(b"Jefe", inner),
+            hmac_sha256(b"Jefe", b"what do ya want for nothing?")
+        );
+    }
+
+    #[test]
+    fn constant_time_eq_compares_all_bytes() {
+        let a = [7u8; 32];
+        let mut b = a;
+        assert!(constant_time_eq(&a, &b));
+        for i in [0, 15, 31] {
+            b = a;
+            b[i] ^= 1;
+            assert!(!constant_time_eq(&a, &b));
+        }
+    }
+
+    #[test]
+    fn base32_rfc4648_vectors() {
+        let vectors = [
+            ("", ""),
+            ("f", "my"),
+            ("fo", "mzxq"),
+            ("foo", "mzxw6"),
+            ("foob", "mzxw6yq"),
+            ("fooba", "mzxw6ytb"),
+            ("foobar", "mzxw6ytboi"),
+        ];
+        for (data, encoded) in vectors {
+            assert_eq!(base32(data.as_bytes()), encoded);
+        }
+        // per device password length
+        assert_eq!(base32(&[0xff; 10]).len(), 16);
+    }
+
+    #[test]
+    fn base64_decodes_with_and_without_padding() {
+        assert_eq!(base64_decode("Zm9vYmFy").unwrap(), b"foobar");
+        assert_eq!(base64_decode("Zm9vYg==").unwrap(), b"foob");
+        assert_eq!(base64_decode("Zm9vYg").unwrap(), b"foob");
+        assert_eq!(base64_decode("dXNlcjpwYXNz").unwrap(), b"user:pass");
+        assert_eq!(base64_decode("").unwrap(), b"");
+    }
+
+    #[test]
+    fn base64_rejects_invalid_input() {
+        for data in ["Z", "Zm9vY", "Zm9v!", "Zm 9v", "Zm9v-_", "=Zm9v"] {
+            assert_eq!(base64_decode(data), None, "{data}");
+        }
+    }
+
+    #[test]
+    fn validates_credentials() {
+        assert!(basic_credentials_valid("admin", "secret"));
+        assert!(!basic_credentials_valid("", "secret"));
+        assert!(!basic_credentials_valid("ad:min", "secret"));
+        assert!(!basic_credentials_valid("admin", ""));
+        assert!(token_valid("abc"));
+        assert!(!token_valid(""));
+        assert!(!token_valid("a b"));
+    }
+}
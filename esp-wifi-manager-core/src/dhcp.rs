@@ -0,0 +1,432 @@
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+
+pub const DHCP_SERVER_PORT: u16 = 67;
+pub const DHCP_CLIENT_PORT: u16 = 68;
+
+/// Fixed bootp part + magic cookie
+const OPTIONS_OFFSET: usize = 240;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+
+const OPT_PAD: u8 = 0;
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS: u8 = 6;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_CAPTIVE_PORTAL: u8 = 114;
+const OPT_END: u8 = 255;
+
+/// Dhcp message type (option 53)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageType {
+    Discover,
+    Offer,
+    Request,
+    Decline,
+    Ack,
+    Nak,
+    Release,
+    Inform,
+}
+
+impl MessageType {
+    fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            1 => Self::Discover,
+            2 => Self::Offer,
+            3 => Self::Request,
+            4 => Self::Decline,
+            5 => Self::Ack,
+            6 => Self::Nak,
+            7 => Self::Release,
+            8 => Self::Inform,
+            _ => return None,
+        })
+    }
+
+    fn as_u8(self) -> u8 {
+        match self {
+            Self::Discover => 1,
+            Self::Offer => 2,
+            Self::Request => 3,
+            Self::Decline => 4,
+            Self::Ack => 5,
+            Self::Nak => 6,
+            Self::Release => 7,
+            Self::Inform => 8,
+        }
+    }
+}
+
+/// Client message (fields server needs to answer it)
+#[derive(Debug, Clone, PartialEq)]
+pub struct DhcpRequest {
+    pub kind: MessageType,
+    pub xid: [u8; 4],
+    pub flags: u16,
+    pub ciaddr: Ipv4Addr,
+    pub chaddr: [u8; 6],
+
+    /// Option 50
+    pub requested_ip: Option<Ipv4Addr>,
+
+    /// Option 54 (server selected by client)
+    pub server_id: Option<Ipv4Addr>,
+}
+
+impl DhcpRequest {
+    /// Parses client message, `None` for replies, non-ethernet clients and malformed packets
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < OPTIONS_OFFSET
+            || data[0] != BOOTREQUEST
+            || data[1] != HTYPE_ETHERNET
+            || data[2] != 6
+            || data[236..240] != MAGIC_COOKIE
+        {
+            return None;
+        }
+
+        let mut kind = None;
+        let mut requested_ip = None;
+        let mut server_id = None;
+        let mut pos = OPTIONS_OFFSET;
+        while let Some(&code) = data.get(pos) {
+            match code {
+                OPT_PAD => {
+                    pos += 1;
+                    continue;
+                }
+                OPT_END => break,
+                _ => {}
+            }
+            let len = *data.get(pos + 1)? as usize;
+            let value = data.get(pos + 2..pos + 2 + len)?;
+            match (code, value) {
+                (OPT_MESSAGE_TYPE, [kind_value]) => kind = MessageType::from_u8(*kind_value),
+                (OPT_REQUESTED_IP, &[a, b, c, d]) => requested_ip = Some(Ipv4Addr::new(a, b, c, d)),
+                (OPT_SERVER_ID, &[a, b, c, d]) => server_id = Some(Ipv4Addr::new(a, b, c, d)),
+                _ => {}
+            }
+            pos += 2 + len;
+        }
+
+        let mut chaddr = [0; 6];
+        chaddr.copy_from_slice(&data[28..34]);
+        Some(Self {
+            kind: kind?,
+            xid: [data[4], data[5], data[6], data[7]],
+            flags: u16::from_be_bytes([data[10], data[11]]),
+            ciaddr: Ipv4Addr::new(data[12], data[13], data[14], data[15]),
+            chaddr,
+            requested_ip,
+            server_id,
+        })
+    }
+
+    /// Where reply of given type goes: client address if it has one,
+    /// broadcast otherwise (client without address cant answer arp) and for nak
+    pub fn reply_addr(&self, kind: MessageType) -> Ipv4Addr {
+        if kind == MessageType::Nak || self.ciaddr.is_unspecified() {
+            Ipv4Addr::BROADCAST
+        } else {
+            self.ciaddr
+        }
+    }
+}
+
+/// Server configuration sent in every offer / ack
+#[derive(Debug, Clone)]
+pub struct DhcpOptions<'a> {
+    /// Server id, router
+    pub server_ip: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub dns: Ipv4Addr,
+    pub lease_secs: u32,
+
+    /// RFC 8910 captive portal API uri (option 114)
+    pub captive_portal: Option<&'a str>,
+}
+
+/// Builds reply of given type into `out`
+///
+/// `Nak` only carries server id, `yiaddr` is ignored for it.
+/// Returns `None` if `out` is too small.
+pub fn build_reply(
+    request: &DhcpRequest,
+    kind: MessageType,
+    yiaddr: Ipv4Addr,
+    options: &DhcpOptions,
+    out: &mut [u8],
+) -> Option<usize> {
+    let mut reply = Vec::with_capacity(300);
+    reply.extend_from_slice(&[BOOTREPLY, HTYPE_ETHERNET, 6, 0]);
+    reply.extend_from_slice(&request.xid);
+    reply.extend_from_slice(&[0, 0]); // secs
+    reply.extend_from_slice(&request.flags.to_be_bytes());
+    let yiaddr = if kind == MessageType::Nak {
+        Ipv4Addr::UNSPECIFIED
+    } else {
+        yiaddr
+    };
+    reply.extend_from_slice(&request.ciaddr.octets());
+    reply.extend_from_slice(&yiaddr.octets());
+    reply.extend_from_slice(&[0; 8]); // siaddr, giaddr
+    reply.extend_from_slice(&request.chaddr);
+    reply.resize(OPTIONS_OFFSET - 4, 0); // chaddr padding, sname, file
+    reply.extend_from_slice(&MAGIC_COOKIE);
+
+    let mut option = |code: u8, value: &[u8]| {
+        reply.push(code);
+        reply.push(value.len() as u8);
+        reply.extend_from_slice(value);
+    };
+    option(OPT_MESSAGE_TYPE, &[kind.as_u8()]);
+    option(OPT_SERVER_ID, &options.server_ip.octets());
+    if kind != MessageType::Nak {
+        option(OPT_LEASE_TIME, &options.lease_secs.to_be_bytes());
+        option(OPT_SUBNET_MASK, &options.netmask.octets());
+        option(OPT_ROUTER, &options.server_ip.octets());
+        option(OPT_DNS, &options.dns.octets());
+        if let Some(uri) = options.captive_portal {
+            option(OPT_CAPTIVE_PORTAL, &uri.as_bytes()[..uri.len().min(255)]);
+        }
+    }
+    reply.push(OPT_END);
+    // some clients drop replies shorter than minimal bootp message
+    reply.resize(reply.len().max(300), OPT_PAD);
+
+    out.get_mut(..reply.len())?.copy_from_slice(&reply);
+    Some(reply.len())
+}
+
+struct Lease {
+    mac: [u8; 6],
+    ip: Ipv4Addr,
+
+    /// Expiry (seconds, same clock as `now` passed to `DhcpLeases`)
+    expires: u64,
+}
+
+/// Leases from address pool, every client (mac) gets own address
+pub struct DhcpLeases {
+    start: u32,
+    end: u32,
+    leases: Vec<Lease>,
+}
+
+impl DhcpLeases {
+    /// Pool from `start` to `end` (inclusive)
+    pub fn new(start: Ipv4Addr, end: Ipv4Addr) -> Self {
+        Self {
+            start: start.into(),
+            end: end.into(),
+            leases: Vec::new(),
+        }
+    }
+
+    /// Address to offer: current lease of client, requested address if it is
+    /// free, otherwise first free one (`None` if pool is exhausted)
+    pub fn offer(&self, mac: [u8; 6], requested: Option<Ipv4Addr>, now: u64) -> Option<Ipv4Addr> {
+        if let Some(lease) = self.leases.iter().find(|lease| lease.mac == mac) {
+            return Some(lease.ip);
+        }
+        if let Some(ip) = requested.filter(|&ip| self.available(ip, mac, now)) {
+            return Some(ip);
+        }
+        (self.start..=self.end)
+            .map(Ipv4Addr::from)
+            .find(|&ip| self.available(ip, mac, now))
+    }
+
+    /// Leases `ip` to client, `false` if it isnt in pool or is leased to other client
+    pub fn ack(&mut self, mac: [u8; 6], ip: Ipv4Addr, now: u64, lease_secs: u32) -> bool {
+        if !self.available(ip, mac, now) {
+            return false;
+        }
+        self.leases
+            .retain(|lease| lease.mac != mac && lease.ip != ip);
+        self.leases.push(Lease {
+            mac,
+            ip,
+            expires: now + lease_secs as u64,
+        });
+        true
+    }
+
+    /// Client released (or declined) its address
+    pub fn release(&mut self, mac: [u8; 6]) {
+        self.leases.retain(|lease| lease.mac != mac);
+    }
+
+    fn available(&self, ip: Ipv4Addr, mac: [u8; 6], now: u64) -> bool {
+        let in_pool = (self.start..=self.end).contains(&u32::from(ip));
+        in_pool
+            && !self
+                .leases
+                .iter()
+                .any(|lease| lease.ip == ip && lease.mac != mac && lease.expires > now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 1];
+    const OTHER_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 2];
+    const SERVER: Ipv4Addr = Ipv4Addr::new(192, 168, 4, 1);
+
+    fn message(kind: u8, options: &[u8]) -> Vec<u8> {
+        let mut data = Vec::from([BOOTREQUEST, HTYPE_ETHERNET, 6, 0, 1, 2, 3, 4, 0, 0, 0x80, 0]);
+        data.resize(28, 0);
+        data.extend_from_slice(&MAC);
+        data.resize(236, 0);
+        data.extend_from_slice(&MAGIC_COOKIE);
+        data.extend_from_slice(&[OPT_MESSAGE_TYPE, 1, kind]);
+        data.extend_from_slice(options);
+        data.push(OPT_END);
+        data
+    }
+
+    fn options() -> DhcpOptions<'static> {
+        DhcpOptions {
+            server_ip: SERVER,
+            netmask: Ipv4Addr::new(255, 255, 255, 0),
+            dns: SERVER,
+            lease_secs: 3600,
+            captive_portal: Some("http://192.168.4.1/captive-portal/api"),
+        }
+    }
+
+    /// Option values of reply (code, value)
+    fn reply_options(reply: &[u8]) -> Vec<(u8, &[u8])> {
+        let mut res = Vec::new();
+        let mut pos = OPTIONS_OFFSET;
+        while reply[pos] != OPT_END {
+            let len = reply[pos + 1] as usize;
+            res.push((reply[pos], &reply[pos + 2..pos + 2 + len]));
+            pos += 2 + len;
+        }
+        res
+    }
+
+    #[test]
+    fn parses_request() {
+        let data = message(3, &[OPT_PAD, 50, 4, 192, 168, 4, 7, 54, 4, 192, 168, 4, 1]);
+        let request = DhcpRequest::parse(&data).unwrap();
+        assert_eq!(request.kind, MessageType::Request);
+        assert_eq!(request.xid, [1, 2, 3, 4]);
+        assert_eq!(request.flags, 0x8000);
+        assert_eq!(request.chaddr, MAC);
+        assert_eq!(request.requested_ip, Some(Ipv4Addr::new(192, 168, 4, 7)));
+        assert_eq!(request.server_id, Some(SERVER));
+        assert_eq!(request.reply_addr(MessageType::Ack), Ipv4Addr::BROADCAST);
+
+        let mut renew = data.clone();
+        renew[12..16].copy_from_slice(&[192, 168, 4, 7]);
+        let renew = DhcpRequest::parse(&renew).unwrap();
+        assert_eq!(renew.ciaddr, Ipv4Addr::new(192, 168, 4, 7));
+        assert_eq!(renew.reply_addr(MessageType::Ack), renew.ciaddr);
+        assert_eq!(renew.reply_addr(MessageType::Nak), Ipv4Addr::BROADCAST);
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        let data = message(1, &[]);
+        assert!(DhcpRequest::parse(&data[..OPTIONS_OFFSET - 1]).is_none());
+
+        let mut reply = data.clone();
+        reply[0] = BOOTREPLY;
+        assert!(DhcpRequest::parse(&reply).is_none());
+
+        let mut cookie = data.clone();
+        cookie[236] = 0;
+        assert!(DhcpRequest::parse(&cookie).is_none());
+
+        // no message type, unknown type, option running past the end
+        assert!(DhcpRequest::parse(&data[..OPTIONS_OFFSET]).is_none());
+        assert!(DhcpRequest::parse(&message(42, &[])).is_none());
+        let mut cut = message(1, &[]);
+        cut.truncate(cut.len() - 1);
+        cut.extend_from_slice(&[50, 4, 192]);
+        assert!(DhcpRequest::parse(&cut).is_none());
+    }
+
+    #[test]
+    fn builds_offer_with_captive_portal_uri() {
+        let request = DhcpRequest::parse(&message(1, &[])).unwrap();
+        let mut out = [0u8; 576];
+        let yiaddr = Ipv4Addr::new(192, 168, 4, 2);
+        let len = build_reply(&request, MessageType::Offer, yiaddr, &options(), &mut out).unwrap();
+        let reply = &out[..len];
+
+        assert!(len >= 300);
+        assert_eq!(reply[..4], [BOOTREPLY, HTYPE_ETHERNET, 6, 0]);
+        assert_eq!(reply[4..8], request.xid);
+        assert_eq!(reply[10..12], [0x80, 0]);
+        assert_eq!(reply[16..20], yiaddr.octets());
+        assert_eq!(reply[28..34], MAC);
+        assert_eq!(reply[236..240], MAGIC_COOKIE);
+
+        let options = reply_options(reply);
+        assert!(options.contains(&(OPT_MESSAGE_TYPE, &[2][..])));
+        assert!(options.contains(&(OPT_SERVER_ID, &SERVER.octets()[..])));
+        assert!(options.contains(&(OPT_LEASE_TIME, &3600u32.to_be_bytes()[..])));
+        assert!(options.contains(&(OPT_SUBNET_MASK, &[255, 255, 255, 0][..])));
+        assert!(options.contains(&(OPT_ROUTER, &SERVER.octets()[..])));
+        assert!(options.contains(&(OPT_DNS, &SERVER.octets()[..])));
+        assert!(options.contains(&(
+            OPT_CAPTIVE_PORTAL,
+            &b"http://192.168.4.1/captive-portal/api"[..]
+        )));
+    }
+
+    #[test]
+    fn nak_has_no_address_or_config() {
+        let request = DhcpRequest::parse(&message(3, &[])).unwrap();
+        let mut out = [0u8; 576];
+        let ip = Ipv4Addr::new(192, 168, 4, 2);
+        let len = build_reply(&request, MessageType::Nak, ip, &options(), &mut out).unwrap();
+        assert_eq!(out[16..20], [0; 4]);
+        let reply = reply_options(&out[..len]);
+        assert_eq!(
+            reply,
+            [
+                (OPT_MESSAGE_TYPE, &[6][..]),
+                (OPT_SERVER_ID, &SERVER.octets()[..])
+            ]
+        );
+        assert!(build_reply(&request, MessageType::Nak, ip, &options(), &mut out[..299]).is_none());
+    }
+
+    #[test]
+    fn leases_own_address_per_client() {
+        let mut leases =
+            DhcpLeases::new(Ipv4Addr::new(192, 168, 4, 2), Ipv4Addr::new(192, 168, 4, 3));
+        let first = Ipv4Addr::new(192, 168, 4, 2);
+        let second = Ipv4Addr::new(192, 168, 4, 3);
+
+        assert_eq!(leases.offer(MAC, None, 0), Some(first));
+        assert!(leases.ack(MAC, first, 0, 60));
+        // same client gets the same address, other client the next one
+        assert_eq!(leases.offer(MAC, Some(second), 10), Some(first));
+        assert_eq!(leases.offer(OTHER_MAC, Some(first), 10), Some(second));
+        assert!(!leases.ack(OTHER_MAC, first, 10, 60));
+        assert!(leases.ack(OTHER_MAC, second, 10, 60));
+
+        // pool exhausted, address outside of pool
+        assert_eq!(leases.offer([0x02, 0, 0, 0, 0, 3], None, 20), None);
+        assert!(!leases.ack(MAC, Ipv4Addr::new(192, 168, 4, 9), 20, 60));
+
+        // expired and released addresses can be leased again
+        assert_eq!(leases.offer([0x02, 0, 0, 0, 0, 3], None, 61), Some(first));
+        leases.release(OTHER_MAC);
+        assert!(leases.ack([0x02, 0, 0, 0, 0, 3], second, 20, 60));
+    }
+}
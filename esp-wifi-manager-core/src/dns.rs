@@ -0,0 +1,147 @@
+use core::net::Ipv4Addr;
+
+const DNS_HEADER_LEN: usize = 12;
+const DNS_TYPE_A: u16 = 1;
+const DNS_TYPE_ANY: u16 = 255;
+const DNS_CLASS_IN: u16 = 1;
+const DNS_ANSWER_TTL: u32 = 60;
+
+/// Builds response for given dns query into `out`
+///
+/// A (and ANY) queries get `ip` as the answer, other types get empty
+/// answer (NOERROR). Returns `None` for malformed queries and responses.
+pub fn build_dns_response(query: &[u8], ip: Ipv4Addr, out: &mut [u8]) -> Option<usize> {
+    if query.len() < DNS_HEADER_LEN {
+        return None;
+    }
+
+    let flags = u16::from_be_bytes([query[2], query[3]]);
+    let is_response = flags & 0x8000 != 0;
+    let opcode = (flags >> 11) & 0xF;
+    let qdcount = u16::from_be_bytes([query[4], query[5]]);
+    if is_response || opcode != 0 || qdcount == 0 {
+        return None;
+    }
+
+    // only first question is answered
+    let mut pos = DNS_HEADER_LEN;
+    loop {
+        let label_len = *query.get(pos)? as usize;
+        if label_len == 0 {
+            pos += 1;
+            break;
+        }
+        if label_len & 0xC0 != 0 {
+            // compression isnt allowed in question
+            return None;
+        }
+        pos += 1 + label_len;
+    }
+    let qtype = u16::from_be_bytes([*query.get(pos)?, *query.get(pos + 1)?]);
+    let qclass = u16::from_be_bytes([*query.get(pos + 2)?, *query.get(pos + 3)?]);
+    let question_end = pos + 4;
+
+    let answer = (qtype == DNS_TYPE_A || qtype == DNS_TYPE_ANY) && qclass == DNS_CLASS_IN;
+    let answer_len = if answer { 16 } else { 0 };
+    if out.len() < question_end + answer_len {
+        return None;
+    }
+
+    out[..question_end].copy_from_slice(&query[..question_end]);
+    // QR=1, same opcode, AA=1, keep RD, RA=0, RCODE=0
+    let res_flags = 0x8000 | 0x0400 | (flags & 0x0100);
+    out[2..4].copy_from_slice(&res_flags.to_be_bytes());
+    out[4..6].copy_from_slice(&1u16.to_be_bytes()); // qdcount
+    out[6..8].copy_from_slice(&(answer as u16).to_be_bytes()); // ancount
+    out[8..12].fill(0); // nscount, arcount
+
+    if answer {
+        let a = &mut out[question_end..question_end + answer_len];
+        a[0..2].copy_from_slice(&0xC00Cu16.to_be_bytes()); // pointer to question name
+        a[2..4].copy_from_slice(&DNS_TYPE_A.to_be_bytes());
+        a[4..6].copy_from_slice(&DNS_CLASS_IN.to_be_bytes());
+        a[6..10].copy_from_slice(&DNS_ANSWER_TTL.to_be_bytes());
+        a[10..12].copy_from_slice(&4u16.to_be_bytes());
+        a[12..16].copy_from_slice(&ip.octets());
+    }
+
+    Some(question_end + answer_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    const IP: Ipv4Addr = Ipv4Addr::new(192, 168, 4, 1);
+
+    /// Query with id 0x1234, RD set and single question
+    fn query(labels: &[&str], qtype: u16) -> Vec<u8> {
+        let mut q = Vec::from([0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+        for label in labels {
+            q.push(label.len() as u8);
+            q.extend_from_slice(label.as_bytes());
+        }
+        q.push(0);
+        q.extend_from_slice(&qtype.to_be_bytes());
+        q.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+        q
+    }
+
+    #[test]
+    fn answers_a_query_with_gateway_ip() {
+        let q = query(&["connectivitycheck", "gstatic", "com"], DNS_TYPE_A);
+        let mut out = [0u8; 512];
+        let len = build_dns_response(&q, IP, &mut out).unwrap();
+        assert_eq!(len, q.len() + 16);
+        assert_eq!(out[..2], [0x12, 0x34]);
+        // QR, AA and RD copied from query
+        assert_eq!(out[2..4], [0x85, 0x00]);
+        assert_eq!(out[4..12], [0, 1, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(out[12..q.len()], q[12..]);
+        assert_eq!(out[q.len()..q.len() + 2], [0xc0, 0x0c]);
+        assert_eq!(out[len - 4..len], IP.octets());
+    }
+
+    #[test]
+    fn other_types_get_empty_answer() {
+        let q = query(&["example", "com"], 28);
+        let mut out = [0u8; 512];
+        assert_eq!(build_dns_response(&q, IP, &mut out), Some(q.len()));
+        assert_eq!(out[6..8], [0, 0]);
+    }
+
+    #[test]
+    fn rejects_malformed_queries() {
+        let mut out = [0u8; 512];
+        let q = query(&["example", "com"], DNS_TYPE_A);
+
+        assert_eq!(
+            build_dns_response(&q[..DNS_HEADER_LEN - 1], IP, &mut out),
+            None
+        );
+        // question cut in the middle of name and of type/class
+        assert_eq!(build_dns_response(&q[..15], IP, &mut out), None);
+        assert_eq!(build_dns_response(&q[..q.len() - 1], IP, &mut out), None);
+
+        let mut response = q.clone();
+        response[2] |= 0x80;
+        assert_eq!(build_dns_response(&response, IP, &mut out), None);
+
+        let mut no_question = q.clone();
+        no_question[5] = 0;
+        assert_eq!(build_dns_response(&no_question, IP, &mut out), None);
+
+        let mut compressed = q.clone();
+        compressed[12] = 0xc0;
+        assert_eq!(build_dns_response(&compressed, IP, &mut out), None);
+    }
+
+    #[test]
+    fn output_buffer_too_small() {
+        let q = query(&["example", "com"], DNS_TYPE_A);
+        let mut out = [0u8; 64];
+        assert_eq!(build_dns_response(&q, IP, &mut out[..q.len() + 15]), None);
+        assert!(build_dns_response(&q, IP, &mut out[..q.len() + 16]).is_some());
+    }
+}
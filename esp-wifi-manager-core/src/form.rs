@@ -0,0 +1,137 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// `application/x-www-form-urlencoded` decoding error
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FormError {
+    /// `%` not followed by two hex digits
+    InvalidEscape,
+
+    /// Decoded bytes arent valid utf-8
+    InvalidUtf8,
+
+    /// Decoded value contains NUL (`%00`)
+    ///
+    /// NUL is reserved as terminator of BLE setup string.
+    Nul,
+
+    /// Decoded setup is invalid (see `AutoSetupSettings::new`)
+    InvalidSetup(SetupError),
+}
+
+/// Why setup sent by client was rejected
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SetupError {
+    /// Ssid missing or not 1-32 bytes
+    InvalidSsid,
+
+    /// Password not empty or 8-64 bytes
+    InvalidPsk,
+
+    /// Country isnt two letter code
+    InvalidCountry,
+}
+
+impl SetupError {
+    /// Json api error code
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidSsid => "invalid_ssid",
+            Self::InvalidPsk => "invalid_psk",
+            Self::InvalidCountry => "invalid_country",
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            Self::InvalidSsid => "Ssid must have 1-32 bytes",
+            Self::InvalidPsk => "Password must be empty or have 8-64 bytes",
+            Self::InvalidCountry => "Unknown country code",
+        }
+    }
+}
+
+/// Decodes single form component (`+` is space, `%XX` is byte)
+pub fn decode(value: &str) -> Result<String, FormError> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut iter = value.bytes();
+    while let Some(b) = iter.next() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hi = iter.next().and_then(hex_val);
+                let lo = iter.next().and_then(hex_val);
+                match (hi, lo) {
+                    (Some(hi), Some(lo)) => bytes.push(hi << 4 | lo),
+                    _ => return Err(FormError::InvalidEscape),
+                }
+            }
+            b => bytes.push(b),
+        }
+    }
+
+    if bytes.contains(&0) {
+        return Err(FormError::Nul);
+    }
+    String::from_utf8(bytes).map_err(|_| FormError::InvalidUtf8)
+}
+
+/// Splits and decodes `key=value&key2=value2` pairs
+///
+/// Empty pairs (`a=1&&b=2`) are skipped, pair without `=` has empty value.
+pub fn parse(data: &str) -> Result<Vec<(String, String)>, FormError> {
+    data.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok((decode(key)?, decode(value)?))
+        })
+        .collect()
+}
+
+fn hex_val(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_plus_and_escapes() {
+        assert_eq!(decode("my+wifi%21").unwrap(), "my wifi!");
+        assert_eq!(decode("%e4%BD%A0%2B").unwrap(), "你+");
+        assert_eq!(decode("").unwrap(), "");
+    }
+
+    #[test]
+    fn rejects_invalid_escapes() {
+        for value in ["%", "%4", "abc%", "%zz", "%4g"] {
+            assert_eq!(decode(value), Err(FormError::InvalidEscape), "{value}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_utf8_and_nul() {
+        assert_eq!(decode("%ff"), Err(FormError::InvalidUtf8));
+        assert_eq!(decode("%e4%bd"), Err(FormError::InvalidUtf8));
+        assert_eq!(decode("a%00b"), Err(FormError::Nul));
+    }
+
+    #[test]
+    fn parses_pairs() {
+        let pairs = parse("ssid=home&&psk=&flag&c=a%3Db").unwrap();
+        let expected = [("ssid", "home"), ("psk", ""), ("flag", ""), ("c", "a=b")];
+        assert_eq!(pairs.len(), expected.len());
+        for ((key, value), (exp_key, exp_value)) in pairs.iter().zip(expected) {
+            assert_eq!((key.as_str(), value.as_str()), (exp_key, exp_value));
+        }
+        assert!(parse("").unwrap().is_empty());
+        assert_eq!(parse("a=1&b=%"), Err(FormError::InvalidEscape));
+    }
+}
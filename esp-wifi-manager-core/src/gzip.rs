@@ -0,0 +1,206 @@
+use alloc::{string::String, vec::Vec};
+
+/// Gzip stream assembled from precompressed deflate parts and raw values
+///
+/// Precompressed parts have to end with sync flush (byte aligned, not final
+/// block), values go in between as stored blocks.
+pub struct GzipBuilder {
+    out: Vec<u8>,
+    crc: u32,
+    len: u32,
+}
+
+impl GzipBuilder {
+    /// Writes gzip header, `capacity` is expected size of whole stream
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut out = Vec::with_capacity(capacity);
+        // deflate, no flags, no mtime, unknown os
+        out.extend_from_slice(&[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff]);
+        Self {
+            out,
+            crc: 0,
+            len: 0,
+        }
+    }
+
+    /// Appends precompressed blocks with uncompressed length and crc32
+    pub fn deflate(&mut self, data: &[u8], len: u32, crc: u32) {
+        self.out.extend_from_slice(data);
+        self.crc = crc32_combine(self.crc, crc, len);
+        self.len = self.len.wrapping_add(len);
+    }
+
+    /// Appends `data` uncompressed (stored blocks)
+    pub fn stored(&mut self, data: &[u8]) {
+        for chunk in data.chunks(u16::MAX as usize) {
+            let chunk_len = chunk.len() as u16;
+            self.out.push(0);
+            self.out.extend_from_slice(&chunk_len.to_le_bytes());
+            self.out.extend_from_slice(&(!chunk_len).to_le_bytes());
+            self.out.extend_from_slice(chunk);
+            self.crc = crc32_update(self.crc, chunk);
+            self.len = self.len.wrapping_add(chunk.len() as u32);
+        }
+    }
+
+    /// Empty final stored block, then gzip trailer
+    pub fn finish(mut self) -> Vec<u8> {
+        self.out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+        self.out.extend_from_slice(&self.crc.to_le_bytes());
+        self.out.extend_from_slice(&self.len.to_le_bytes());
+        self.out
+    }
+}
+
+pub fn html_escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Reflected crc32 polynomial (gzip)
+const CRC32_POLY: u32 = 0xedb8_8320;
+
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ CRC32_POLY
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// a(x) * b(x) mod p(x)
+fn multmodp(a: u32, mut b: u32) -> u32 {
+    let mut m = 1 << 31;
+    let mut p = 0;
+    loop {
+        if a & m != 0 {
+            p ^= b;
+            if a & (m - 1) == 0 {
+                break;
+            }
+        }
+        m >>= 1;
+        b = if b & 1 != 0 {
+            (b >> 1) ^ CRC32_POLY
+        } else {
+            b >> 1
+        };
+    }
+    p
+}
+
+/// Crc32 of concatenation from crc32 of both parts (as zlib `crc32_combine`)
+pub fn crc32_combine(crc1: u32, crc2: u32, len2: u32) -> u32 {
+    // x^(8 * len2) mod p(x), by squaring x^(2^k)
+    let mut x2k: u32 = 1 << 30;
+    let mut xn: u32 = 1 << 31;
+    let mut n = len2 as u64 * 8;
+    while n != 0 {
+        if n & 1 != 0 {
+            xn = multmodp(x2k, xn);
+        }
+        x2k = multmodp(x2k, x2k);
+        n >>= 1;
+    }
+    multmodp(xn, crc1) ^ crc2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    fn crc(data: &[u8]) -> u32 {
+        crc32_update(0, data)
+    }
+
+    /// Same as `deflate_sync` in `build.rs`
+    fn deflate(builder: &mut GzipBuilder, text: &str) {
+        let mut encoder =
+            flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(text.as_bytes()).unwrap();
+        encoder.flush().unwrap();
+        builder.deflate(encoder.get_ref(), text.len() as u32, crc(text.as_bytes()));
+    }
+
+    fn gunzip(data: &[u8]) -> String {
+        let mut out = String::new();
+        flate2::read::GzDecoder::new(data)
+            .read_to_string(&mut out)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn crc32_known_values() {
+        assert_eq!(crc(b""), 0);
+        assert_eq!(crc(b"123456789"), 0xcbf4_3926);
+        // update continues previous crc
+        assert_eq!(crc32_update(crc(b"1234"), b"56789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn crc32_combine_matches_concatenation() {
+        let data = b"The quick brown fox jumps over the lazy dog";
+        for split in [0, 1, 20, data.len() - 1, data.len()] {
+            let (a, b) = data.split_at(split);
+            assert_eq!(
+                crc32_combine(crc(a), crc(b), b.len() as u32),
+                crc(data),
+                "split at {split}"
+            );
+        }
+    }
+
+    #[test]
+    fn builds_valid_gzip() {
+        let mut builder = GzipBuilder::with_capacity(64);
+        deflate(&mut builder, "<h1>");
+        builder.stored(html_escape("ESP-<1>").as_bytes());
+        deflate(&mut builder, "</h1><p lang=\"");
+        builder.stored(b"en");
+        builder.stored(b"");
+        deflate(&mut builder, "\"></p>");
+        assert_eq!(
+            gunzip(&builder.finish()),
+            "<h1>ESP-&lt;1&gt;</h1><p lang=\"en\"></p>"
+        );
+    }
+
+    #[test]
+    fn builds_long_and_empty_streams() {
+        // more than one stored block
+        let long = "v".repeat(u16::MAX as usize + 10);
+        let mut builder = GzipBuilder::with_capacity(0);
+        builder.stored(long.as_bytes());
+        assert_eq!(gunzip(&builder.finish()), long);
+
+        assert_eq!(gunzip(&GzipBuilder::with_capacity(0).finish()), "");
+    }
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(
+            html_escape(r#"<a href="x">'&'</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;"
+        );
+        assert_eq!(html_escape("ÄÖ plain"), "ÄÖ plain");
+    }
+}
@@ -0,0 +1,422 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::net::IpAddr;
+use core::str;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HttpMethod {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Options,
+    Patch,
+}
+
+impl HttpMethod {
+    pub fn from_bytes(method: &[u8]) -> Option<Self> {
+        Some(match method {
+            b"GET" => Self::Get,
+            b"HEAD" => Self::Head,
+            b"POST" => Self::Post,
+            b"PUT" => Self::Put,
+            b"DELETE" => Self::Delete,
+            b"OPTIONS" => Self::Options,
+            b"PATCH" => Self::Patch,
+            _ => return None,
+        })
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Get => "GET",
+            Self::Head => "HEAD",
+            Self::Post => "POST",
+            Self::Put => "PUT",
+            Self::Delete => "DELETE",
+            Self::Options => "OPTIONS",
+            Self::Patch => "PATCH",
+        }
+    }
+}
+
+/// Request parsing error, every variant maps to http error response
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HttpError {
+    /// Malformed request line, header or `Content-Length`
+    BadRequest,
+
+    /// Unknown path
+    NotFound,
+
+    /// Method not supported by path
+    MethodNotAllowed,
+
+    /// Unknown method
+    NotImplemented,
+
+    /// `Content-Length` above body limit (`HttpLimits::max_body_len`)
+    PayloadTooLarge,
+
+    /// Request line and headers dont fit into header limit
+    HeaderTooLarge,
+}
+
+impl HttpError {
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::BadRequest => 400,
+            Self::NotFound => 404,
+            Self::MethodNotAllowed => 405,
+            Self::PayloadTooLarge => 413,
+            Self::HeaderTooLarge => 431,
+            Self::NotImplemented => 501,
+        }
+    }
+}
+
+/// Reason phrase for status code
+pub fn status_text(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        202 => "Accepted",
+        204 => "No Content",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        422 => "Unprocessable Content",
+        426 => "Upgrade Required",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+/// Limits enforced while reading requests
+#[derive(Debug, Clone, Copy)]
+pub struct HttpLimits {
+    /// Max size of request line + headers
+    pub max_header_len: usize,
+
+    /// Max number of headers
+    pub max_headers: usize,
+
+    /// Max body size (`Content-Length`)
+    pub max_body_len: usize,
+}
+
+impl Default for HttpLimits {
+    fn default() -> Self {
+        Self {
+            max_header_len: 1536,
+            max_headers: 24,
+            max_body_len: 2048,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: HttpMethod,
+
+    /// Path without query string
+    pub path: String,
+
+    /// Raw query string (without `?`)
+    pub query: String,
+
+    /// Minor version of `HTTP/1.x` (`1.0` clients close connections by default)
+    pub version_minor: u8,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+
+    /// Client address (set by server, `None` from `parse_head`)
+    pub remote: Option<IpAddr>,
+}
+
+impl HttpRequest {
+    /// Header value (name is case insensitive)
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Raw (not decoded) query parameter value
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .split('&')
+            .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Body as utf-8 string
+    pub fn body_str(&self) -> Option<&str> {
+        str::from_utf8(&self.body).ok()
+    }
+
+    /// Decoded `application/x-www-form-urlencoded` body (`None` if it isnt valid)
+    pub fn form(&self) -> Option<Vec<(String, String)>> {
+        crate::form::parse(self.body_str()?).ok()
+    }
+
+    /// Length of body announced in headers (0 if there is no `Content-Length`)
+    pub fn content_length(&self) -> usize {
+        self.header("Content-Length")
+            .and_then(|len| len.parse().ok())
+            .unwrap_or(0)
+    }
+
+    /// Checks if connection should be kept open after response
+    ///
+    /// `HTTP/1.1` keeps it unless client sends `Connection: close`,
+    /// `HTTP/1.0` only with `Connection: keep-alive`.
+    pub fn keep_alive(&self) -> bool {
+        let has_option = |option: &str| {
+            self.header("Connection").is_some_and(|c| {
+                c.split(',')
+                    .any(|token| token.trim().eq_ignore_ascii_case(option))
+            })
+        };
+        if self.version_minor == 0 {
+            has_option("keep-alive")
+        } else {
+            !has_option("close")
+        }
+    }
+
+    /// Parses request line and headers
+    ///
+    /// Returns `Ok(None)` if `data` doesnt contain whole header section yet,
+    /// otherwise request (with empty body) and length of header section.
+    /// Body has to be read separately (`content_length()` bytes after header section).
+    pub fn parse_head(
+        data: &[u8],
+        limits: &HttpLimits,
+    ) -> Result<Option<(Self, usize)>, HttpError> {
+        let Some(head_end) = find(data, b"\r\n\r\n") else {
+            if data.len() >= limits.max_header_len {
+                return Err(HttpError::HeaderTooLarge);
+            }
+            return Ok(None);
+        };
+        let head_len = head_end + 4;
+        if head_len > limits.max_header_len {
+            return Err(HttpError::HeaderTooLarge);
+        }
+
+        let mut lines = data[..head_end]
+            .split(|&b| b == b'\n')
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line));
+
+        // request line: METHOD SP target SP HTTP/1.x
+        let request_line = lines.next().ok_or(HttpError::BadRequest)?;
+        let mut parts = request_line.split(|&b| b == b' ').filter(|p| !p.is_empty());
+        let method = parts.next().ok_or(HttpError::BadRequest)?;
+        let target = parts.next().ok_or(HttpError::BadRequest)?;
+        let version = parts.next().ok_or(HttpError::BadRequest)?;
+        if parts.next().is_some() {
+            return Err(HttpError::BadRequest);
+        }
+        let version_minor = match version.strip_prefix(b"HTTP/1.") {
+            Some(b"0") => 0,
+            // later 1.x versions are answered as 1.1
+            Some([minor]) if minor.is_ascii_digit() => 1,
+            _ => return Err(HttpError::BadRequest),
+        };
+        let method = HttpMethod::from_bytes(method).ok_or(HttpError::NotImplemented)?;
+
+        let target = str::from_utf8(target).map_err(|_| HttpError::BadRequest)?;
+        let target = target.split('#').next().unwrap_or_default();
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        // absolute form (proxies, some captive probes)
+        let path = match path.strip_prefix("http://") {
+            Some(rest) => rest.find('/').map(|i| &rest[i..]).unwrap_or("/"),
+            None => path,
+        };
+        if !path.starts_with('/') && path != "*" {
+            return Err(HttpError::BadRequest);
+        }
+
+        let mut headers = Vec::new();
+        for line in lines {
+            if headers.len() >= limits.max_headers {
+                return Err(HttpError::HeaderTooLarge);
+            }
+            let colon = line
+                .iter()
+                .position(|&b| b == b':')
+                .ok_or(HttpError::BadRequest)?;
+            let key = str::from_utf8(&line[..colon]).map_err(|_| HttpError::BadRequest)?;
+            if key.is_empty() || key.contains([' ', '\t']) {
+                return Err(HttpError::BadRequest);
+            }
+            let value = String::from_utf8_lossy(&line[colon + 1..]);
+            headers.push((String::from(key), String::from(value.trim())));
+        }
+
+        let request = Self {
+            method,
+            path: String::from(path),
+            query: String::from(query),
+            version_minor,
+            headers,
+            body: Vec::new(),
+            remote: None,
+        };
+
+        if request.header("Transfer-Encoding").is_some() {
+            // chunked bodies arent supported
+            return Err(HttpError::BadRequest);
+        }
+        if let Some(len) = request.header("Content-Length") {
+            // body limit is checked by caller (some paths allow bigger bodies)
+            len.parse::<usize>().map_err(|_| HttpError::BadRequest)?;
+        }
+
+        Ok(Some((request, head_len)))
+    }
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(data: &str) -> Result<Option<(HttpRequest, usize)>, HttpError> {
+        HttpRequest::parse_head(data.as_bytes(), &HttpLimits::default())
+    }
+
+    fn request(data: &str) -> HttpRequest {
+        parse(data).unwrap().unwrap().0
+    }
+
+    #[test]
+    fn parses_request_head() {
+        let data = "POST /setup?lang=en&x HTTP/1.1\r\nHost: 192.168.4.1\r\ncontent-length: 5\r\nX-Empty:\r\n\r\nhello";
+        let (request, head_len) = parse(data).unwrap().unwrap();
+        assert_eq!(head_len, data.len() - 5);
+        assert_eq!(request.method, HttpMethod::Post);
+        assert_eq!(request.path, "/setup");
+        assert_eq!(request.query_param("lang"), Some("en"));
+        assert_eq!(request.query_param("x"), Some(""));
+        assert_eq!(request.query_param("y"), None);
+        assert_eq!(request.header("HOST"), Some("192.168.4.1"));
+        assert_eq!(request.header("X-Empty"), Some(""));
+        assert_eq!(request.content_length(), 5);
+        assert!(request.body.is_empty());
+        assert_eq!(request.version_minor, 1);
+    }
+
+    #[test]
+    fn normalizes_target() {
+        assert_eq!(
+            request("GET http://example.com/generate_204 HTTP/1.1\r\n\r\n").path,
+            "/generate_204"
+        );
+        assert_eq!(request("GET http://example.com HTTP/1.1\r\n\r\n").path, "/");
+        assert_eq!(request("GET /a#frag HTTP/1.1\r\n\r\n").path, "/a");
+        assert_eq!(request("OPTIONS * HTTP/1.1\r\n\r\n").path, "*");
+    }
+
+    #[test]
+    fn waits_for_whole_head() {
+        assert!(parse("").unwrap().is_none());
+        assert!(parse("GET / HTTP/1.1\r\nHost: x\r\n").unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_heads() {
+        let bad = [
+            "GET\r\n\r\n",
+            "GET /\r\n\r\n",
+            "GET / HTTP/1.1 x\r\n\r\n",
+            "GET / HTTP/2.0\r\n\r\n",
+            "GET / HTTP/1.\r\n\r\n",
+            "GET / HTTP/1.10\r\n\r\n",
+            "GET / http/1.1\r\n\r\n",
+            "GET relative HTTP/1.1\r\n\r\n",
+            "GET / HTTP/1.1\r\nno colon\r\n\r\n",
+            "GET / HTTP/1.1\r\n: empty\r\n\r\n",
+            "GET / HTTP/1.1\r\nBad Key: x\r\n\r\n",
+            "POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n",
+            "POST / HTTP/1.1\r\nContent-Length: 1x\r\n\r\n",
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n",
+        ];
+        for data in bad {
+            assert_eq!(parse(data).err(), Some(HttpError::BadRequest), "{data:?}");
+        }
+        assert!(
+            HttpRequest::parse_head(b"GET /\xff HTTP/1.1\r\n\r\n", &HttpLimits::default()).is_err()
+        );
+    }
+
+    #[test]
+    fn unknown_method_is_not_implemented() {
+        let err = parse("BREW /pot HTTP/1.1\r\n\r\n").err();
+        assert_eq!(err, Some(HttpError::NotImplemented));
+        assert_eq!(err.unwrap().status(), 501);
+        assert_eq!(
+            parse("get / HTTP/1.1\r\n\r\n").err(),
+            Some(HttpError::NotImplemented)
+        );
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let limits = HttpLimits {
+            max_header_len: 64,
+            max_headers: 2,
+            max_body_len: 0,
+        };
+        let parse = |data: &str| HttpRequest::parse_head(data.as_bytes(), &limits);
+
+        // incomplete head already at limit
+        let long = alloc::format!("GET /{} HTTP/1.1\r\n", "a".repeat(64));
+        assert_eq!(parse(&long).err(), Some(HttpError::HeaderTooLarge));
+        // complete head over limit
+        let long = alloc::format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(47));
+        assert_eq!(long.len(), 65);
+        assert_eq!(parse(&long).err(), Some(HttpError::HeaderTooLarge));
+        let fits = alloc::format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(46));
+        assert!(parse(&fits).is_ok());
+
+        assert!(parse("GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\n\r\n").is_ok());
+        assert_eq!(
+            parse("GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n").err(),
+            Some(HttpError::HeaderTooLarge)
+        );
+    }
+
+    #[test]
+    fn keep_alive_depends_on_version() {
+        assert!(request("GET / HTTP/1.1\r\n\r\n").keep_alive());
+        assert!(request("GET / HTTP/1.1\r\nConnection: keep-alive\r\n\r\n").keep_alive());
+        assert!(!request("GET / HTTP/1.1\r\nConnection: Close\r\n\r\n").keep_alive());
+        assert!(!request("GET / HTTP/1.1\r\nConnection: TE, close\r\n\r\n").keep_alive());
+        assert!(!request("GET / HTTP/1.0\r\n\r\n").keep_alive());
+        assert!(request("GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n").keep_alive());
+        assert!(!request("GET / HTTP/1.0\r\nConnection: close\r\n\r\n").keep_alive());
+        // later minor versions are handled as 1.1
+        assert!(request("GET / HTTP/1.2\r\n\r\n").keep_alive());
+    }
+}
@@ -0,0 +1,296 @@
+/// Portal and BLE texts of one language
+///
+/// Templates use texts with `{{t:key}}` placeholders (`{{lang}}` is replaced
+/// by `code`). Keys missing in language fall back to English, own keys can be
+/// added for own pages. Add languages with `WmSettingsBuilder::language`.
+#[derive(Debug, PartialEq)]
+pub struct Language {
+    /// Language tag matched against `Accept-Language` (`en`, `zh`, `pt-BR`)
+    pub code: &'static str,
+
+    /// `(key, text)` pairs
+    pub texts: &'static [(&'static str, &'static str)],
+}
+
+impl Language {
+    /// Text for key (English one if missing, empty if unknown)
+    pub fn text(&self, key: &str) -> &'static str {
+        self.get(key)
+            .or_else(|| ENGLISH.get(key))
+            .unwrap_or_else(|| {
+                log::warn!("[i18n] missing text: {key}");
+                ""
+            })
+    }
+
+    fn get(&self, key: &str) -> Option<&'static str> {
+        self.texts.iter().find(|(k, _)| *k == key).map(|(_, t)| *t)
+    }
+}
+
+pub const ENGLISH: Language = Language {
+    code: "en",
+    texts: &[
+        ("title", "WiFi Setup"),
+        ("credentials", "WiFi Credentials"),
+        ("ssid_placeholder", "Enter SSID..."),
+        ("psk_placeholder", "Enter Password..."),
+        ("country_placeholder", "Country code (optional, e.g. JP)..."),
+        ("connect", "Connect to Network"),
+        ("networks", "Available Networks"),
+        ("refresh", "Refresh"),
+        ("close", "Close"),
+        ("retry", "Try again"),
+        ("done_heading", "Setup complete!"),
+        (
+            "done_message",
+            "The device will restart and connect to the configured WiFi network.",
+        ),
+        (
+            "status_pending",
+            "Credentials received, waiting to connect...",
+        ),
+        ("status_connecting", "Connecting..."),
+        ("status_obtaining_ip", "Connected, obtaining IP address..."),
+        ("status_connected", "Connected!"),
+        ("status_failed", "Connection failed"),
+        ("fail_network_not_found", "network not found"),
+        (
+            "fail_connect_failed",
+            "could not connect, check the password",
+        ),
+        ("fail_no_ip", "no IP address received"),
+        ("fail_country_rejected", "country settings not accepted"),
+        (
+            "fail_config_rejected",
+            "network name or password not accepted",
+        ),
+        ("label_ip", "IP address"),
+        ("label_hostname", "Hostname"),
+        ("ble_waiting", "Waiting for WiFi credentials"),
+        ("ble_invalid_setup", "Invalid setup string"),
+        ("ble_scanning", "Scanning for networks..."),
+        ("ble_scan_done", "Scan finished"),
+        ("ble_scan_aborted", "Scan aborted"),
+        ("ble_invalid_scan", "Invalid scan request"),
+        ("admin_title", "Device settings"),
+        ("admin_connection", "Connection"),
+        ("admin_saved", "Saved networks"),
+        ("admin_add", "Add network"),
+        ("admin_save", "Save network"),
+        ("admin_scan", "Scan for networks"),
+        ("admin_forget", "Forget"),
+        ("admin_current", "current"),
+        ("admin_connected", "Connected"),
+        ("admin_disconnected", "Disconnected"),
+        ("admin_reprovision", "Restart into setup mode"),
+        (
+            "admin_reprovision_confirm",
+            "The device will restart and open the setup portal. Continue?",
+        ),
+        ("admin_restarting", "Restarting into setup mode..."),
+        ("admin_request_failed", "Request failed"),
+        ("label_state", "State"),
+        ("label_ssid", "Network"),
+        ("label_gateway", "Gateway"),
+        ("label_dns", "DNS"),
+        ("auth_failed", "Authentication failed"),
+        ("auth_locked", "Too many failed attempts, try again later"),
+        ("auth_token_prompt", "Access token"),
+    ],
+};
+
+pub const CHINESE: Language = Language {
+    code: "zh",
+    texts: &[
+        ("title", "WiFi 配置"),
+        ("credentials", "WiFi 凭据"),
+        ("ssid_placeholder", "输入网络名称 (SSID)..."),
+        ("psk_placeholder", "输入密码..."),
+        ("country_placeholder", "国家代码（可选，例如 CN）..."),
+        ("connect", "连接网络"),
+        ("networks", "可用网络"),
+        ("refresh", "刷新"),
+        ("close", "关闭"),
+        ("retry", "重试"),
+        ("done_heading", "配置成功！"),
+        ("done_message", "设备将重新启动并连接到配置的WiFi网络。"),
+        ("status_pending", "已收到凭据，等待连接..."),
+        ("status_connecting", "正在连接..."),
+        ("status_obtaining_ip", "已连接，正在获取 IP 地址..."),
+        ("status_connected", "连接成功！"),
+        ("status_failed", "连接失败"),
+        ("fail_network_not_found", "未找到网络"),
+        ("fail_connect_failed", "无法连接，请检查密码"),
+        ("fail_no_ip", "未获取到 IP 地址"),
+        ("fail_country_rejected", "国家/地区设置无效"),
+        ("fail_config_rejected", "网络名称或密码无效"),
+        ("label_ip", "IP 地址"),
+        ("label_hostname", "主机名"),
+        ("ble_waiting", "等待 WiFi 配置"),
+        ("ble_invalid_setup", "配置字符串无效"),
+        ("ble_scanning", "正在扫描网络..."),
+        ("ble_scan_done", "扫描完成"),
+        ("ble_scan_aborted", "扫描已中止"),
+        ("ble_invalid_scan", "扫描请求无效"),
+        ("admin_title", "设备设置"),
+        ("admin_connection", "连接"),
+        ("admin_saved", "已保存的网络"),
+        ("admin_add", "添加网络"),
+        ("admin_save", "保存网络"),
+        ("admin_scan", "扫描网络"),
+        ("admin_forget", "忘记"),
+        ("admin_current", "当前"),
+        ("admin_connected", "已连接"),
+        ("admin_disconnected", "未连接"),
+        ("admin_reprovision", "重启进入配置模式"),
+        (
+            "admin_reprovision_confirm",
+            "设备将重新启动并打开配置页面，是否继续？",
+        ),
+        ("admin_restarting", "正在重启进入配置模式..."),
+        ("admin_request_failed", "请求失败"),
+        ("label_state", "状态"),
+        ("label_ssid", "网络"),
+        ("label_gateway", "网关"),
+        ("label_dns", "DNS"),
+        ("auth_failed", "认证失败"),
+        ("auth_locked", "失败次数过多，请稍后再试"),
+        ("auth_token_prompt", "访问令牌"),
+    ],
+};
+
+/// Picks language for `Accept-Language` header value (`zh-CN,zh;q=0.9,en;q=0.8`)
+///
+/// Tag matches language with the same code, or with code equal to its primary
+/// subtag (`zh-CN` matches `zh`). Returns `None` if nothing matches.
+pub fn negotiate<'a>(accept_language: &str, languages: &[&'a Language]) -> Option<&'a Language> {
+    let mut best: Option<(&'a Language, u16)> = None;
+    for range in accept_language.split(',') {
+        let mut params = range.split(';');
+        let tag = params.next().unwrap_or_default().trim();
+        let quality = params
+            .find_map(|p| p.trim().strip_prefix("q="))
+            .map_or(Some(1000), parse_quality);
+        let Some(quality) = quality.filter(|q| *q > 0) else {
+            continue;
+        };
+        if best.is_some_and(|(_, best_quality)| best_quality >= quality) {
+            continue;
+        }
+
+        let primary = tag.split('-').next().unwrap_or_default();
+        let language = languages
+            .iter()
+            .copied()
+            .find(|l| l.code.eq_ignore_ascii_case(tag))
+            .or_else(|| {
+                languages
+                    .iter()
+                    .copied()
+                    .find(|l| l.code.eq_ignore_ascii_case(primary))
+            });
+        if let Some(language) = language {
+            best = Some((language, quality));
+        }
+    }
+
+    best.map(|(language, _)| language)
+}
+
+/// Language for request (`default` if header is missing or nothing matches)
+pub fn select(
+    accept_language: Option<&str>,
+    languages: &[&'static Language],
+    default: &str,
+) -> &'static Language {
+    accept_language
+        .and_then(|accept| negotiate(accept, languages))
+        .or_else(|| languages.iter().copied().find(|l| l.code == default))
+        .unwrap_or(&ENGLISH)
+}
+
+/// `q` value (`0.8`) in thousandths
+fn parse_quality(q: &str) -> Option<u16> {
+    let (int, frac) = q.split_once('.').unwrap_or((q, ""));
+    if frac.len() > 3 || !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let frac = alloc::format!("{frac:0<3}").parse::<u16>().ok()?;
+    match int {
+        "0" => Some(frac),
+        "1" if frac == 0 => Some(1000),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PT_BR: Language = Language {
+        code: "pt-BR",
+        texts: &[],
+    };
+    const LANGUAGES: &[&Language] = &[&ENGLISH, &CHINESE, &PT_BR];
+
+    fn code(accept_language: &str) -> Option<&'static str> {
+        negotiate(accept_language, LANGUAGES).map(|l| l.code)
+    }
+
+    #[test]
+    fn picks_highest_quality() {
+        assert_eq!(code("zh-CN,zh;q=0.9,en;q=0.8"), Some("zh"));
+        assert_eq!(code("en;q=0.5, zh;q=0.7"), Some("zh"));
+        assert_eq!(code("fr, en;q=0.1"), Some("en"));
+        // first one wins on equal quality
+        assert_eq!(code("en;q=0.5,zh;q=0.5"), Some("en"));
+    }
+
+    #[test]
+    fn matches_tag_and_primary_subtag() {
+        assert_eq!(code("PT-br"), Some("pt-BR"));
+        assert_eq!(code("ZH-Hant-TW"), Some("zh"));
+        // primary subtag of range, not of language
+        assert_eq!(code("pt"), None);
+    }
+
+    #[test]
+    fn ignores_invalid_and_zero_quality() {
+        assert_eq!(code(""), None);
+        assert_eq!(code("*"), None);
+        assert_eq!(code("zh;q=0"), None);
+        assert_eq!(code("zh;q=0.000, en;q=0.001"), Some("en"));
+        for q in ["1.5", "0.1234", "abc", "", "-1", "0.x"] {
+            assert_eq!(
+                code(&alloc::format!("zh;q={q}, en;q=0.1")),
+                Some("en"),
+                "{q}"
+            );
+        }
+        assert_eq!(code("zh;q=1.000"), Some("zh"));
+    }
+
+    #[test]
+    fn quality_values() {
+        assert_eq!(parse_quality("0"), Some(0));
+        assert_eq!(parse_quality("0.8"), Some(800));
+        assert_eq!(parse_quality("0.05"), Some(50));
+        assert_eq!(parse_quality("1"), Some(1000));
+        assert_eq!(parse_quality("1.001"), None);
+    }
+
+    #[test]
+    fn select_falls_back_to_default() {
+        assert_eq!(select(None, LANGUAGES, "zh").code, "zh");
+        assert_eq!(select(Some("fr"), LANGUAGES, "zh").code, "zh");
+        assert_eq!(select(Some("fr"), LANGUAGES, "xx").code, "en");
+        assert_eq!(select(Some("pt-BR"), LANGUAGES, "zh").code, "pt-BR");
+    }
+
+    #[test]
+    fn missing_texts_fall_back_to_english() {
+        assert_eq!(PT_BR.text("label_dns"), ENGLISH.text("label_dns"));
+        assert_eq!(CHINESE.text("no_such_key"), "");
+    }
+}
@@ -0,0 +1,463 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+
+/// Max nesting of arrays/objects accepted by `parse`
+const MAX_DEPTH: usize = 8;
+
+/// Minimal json value (no_std replacement for `serde_json::Value`)
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+
+    /// Object keeps insertion order (small objects, linear lookup is enough)
+    Object(Vec<(String, JsonValue)>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JsonError {
+    /// Unexpected character at given byte offset
+    Syntax(usize),
+
+    /// Input ended in the middle of value
+    UnexpectedEnd,
+
+    /// Nesting deeper than `MAX_DEPTH`
+    TooDeep,
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Syntax(pos) => write!(f, "syntax error at byte {pos}"),
+            Self::UnexpectedEnd => f.write_str("unexpected end of input"),
+            Self::TooDeep => f.write_str("nesting too deep"),
+        }
+    }
+}
+
+impl JsonValue {
+    /// Empty object, filled with `with`
+    pub fn object() -> Self {
+        Self::Object(Vec::new())
+    }
+
+    /// Appends field to object (no-op for other values)
+    pub fn with(mut self, key: &str, value: impl Into<JsonValue>) -> Self {
+        if let Self::Object(ref mut fields) = self {
+            fields.push((String::from(key), value.into()));
+        }
+        self
+    }
+
+    /// Object field
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            Self::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// Non-negative integer (`None` for fractions and out of range numbers)
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Self::Number(n) if *n >= 0.0 && *n <= u64::MAX as f64 && *n as u64 as f64 == *n => {
+                Some(*n as u64)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[JsonValue]> {
+        match self {
+            Self::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+impl From<bool> for JsonValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<&str> for JsonValue {
+    fn from(value: &str) -> Self {
+        Self::String(String::from(value))
+    }
+}
+
+impl From<String> for JsonValue {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+impl From<Vec<JsonValue>> for JsonValue {
+    fn from(value: Vec<JsonValue>) -> Self {
+        Self::Array(value)
+    }
+}
+
+impl<T: Into<JsonValue>> From<Option<T>> for JsonValue {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Self::Null, Into::into)
+    }
+}
+
+macro_rules! json_from_number {
+    ($($ty:ty),*) => {
+        $(impl From<$ty> for JsonValue {
+            fn from(value: $ty) -> Self {
+                Self::Number(value as f64)
+            }
+        })*
+    };
+}
+json_from_number!(i8, u8, i16, u16, i32, u32, u64, usize, f32, f64);
+
+impl fmt::Display for JsonValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => f.write_str("null"),
+            Self::Bool(b) => write!(f, "{b}"),
+            // json has no NaN/inf
+            Self::Number(n) if !n.is_finite() => f.write_str("null"),
+            Self::Number(n) => write!(f, "{n}"),
+            Self::String(s) => write_escaped(f, s),
+            Self::Array(items) => {
+                f.write_char('[')?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                f.write_char(']')
+            }
+            Self::Object(fields) => {
+                f.write_char('{')?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_escaped(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+/// Writes `s` as quoted json string
+pub fn write_escaped(out: &mut impl Write, s: &str) -> fmt::Result {
+    out.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => out.write_str("\\\"")?,
+            '\\' => out.write_str("\\\\")?,
+            '\n' => out.write_str("\\n")?,
+            '\r' => out.write_str("\\r")?,
+            '\t' => out.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.write_char(c)?,
+        }
+    }
+    out.write_char('"')
+}
+
+/// Parses json document (whole input has to be single value)
+pub fn parse(input: &str) -> Result<JsonValue, JsonError> {
+    let mut parser = Parser {
+        data: input.as_bytes(),
+        pos: 0,
+    };
+    let value = parser.value(0)?;
+    parser.skip_ws();
+    if parser.pos != parser.data.len() {
+        return Err(JsonError::Syntax(parser.pos));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_ws(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.data.get(self.pos) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Result<u8, JsonError> {
+        self.data
+            .get(self.pos)
+            .copied()
+            .ok_or(JsonError::UnexpectedEnd)
+    }
+
+    fn expect(&mut self, b: u8) -> Result<(), JsonError> {
+        if self.peek()? != b {
+            return Err(JsonError::Syntax(self.pos));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, lit: &[u8], value: JsonValue) -> Result<JsonValue, JsonError> {
+        let end = self.pos + lit.len();
+        if end > self.data.len() {
+            return Err(JsonError::UnexpectedEnd);
+        }
+        if &self.data[self.pos..end] != lit {
+            return Err(JsonError::Syntax(self.pos));
+        }
+        self.pos = end;
+        Ok(value)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<JsonValue, JsonError> {
+        if depth > MAX_DEPTH {
+            return Err(JsonError::TooDeep);
+        }
+
+        self.skip_ws();
+        match self.peek()? {
+            b'n' => self.literal(b"null", JsonValue::Null),
+            b't' => self.literal(b"true", JsonValue::Bool(true)),
+            b'f' => self.literal(b"false", JsonValue::Bool(false)),
+            b'"' => self.string().map(JsonValue::String),
+            b'-' | b'0'..=b'9' => self.number(),
+            b'[' => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.skip_ws();
+                if self.peek()? == b']' {
+                    self.pos += 1;
+                    return Ok(JsonValue::Array(items));
+                }
+                loop {
+                    items.push(self.value(depth + 1)?);
+                    self.skip_ws();
+                    match self.peek()? {
+                        b',' => self.pos += 1,
+                        b']' => {
+                            self.pos += 1;
+                            return Ok(JsonValue::Array(items));
+                        }
+                        _ => return Err(JsonError::Syntax(self.pos)),
+                    }
+                }
+            }
+            b'{' => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                self.skip_ws();
+                if self.peek()? == b'}' {
+                    self.pos += 1;
+                    return Ok(JsonValue::Object(fields));
+                }
+                loop {
+                    self.skip_ws();
+                    let key = self.string()?;
+                    self.skip_ws();
+                    self.expect(b':')?;
+                    let value = self.value(depth + 1)?;
+                    fields.push((key, value));
+                    self.skip_ws();
+                    match self.peek()? {
+                        b',' => self.pos += 1,
+                        b'}' => {
+                            self.pos += 1;
+                            return Ok(JsonValue::Object(fields));
+                        }
+                        _ => return Err(JsonError::Syntax(self.pos)),
+                    }
+                }
+            }
+            _ => Err(JsonError::Syntax(self.pos)),
+        }
+    }
+
+    fn number(&mut self) -> Result<JsonValue, JsonError> {
+        let start = self.pos;
+        while let Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') = self.data.get(self.pos) {
+            self.pos += 1;
+        }
+        // input is &str and number chars are ascii
+        let text = core::str::from_utf8(&self.data[start..self.pos]).unwrap_or_default();
+        text.parse::<f64>()
+            .map(JsonValue::Number)
+            .map_err(|_| JsonError::Syntax(start))
+    }
+
+    fn hex4(&mut self) -> Result<u32, JsonError> {
+        let end = self.pos + 4;
+        let digits = self
+            .data
+            .get(self.pos..end)
+            .ok_or(JsonError::UnexpectedEnd)?;
+        // from_str_radix alone would accept sign (`\u+123`)
+        if !digits.iter().all(u8::is_ascii_hexdigit) {
+            return Err(JsonError::Syntax(self.pos));
+        }
+        let digits = core::str::from_utf8(digits).map_err(|_| JsonError::Syntax(self.pos))?;
+        let value = u32::from_str_radix(digits, 16).map_err(|_| JsonError::Syntax(self.pos))?;
+        self.pos = end;
+        Ok(value)
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.expect(b'"')?;
+        let mut out = Vec::new();
+        loop {
+            let b = self.peek()?;
+            self.pos += 1;
+            match b {
+                b'"' => break,
+                b'\\' => {
+                    let esc = self.peek()?;
+                    self.pos += 1;
+                    let c = match esc {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        _ => return Err(JsonError::Syntax(self.pos - 1)),
+                    };
+                    let mut buf = [0u8; 4];
+                    out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+                b if b < 0x20 => return Err(JsonError::Syntax(self.pos - 1)),
+                b => out.push(b),
+            }
+        }
+        // raw bytes come from &str and escapes are encoded as utf-8
+        String::from_utf8(out).map_err(|_| JsonError::Syntax(self.pos))
+    }
+
+    /// `\uXXXX` (with surrogate pairs), `\u` already consumed
+    fn unicode_escape(&mut self) -> Result<char, JsonError> {
+        let start = self.pos;
+        let hi = self.hex4()?;
+        let code = if (0xD800..0xDC00).contains(&hi) {
+            if self.data.get(self.pos..self.pos + 2) != Some(b"\\u") {
+                return Err(JsonError::Syntax(start));
+            }
+            self.pos += 2;
+            let lo = self.hex4()?;
+            if !(0xDC00..0xE000).contains(&lo) {
+                return Err(JsonError::Syntax(start));
+            }
+            0x10000 + ((hi - 0xD800) << 10) + (lo - 0xDC00)
+        } else {
+            hi
+        };
+        char::from_u32(code).ok_or(JsonError::Syntax(start))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn parses_nested_values() {
+        let value =
+            parse(r#" {"ssid":"home","psk":"","n":[1,-2.5,3e2],"ok":true,"x":null} "#).unwrap();
+        assert_eq!(value.get("ssid").and_then(JsonValue::as_str), Some("home"));
+        assert_eq!(value.get("psk").and_then(JsonValue::as_str), Some(""));
+        assert_eq!(value.get("ok").and_then(JsonValue::as_bool), Some(true));
+        assert_eq!(value.get("x"), Some(&JsonValue::Null));
+        let numbers = value.get("n").and_then(JsonValue::as_array).unwrap();
+        assert_eq!(numbers[0].as_u64(), Some(1));
+        assert_eq!(numbers[1], JsonValue::Number(-2.5));
+        assert_eq!(numbers[1].as_u64(), None);
+        assert_eq!(numbers[2].as_u64(), Some(300));
+        assert_eq!(value.get("missing"), None);
+    }
+
+    #[test]
+    fn parses_escapes() {
+        let value = parse(r#""a\"\\\/\n\t\u00e9\ud83d\ude00""#).unwrap();
+        assert_eq!(value.as_str(), Some("a\"\\/\n\té😀"));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert_eq!(parse(""), Err(JsonError::UnexpectedEnd));
+        assert_eq!(parse("{\"a\":"), Err(JsonError::UnexpectedEnd));
+        assert_eq!(parse("[1,2"), Err(JsonError::UnexpectedEnd));
+        assert_eq!(parse("\"abc"), Err(JsonError::UnexpectedEnd));
+        assert_eq!(parse("tru"), Err(JsonError::UnexpectedEnd));
+        assert_eq!(parse("nul1"), Err(JsonError::Syntax(0)));
+        assert_eq!(parse("[1,]"), Err(JsonError::Syntax(3)));
+        assert_eq!(parse("{\"a\" 1}"), Err(JsonError::Syntax(5)));
+        assert_eq!(parse("{a:1}"), Err(JsonError::Syntax(1)));
+        assert_eq!(parse("1 2"), Err(JsonError::Syntax(2)));
+        assert_eq!(parse("-"), Err(JsonError::Syntax(0)));
+        assert_eq!(parse("1.2.3"), Err(JsonError::Syntax(0)));
+        assert!(parse("\"a\nb\"").is_err());
+        assert!(parse(r#""\x""#).is_err());
+        assert!(parse(r#""\u12g4""#).is_err());
+        assert!(parse(r#""\u+123""#).is_err());
+        // lone and reversed surrogates
+        assert!(parse(r#""\ud83d""#).is_err());
+        assert!(parse(r#""\ud83d\u0041""#).is_err());
+        assert!(parse(r#""\ude00""#).is_err());
+    }
+
+    #[test]
+    fn limits_nesting() {
+        let nested = |depth| "[".repeat(depth) + &"]".repeat(depth);
+        assert!(parse(&nested(MAX_DEPTH + 1)).is_ok());
+        assert_eq!(parse(&nested(MAX_DEPTH + 2)), Err(JsonError::TooDeep));
+    }
+
+    #[test]
+    fn serializes_and_escapes() {
+        let value = JsonValue::object()
+            .with("s", "q\"\\\n\u{1}é")
+            .with("n", 42u32)
+            .with("f", f64::NAN)
+            .with("o", None::<&str>)
+            .with(
+                "a",
+                alloc::vec![JsonValue::from(true), JsonValue::from(-1i32)],
+            );
+        assert_eq!(
+            value.to_string(),
+            r#"{"s":"q\"\\\n\u0001é","n":42,"f":null,"o":null,"a":[true,-1]}"#
+        );
+        assert_eq!(parse(&value.to_string()).unwrap().get("s"), value.get("s"));
+    }
+}
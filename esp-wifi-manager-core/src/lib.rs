@@ -0,0 +1,18 @@
+//! Hardware independent parts of `esp-wifi-manager`
+//!
+//! Kept in own crate, so they build (and are tested) on host:
+//! `cd esp-wifi-manager-core && cargo test`
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+pub mod auth;
+pub mod dhcp;
+pub mod dns;
+pub mod form;
+pub mod gzip;
+pub mod http_request;
+pub mod i18n;
+pub mod json;
+pub mod ota;
+pub mod ws;
@@ -0,0 +1,49 @@
+/// Ota http endpoint (`/api/v1/ota...`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OtaEndpoint {
+    /// `GET /api/v1/ota`
+    Status,
+
+    /// `POST /api/v1/ota`
+    Upload,
+
+    /// `POST /api/v1/ota/valid`
+    MarkValid,
+
+    /// `POST /api/v1/ota/rollback`
+    Rollback,
+}
+
+/// Server can serve `endpoint`
+///
+/// Upload is authenticated by http auth or by image signature (`signed_images`),
+/// marking image valid and rollback carry no signature, so they need http auth.
+pub fn ota_allowed(endpoint: OtaEndpoint, http_auth: bool, signed_images: bool) -> bool {
+    match endpoint {
+        OtaEndpoint::Status => true,
+        OtaEndpoint::Upload => http_auth || signed_images,
+        OtaEndpoint::MarkValid | OtaEndpoint::Rollback => http_auth,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsigned_endpoints_need_http_auth() {
+        for endpoint in [OtaEndpoint::MarkValid, OtaEndpoint::Rollback] {
+            assert!(!ota_allowed(endpoint, false, true), "{endpoint:?}");
+            assert!(!ota_allowed(endpoint, false, false), "{endpoint:?}");
+            assert!(ota_allowed(endpoint, true, false), "{endpoint:?}");
+        }
+    }
+
+    #[test]
+    fn upload_needs_auth_or_signature() {
+        assert!(!ota_allowed(OtaEndpoint::Upload, false, false));
+        assert!(ota_allowed(OtaEndpoint::Upload, false, true));
+        assert!(ota_allowed(OtaEndpoint::Upload, true, false));
+        assert!(ota_allowed(OtaEndpoint::Status, false, false));
+    }
+}
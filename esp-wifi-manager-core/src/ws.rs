@@ -0,0 +1,248 @@
+use alloc::{string::String, vec::Vec};
+use sha1::{Digest, Sha1};
+
+use crate::http_request::HttpRequest;
+
+/// Longest client frame payload kept (control frames have max 125 bytes)
+pub const MAX_CLIENT_PAYLOAD: usize = 256;
+
+pub const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+pub const OP_CONTINUATION: u8 = 0x0;
+pub const OP_TEXT: u8 = 0x1;
+pub const OP_BINARY: u8 = 0x2;
+pub const OP_CLOSE: u8 = 0x8;
+pub const OP_PING: u8 = 0x9;
+pub const OP_PONG: u8 = 0xa;
+
+/// Close status codes
+pub const CLOSE_NORMAL: u16 = 1000;
+pub const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+pub const CLOSE_TOO_BIG: u16 = 1009;
+
+/// `Upgrade: websocket` request
+pub fn is_upgrade(request: &HttpRequest) -> bool {
+    request
+        .header("Upgrade")
+        .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"))
+        && request.header("Connection").is_some_and(|connection| {
+            connection
+                .split(',')
+                .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+        })
+}
+
+/// Client frame (unmasked payload)
+pub struct Frame {
+    pub opcode: u8,
+    pub payload: Vec<u8>,
+}
+
+/// Takes first frame from `buffer` (`Ok(None)` if it isnt complete yet)
+///
+/// Error is close status code. Oversized data frames are an error too,
+/// client has nothing to send but pings.
+pub fn parse_frame(buffer: &mut Vec<u8>) -> Result<Option<Frame>, u16> {
+    let [first, second, ..] = buffer[..] else {
+        return Ok(None);
+    };
+    let opcode = first & 0x0f;
+    // client frames have to be masked, reserved bits arent negotiated
+    if second & 0x80 == 0 || first & 0x70 != 0 {
+        return Err(CLOSE_PROTOCOL_ERROR);
+    }
+
+    let (len, mut pos) = match second & 0x7f {
+        126 => match buffer.get(2..4) {
+            Some(len) => (u16::from_be_bytes([len[0], len[1]]) as usize, 4),
+            None => return Ok(None),
+        },
+        127 => return Err(CLOSE_TOO_BIG),
+        len => (len as usize, 2),
+    };
+    if len > MAX_CLIENT_PAYLOAD || (opcode & 0x8 != 0 && len > 125) {
+        return Err(CLOSE_TOO_BIG);
+    }
+    if buffer.len() < pos + 4 + len {
+        return Ok(None);
+    }
+
+    let mask = [
+        buffer[pos],
+        buffer[pos + 1],
+        buffer[pos + 2],
+        buffer[pos + 3],
+    ];
+    pos += 4;
+    let payload = buffer[pos..pos + len]
+        .iter()
+        .enumerate()
+        .map(|(i, b)| b ^ mask[i % 4])
+        .collect();
+    buffer.drain(..pos + len);
+    Ok(Some(Frame { opcode, payload }))
+}
+
+/// Header of unmasked final frame
+pub fn frame_header(opcode: u8, len: usize) -> Vec<u8> {
+    let mut header = Vec::with_capacity(10);
+    header.push(0x80 | opcode);
+    match len {
+        0..=125 => header.push(len as u8),
+        126..=0xffff => {
+            header.push(126);
+            header.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        _ => {
+            header.push(127);
+            header.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    header
+}
+
+/// `Sec-WebSocket-Accept` value for client key
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(GUID.as_bytes());
+    base64_encode(&sha1.finalize())
+}
+
+pub fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_request::HttpLimits;
+    use alloc::vec;
+
+    const MASK: [u8; 4] = [0x37, 0xfa, 0x21, 0x3d];
+
+    /// Masked client frame
+    fn frame(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mut out = vec![first];
+        match payload.len() {
+            len @ 0..=125 => out.push(0x80 | len as u8),
+            len => {
+                out.push(0x80 | 126);
+                out.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+        }
+        out.extend_from_slice(&MASK);
+        out.extend(payload.iter().enumerate().map(|(i, b)| b ^ MASK[i % 4]));
+        out
+    }
+
+    fn request(head: &str) -> HttpRequest {
+        HttpRequest::parse_head(head.as_bytes(), &HttpLimits::default())
+            .unwrap()
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn accept_key_rfc6455_example() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn base64_encode_padding() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+    }
+
+    #[test]
+    fn parses_masked_frames() {
+        // RFC 6455 5.7 masked "Hello"
+        let mut buffer = vec![
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        buffer.extend(frame(0x80 | OP_PING, b"ping"));
+        let hello = parse_frame(&mut buffer).unwrap().unwrap();
+        assert_eq!((hello.opcode, &hello.payload[..]), (OP_TEXT, &b"Hello"[..]));
+        let ping = parse_frame(&mut buffer).unwrap().unwrap();
+        assert_eq!((ping.opcode, &ping.payload[..]), (OP_PING, &b"ping"[..]));
+        assert!(buffer.is_empty());
+        assert!(parse_frame(&mut buffer).unwrap().is_none());
+    }
+
+    #[test]
+    fn waits_for_whole_frame() {
+        let data = frame(0x81, &[b'x'; 200]);
+        for len in [1, 2, 3, 7, data.len() - 1] {
+            let mut buffer = data[..len].to_vec();
+            assert!(parse_frame(&mut buffer).unwrap().is_none(), "{len}");
+            assert_eq!(buffer.len(), len);
+        }
+        let mut buffer = data.clone();
+        assert_eq!(
+            parse_frame(&mut buffer).unwrap().unwrap().payload.len(),
+            200
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_frames() {
+        let err = |mut buffer: Vec<u8>| parse_frame(&mut buffer).err();
+        // unmasked, reserved bits
+        assert_eq!(err(vec![0x81, 0x00]), Some(CLOSE_PROTOCOL_ERROR));
+        assert_eq!(err(frame(0xc1, b"x")), Some(CLOSE_PROTOCOL_ERROR));
+        // 64 bit length, payload over limit, control frame over 125 bytes
+        assert_eq!(err(vec![0x81, 0xff]), Some(CLOSE_TOO_BIG));
+        assert_eq!(
+            err(frame(0x82, &[0; MAX_CLIENT_PAYLOAD + 1])),
+            Some(CLOSE_TOO_BIG)
+        );
+        assert_eq!(err(frame(0x89, &[0; 126])), Some(CLOSE_TOO_BIG));
+        assert_eq!(err(frame(0x81, &[0; MAX_CLIENT_PAYLOAD])), None);
+    }
+
+    #[test]
+    fn builds_frame_headers() {
+        assert_eq!(frame_header(OP_TEXT, 5), [0x81, 5]);
+        assert_eq!(frame_header(OP_CLOSE, 126), [0x88, 126, 0, 126]);
+        assert_eq!(frame_header(OP_TEXT, 0xffff), [0x81, 126, 0xff, 0xff]);
+        assert_eq!(
+            frame_header(OP_BINARY, 0x10000),
+            [0x82, 127, 0, 0, 0, 0, 0, 1, 0, 0]
+        );
+    }
+
+    #[test]
+    fn detects_upgrade_requests() {
+        assert!(is_upgrade(&request(
+            "GET /api/v1/events HTTP/1.1\r\nUpgrade: WebSocket\r\nConnection: keep-alive, Upgrade\r\n\r\n"
+        )));
+        assert!(!is_upgrade(&request(
+            "GET /api/v1/events HTTP/1.1\r\nUpgrade: websocket\r\n\r\n"
+        )));
+        assert!(!is_upgrade(&request(
+            "GET /api/v1/events HTTP/1.1\r\nUpgrade: h2c\r\nConnection: Upgrade\r\n\r\n"
+        )));
+    }
+}
@@ -0,0 +1,319 @@
+use alloc::{boxed::Box, rc::Rc, string::String, vec::Vec};
+use embassy_net::Stack;
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex};
+use embassy_time::{with_timeout, Duration, Timer};
+use esp_nvs::platform::EspFlash;
+use esp_radio::wifi::WifiStaState;
+
+use crate::api::{self, write_error, API_PREFIX};
+use crate::assets::{self, AssetVars, ADMIN_ASSETS};
+use crate::auth::{AuthLockout, Authenticator, HttpAuth};
+use crate::device_info::DeviceInfo;
+use crate::form;
+use crate::http_request::{HttpError, HttpMethod, HttpRequest};
+use crate::http_server::{serve_connections, HttpServerConfig, ResponseWriter, HTTP_MAX_WORKERS};
+use crate::json::JsonValue;
+use crate::log_capture::{self, LogCapture};
+use crate::ota::{self, Ota};
+use crate::router::{HandlerFuture, HttpHandler, HttpRouter};
+use crate::structs::WmTaskSignals;
+use crate::MAX_SAVED_NETWORKS;
+
+/// Max time of `/api/v1/scan` (including wait for connection and other scans)
+const SCAN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Nvs shared by application and admin server
+pub type SharedNvs = Rc<Mutex<NoopRawMutex, esp_nvs::Nvs<'static, EspFlash<'static>>>>;
+
+/// Admin server settings (`WmReturn::start_admin`)
+#[derive(Clone, Debug)]
+pub struct AdminSettings {
+    pub port: u16,
+
+    /// Number of http worker tasks (parallel connections), max `HTTP_MAX_WORKERS`
+    pub workers: usize,
+
+    pub http: HttpServerConfig,
+
+    /// Application pages and endpoints (checked before admin ones)
+    pub router: HttpRouter,
+
+    /// Authentication of state-changing requests (adding / forgetting networks,
+    /// reprovisioning, application `POST` routes...)
+    pub auth: HttpAuth,
+    pub auth_lockout: AuthLockout,
+
+    /// Firmware upload endpoint (`/api/v1/ota`)
+    pub ota: Option<Rc<Ota>>,
+
+    /// Kept log records (`/api/v1/logs`)
+    pub log_capture: Option<&'static LogCapture>,
+}
+
+impl AdminSettings {
+    /// Admin server is reachable by everybody on station network, so `auth`
+    /// has to be chosen explicitly (`HttpAuth::None` only for trusted networks)
+    pub fn new(auth: HttpAuth) -> Self {
+        Self {
+            port: 80,
+            workers: 1,
+            http: HttpServerConfig::default(),
+            router: HttpRouter::new(),
+            auth,
+            auth_lockout: AuthLockout::default(),
+            ota: None,
+            log_capture: None,
+        }
+    }
+}
+
+/// Admin pages and api served on station network
+pub(crate) struct AdminSite {
+    pub stack: Stack<'static>,
+    pub nvs: SharedNvs,
+
+    /// Ssid of network connected on boot
+    pub ssid: String,
+    pub task_signals: Rc<WmTaskSignals>,
+    pub router: HttpRouter,
+    pub vars: AssetVars,
+    pub auth: Authenticator,
+    pub ota: Option<Rc<Ota>>,
+    pub log_capture: Option<&'static LogCapture>,
+}
+
+#[embassy_executor::task(pool_size = HTTP_MAX_WORKERS)]
+pub(crate) async fn admin_server(
+    worker_id: usize,
+    port: u16,
+    config: HttpServerConfig,
+    site: Rc<AdminSite>,
+) {
+    embassy_futures::select::select(
+        serve_connections(worker_id, site.stack, port, config, &*site),
+        site.task_signals.admin_shutdown_requested(),
+    )
+    .await;
+    log::info!("admin worker {worker_id} stopped");
+    site.task_signals.admin_worker_stopped();
+}
+
+impl HttpHandler for AdminSite {
+    fn handle<'a>(
+        &'a self,
+        request: &'a HttpRequest,
+        writer: &'a mut ResponseWriter<'_, '_>,
+    ) -> HandlerFuture<'a> {
+        Box::pin(async move {
+            if !self.auth.authorize(request, writer).await {
+                return;
+            }
+            if self.router.dispatch(request, writer).await {
+                return;
+            }
+
+            log::info!("[admin] {:?} {:?}", request.method, request.path);
+            if let Some(ota) = &self.ota {
+                if ota::handle_api(ota, self.auth.enabled(), request, writer).await {
+                    return;
+                }
+            }
+            if let Some(capture) = self.log_capture {
+                if log_capture::handle_api(capture, &self.auth, request, writer).await {
+                    return;
+                }
+            }
+            if request.path.starts_with(API_PREFIX) {
+                self.handle_api(request, writer).await;
+                return;
+            }
+
+            match assets::find(ADMIN_ASSETS, &request.path) {
+                Some(asset) if request.method == HttpMethod::Get => {
+                    let language = self.vars.language(request.header("Accept-Language"));
+                    writer.write_asset(asset, &self.vars, language).await;
+                }
+                Some(_) => writer.write_error(HttpError::MethodNotAllowed).await,
+                None => writer.write_error(HttpError::NotFound).await,
+            }
+        })
+    }
+
+    fn streams_body(&self, request: &HttpRequest) -> bool {
+        self.ota.is_some() && ota::is_upload(request)
+    }
+}
+
+impl AdminSite {
+    async fn handle_api(&self, request: &HttpRequest, writer: &mut ResponseWriter<'_, '_>) {
+        let endpoint = request.path.trim_start_matches(API_PREFIX);
+        match (request.method, endpoint) {
+            (HttpMethod::Get, "device") => {
+                let res = DeviceInfo::read(&self.vars.version)
+                    .to_json()
+                    .with("ip", self.ip())
+                    .with("hostname", self.vars.hostname.as_str());
+                writer.write_json(200, &res).await;
+            }
+            (HttpMethod::Get, "connection") => {
+                writer.write_json(200, &self.connection()).await;
+            }
+            (HttpMethod::Get, "saved") => {
+                let res = self.saved().await;
+                writer.write_json(200, &res).await;
+            }
+            (HttpMethod::Post, "saved") => match api::parse_setup(request) {
+                Ok(network) => {
+                    log::info!("[admin] saving network: {}", network.ssid);
+                    let mut nvs = self.nvs.lock().await;
+                    match crate::save_network(&mut nvs, network, false) {
+                        Ok(true) => {
+                            drop(nvs);
+                            let res = self.saved().await;
+                            writer.write_json(200, &res).await;
+                        }
+                        Ok(false) => {
+                            write_error(writer, 409, "storage_full", "All network slots are taken")
+                                .await
+                        }
+                        Err(e) => {
+                            log::error!("[admin] cannot save network: {e:?}");
+                            write_error(writer, 500, "storage_error", "Cannot write to flash").await
+                        }
+                    }
+                }
+                Err((code, message)) => write_error(writer, 400, code, message).await,
+            },
+            (HttpMethod::Delete, "saved") => {
+                let ssid = request
+                    .query_param("ssid")
+                    .and_then(|ssid| form::decode(ssid).ok());
+                let Some(ssid) = ssid.filter(|ssid| !ssid.is_empty()) else {
+                    write_error(
+                        writer,
+                        400,
+                        "invalid_ssid",
+                        "Query parameter `ssid` is required",
+                    )
+                    .await;
+                    return;
+                };
+
+                log::info!("[admin] forgetting network: {ssid}");
+                let mut nvs = self.nvs.lock().await;
+                match crate::forget_network(&mut nvs, &ssid) {
+                    Ok(true) => {
+                        drop(nvs);
+                        let res = self.saved().await;
+                        writer.write_json(200, &res).await;
+                    }
+                    Ok(false) => write_error(writer, 404, "not_found", "Network isnt saved").await,
+                    Err(e) => {
+                        log::error!("[admin] cannot forget network: {e:?}");
+                        write_error(writer, 500, "storage_error", "Cannot write to flash").await
+                    }
+                }
+            }
+            (HttpMethod::Post, "scan") => match api::parse_scan(request) {
+                Ok(scan) => {
+                    // scan is run by connection task (fails when wifi isnt connected)
+                    match with_timeout(SCAN_TIMEOUT, self.task_signals.scan(scan)).await {
+                        Ok(Ok(wifis)) => {
+                            let res = api::networks_json(&wifis).with("completed", true);
+                            writer.write_json(200, &res).await;
+                        }
+                        Ok(Err(e)) => {
+                            log::warn!("[admin] scan failed: {e:?}");
+                            write_error(writer, 503, "scan_failed", "Scan failed").await
+                        }
+                        Err(_) => {
+                            log::warn!("[admin] scan timed out");
+                            write_error(writer, 503, "scan_timeout", "Scan didnt finish in time")
+                                .await
+                        }
+                    }
+                }
+                Err((code, message)) => write_error(writer, 400, code, message).await,
+            },
+            (HttpMethod::Post, "reprovision") => {
+                let res = crate::request_reprovisioning(&mut *self.nvs.lock().await);
+                if let Err(e) = res {
+                    log::error!("[admin] cannot request reprovisioning: {e:?}");
+                    write_error(writer, 500, "storage_error", "Cannot write to flash").await;
+                    return;
+                }
+
+                log::info!("[admin] reprovisioning requested, restarting...");
+                let res = JsonValue::object().with("status", "restarting");
+                writer.write_json(202, &res).await;
+                Timer::after_millis(1000).await;
+                esp_hal::system::software_reset();
+            }
+            (_, "device" | "connection" | "saved" | "scan" | "reprovision") => {
+                write_error(writer, 405, "method_not_allowed", "Method not allowed").await;
+            }
+            _ => write_error(writer, 404, "not_found", "Unknown endpoint").await,
+        }
+    }
+
+    /// Station ip address (`None` without dhcp lease)
+    fn ip(&self) -> Option<String> {
+        self.stack
+            .config_v4()
+            .map(|config| alloc::format!("{}", config.address.address()))
+    }
+
+    /// `{"state":"connected","ssid":"..","ip":"..","prefix_len":24,"gateway":"..","dns":[".."]}`
+    fn connection(&self) -> JsonValue {
+        let connected = esp_radio::wifi::sta_state() == WifiStaState::Connected;
+        let mut res = JsonValue::object()
+            .with(
+                "state",
+                if connected {
+                    "connected"
+                } else {
+                    "disconnected"
+                },
+            )
+            .with("ssid", self.ssid.as_str())
+            .with("hostname", self.vars.hostname.as_str());
+        if let Some(config) = self.stack.config_v4() {
+            let dns: Vec<JsonValue> = config
+                .dns_servers
+                .iter()
+                .map(|dns| alloc::format!("{dns}").into())
+                .collect();
+            res = res
+                .with("ip", alloc::format!("{}", config.address.address()))
+                .with("prefix_len", config.address.prefix_len())
+                .with("gateway", config.gateway.map(|gw| alloc::format!("{gw}")))
+                .with("dns", dns);
+        }
+        res
+    }
+
+    /// `{"networks":[{"ssid":"..","country":"PL","connected":true}],"max":4}`
+    ///
+    /// Passwords are never sent back.
+    async fn saved(&self) -> JsonValue {
+        let networks = crate::saved_networks(&mut *self.nvs.lock().await);
+        let networks: Vec<JsonValue> = networks
+            .iter()
+            .map(|network| {
+                let country = network
+                    .country
+                    .as_ref()
+                    .and_then(|c| core::str::from_utf8(&c.country_code).ok());
+                JsonValue::object()
+                    .with("ssid", network.ssid.as_str())
+                    .with("country", country)
+                    .with("open", network.psk.is_empty())
+                    .with("connected", network.ssid == self.ssid)
+            })
+            .collect();
+
+        JsonValue::object()
+            .with("networks", networks)
+            .with("max", MAX_SAVED_NETWORKS)
+    }
+}
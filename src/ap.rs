@@ -1,32 +1,114 @@
 use alloc::rc::Rc;
-use embassy_net::{Runner, Stack};
-use embassy_time::Duration;
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    IpEndpoint, Runner, Stack,
+};
+use embassy_time::Instant;
 use esp_radio::wifi::WifiDevice;
+use esp_wifi_manager_core::dhcp::{
+    build_reply, DhcpLeases, DhcpOptions, DhcpRequest, MessageType, DHCP_CLIENT_PORT,
+    DHCP_SERVER_PORT,
+};
+
+use crate::{captive::CAPTIVE_API_PATH, structs::WmInnerSignals, WmSettings};
 
-use crate::structs::WmInnerSignals;
 #[embassy_executor::task]
-pub async fn run_dhcp_server(ap_stack: Stack<'static>) {
-    let mut leaser = esp_hal_dhcp_server::simple_leaser::SingleDhcpLeaser::new(
-        esp_hal_dhcp_server::Ipv4Addr::new(192, 168, 4, 100),
-    );
+pub async fn run_dhcp_server(
+    ap_stack: Stack<'static>,
+    settings: WmSettings,
+    signals: Rc<WmInnerSignals>,
+) {
+    let _running = signals.ap_task_running();
+    embassy_futures::select::select(serve(ap_stack, settings), signals.end_signalled()).await;
+    log::info!("dhcp server stopped");
+}
+
+async fn serve(ap_stack: Stack<'static>, settings: WmSettings) {
+    let Some((start, end)) = settings.dhcp_pool() else {
+        log::error!("run_dhcp_server failed! (AP subnet too small)");
+        return;
+    };
+    log::info!("dhcp pool: {start} - {end}");
 
-    let ip = esp_hal_dhcp_server::Ipv4Addr::new(192, 168, 4, 1);
-    let res = esp_hal_dhcp_server::run_dhcp_server(
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0u8; 1024];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0u8; 1024];
+    let mut socket = UdpSocket::new(
         ap_stack,
-        esp_hal_dhcp_server::structs::DhcpServerConfig {
-            ip,
-            lease_time: Duration::from_secs(3600),
-            gateways: &[ip],
-            subnet: None,
-            dns: &[ip],
-            use_captive_portal: true,
-        },
-        &mut leaser,
-    )
-    .await;
-
-    if let Err(e) = res {
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    if let Err(e) = socket.bind(DHCP_SERVER_PORT) {
         log::error!("run_dhcp_server failed! ({e:?})");
+        return;
+    }
+
+    let ip = settings.gw_ip_addr;
+    let portal_uri = alloc::format!("http://{ip}{CAPTIVE_API_PATH}");
+    let options = DhcpOptions {
+        server_ip: ip,
+        netmask: settings.ap_netmask(),
+        dns: settings.dhcp_dns.unwrap_or(ip),
+        lease_secs: settings.dhcp_lease_time.as_secs() as u32,
+        captive_portal: Some(&portal_uri),
+    };
+
+    // tracks leases by client mac, so every client gets own address
+    let mut leases = DhcpLeases::new(start, end);
+    let mut packet = [0u8; 576];
+    let mut reply = [0u8; 576];
+    loop {
+        let len = match socket.recv_from(&mut packet).await {
+            Ok((len, _)) => len,
+            Err(e) => {
+                log::warn!("dhcp recv error: {e:?}");
+                continue;
+            }
+        };
+        let Some(request) = DhcpRequest::parse(&packet[..len]) else {
+            continue;
+        };
+
+        let now = Instant::now().as_secs();
+        let (kind, yiaddr) = match request.kind {
+            MessageType::Discover => {
+                let Some(offer) = leases.offer(request.chaddr, request.requested_ip, now) else {
+                    log::warn!("dhcp pool exhausted");
+                    continue;
+                };
+                (MessageType::Offer, offer)
+            }
+            MessageType::Request => {
+                // client selected other server
+                if request.server_id.is_some_and(|server_id| server_id != ip) {
+                    continue;
+                }
+
+                // renewing clients send their address in ciaddr instead of option 50
+                let requested = request.requested_ip.unwrap_or(request.ciaddr);
+                if leases.ack(request.chaddr, requested, now, options.lease_secs) {
+                    (MessageType::Ack, requested)
+                } else {
+                    (MessageType::Nak, requested)
+                }
+            }
+            MessageType::Release | MessageType::Decline => {
+                leases.release(request.chaddr);
+                continue;
+            }
+            _ => continue,
+        };
+
+        let Some(reply_len) = build_reply(&request, kind, yiaddr, &options, &mut reply) else {
+            continue;
+        };
+        let endpoint = IpEndpoint::new(request.reply_addr(kind).into(), DHCP_CLIENT_PORT);
+        if let Err(e) = socket.send_to(&reply[..reply_len], endpoint).await {
+            log::warn!("dhcp send error: {e:?}");
+        }
     }
 }
 
@@ -35,5 +117,6 @@ pub async fn ap_task(
     mut runner: Runner<'static, WifiDevice<'static>>,
     signals: Rc<WmInnerSignals>,
 ) {
+    let _running = signals.ap_task_running();
     embassy_futures::select::select(runner.run(), signals.end_signalled()).await;
 }
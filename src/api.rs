@@ -0,0 +1,205 @@
+use alloc::{rc::Rc, string::String, vec::Vec};
+use core::net::Ipv4Addr;
+use embassy_time::Duration;
+
+use crate::assets::AssetVars;
+use crate::device_info::DeviceInfo;
+use crate::http_request::{HttpMethod, HttpRequest};
+use crate::http_server::ResponseWriter;
+use crate::json::{self, JsonValue};
+use crate::structs::{AutoSetupSettings, ConnState, ScanResult, ScanSettings, WmInnerSignals};
+use crate::utils::mac_str;
+use crate::ws;
+
+/// Prefix of versioned json api
+pub const API_PREFIX: &str = "/api/v1/";
+
+/// Handles `/api/v1/*` request
+///
+/// Every response (including errors) has json body,
+/// errors look like `{"error":"<code>","message":"<text>"}`.
+pub async fn handle_api(
+    request: &HttpRequest,
+    writer: &mut ResponseWriter<'_, '_>,
+    states: &Rc<WmInnerSignals>,
+    gw_ip_addr: Ipv4Addr,
+    vars: &AssetVars,
+) {
+    let endpoint = request.path.trim_start_matches(API_PREFIX);
+    match (request.method, endpoint) {
+        (HttpMethod::Get, "networks") => {
+            let networks = networks(states).await;
+            writer.write_json(200, &networks).await;
+        }
+        (HttpMethod::Post, "setup") => match parse_setup(request) {
+            Ok(setup) => {
+                log::info!("[api] setup: {}", setup.ssid);
+                states.submit_credentials(setup);
+                let res = JsonValue::object().with("status", "connecting");
+                writer.write_json(202, &res).await;
+            }
+            Err((code, message)) => write_error(writer, 400, code, message).await,
+        },
+        (HttpMethod::Post, "scan") => match parse_scan(request) {
+            Ok(scan) => {
+                let completed = states.scan(scan).await;
+                let res = networks(states).await.with("completed", completed);
+                writer.write_json(200, &res).await;
+            }
+            Err((code, message)) => write_error(writer, 400, code, message).await,
+        },
+        (HttpMethod::Get, "status") => {
+            writer.write_json(200, &status_json(states, vars)).await;
+            if let ConnState::Connected(_) = states.conn_state.get() {
+                // AP can go down now
+                states.result_fetched.signal(());
+            }
+        }
+        (HttpMethod::Get, "events") => ws::serve_events(request, writer, states, vars).await,
+        (HttpMethod::Get, "device") => {
+            let res = DeviceInfo::read(&vars.version)
+                .to_json()
+                .with("ip", alloc::format!("{gw_ip_addr}"));
+            writer.write_json(200, &res).await;
+        }
+        (_, "networks" | "scan" | "setup" | "status" | "device" | "events") => {
+            write_error(writer, 405, "method_not_allowed", "Method not allowed").await;
+        }
+        _ => write_error(writer, 404, "not_found", "Unknown endpoint").await,
+    }
+}
+
+pub(crate) async fn write_error(
+    writer: &mut ResponseWriter<'_, '_>,
+    status: u16,
+    code: &str,
+    message: &str,
+) {
+    writer.write_json(status, &error_json(code, message)).await;
+}
+
+/// `{"error":"<code>","message":"<text>"}`
+pub(crate) fn error_json(code: &str, message: &str) -> JsonValue {
+    JsonValue::object()
+        .with("error", code)
+        .with("message", message)
+}
+
+/// `{"state":"connected","provisioned":true,"scanning":false,"ip":"..","hostname":".."}`
+pub(crate) fn status_json(states: &WmInnerSignals, vars: &AssetVars) -> JsonValue {
+    let state = states.conn_state.get();
+    let res = JsonValue::object()
+        .with("state", state.as_str())
+        .with("provisioned", states.provisioned.get())
+        .with("scanning", states.scanning.get());
+    match state {
+        ConnState::Connected(ip) => res
+            .with("ip", alloc::format!("{}", Ipv4Addr::from(ip)))
+            .with("hostname", vars.hostname.as_str()),
+        ConnState::Failed(reason) => res.with("reason", reason.as_str()),
+        _ => res,
+    }
+}
+
+/// Last scan results
+async fn networks(states: &Rc<WmInnerSignals>) -> JsonValue {
+    networks_json(&states.wifi_scan_res.lock().await)
+}
+
+/// `{"networks":[{"ssid":"..","bssid":"..","channel":1,"rssi":-60,"auth":"..","open":false}]}`
+pub(crate) fn networks_json(wifis: &[ScanResult]) -> JsonValue {
+    let networks: Vec<JsonValue> = wifis
+        .iter()
+        .map(|wifi| {
+            JsonValue::object()
+                .with("ssid", wifi.ssid.as_str())
+                .with("bssid", mac_str(&wifi.bssid))
+                .with("channel", wifi.channel)
+                .with("rssi", wifi.rssi)
+                .with("auth", wifi.auth_method.map(|a| alloc::format!("{a:?}")))
+                .with("open", wifi.is_open())
+        })
+        .collect();
+
+    JsonValue::object().with("networks", networks)
+}
+
+/// Parses `{"passive":false,"dwell_ms":120,"channels":[1,6,11],"show_hidden":false}`
+///
+/// Every field is optional, empty body means default scan.
+pub(crate) fn parse_scan(
+    request: &HttpRequest,
+) -> Result<ScanSettings, (&'static str, &'static str)> {
+    const INVALID: (&str, &str) = ("invalid_scan", "Invalid scan parameters");
+
+    let mut scan = ScanSettings::default();
+    let body = request
+        .body_str()
+        .ok_or(("invalid_json", "Body is not valid utf-8"))?;
+    if body.trim().is_empty() {
+        return Ok(scan);
+    }
+    let params = json::parse(body).map_err(|e| {
+        log::warn!("[api] invalid json: {e}");
+        ("invalid_json", "Body is not valid json")
+    })?;
+
+    if let Some(passive) = params.get("passive") {
+        scan.passive = passive.as_bool().ok_or(INVALID)?;
+    }
+    if let Some(show_hidden) = params.get("show_hidden") {
+        scan.show_hidden = show_hidden.as_bool().ok_or(INVALID)?;
+    }
+    if let Some(dwell_ms) = params.get("dwell_ms") {
+        let dwell_ms = dwell_ms.as_u64().ok_or(INVALID)?;
+        scan.dwell_time = Some(Duration::from_millis(dwell_ms));
+    }
+    if let Some(channels) = params.get("channels") {
+        let channels = channels
+            .as_array()
+            .ok_or(INVALID)?
+            .iter()
+            .map(|ch| ch.as_u64().and_then(|ch| u8::try_from(ch).ok()))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or(INVALID)?;
+        scan = scan.with_channels(&channels);
+    }
+
+    scan.validate().map_err(|_| INVALID)?;
+    Ok(scan)
+}
+
+/// Parses `{"ssid":"..","psk":"..","country":".."}` (`psk` and `country` are optional)
+pub(crate) fn parse_setup(
+    request: &HttpRequest,
+) -> Result<AutoSetupSettings, (&'static str, &'static str)> {
+    let body = request
+        .body_str()
+        .ok_or(("invalid_json", "Body is not valid utf-8"))?;
+    let setup = json::parse(body).map_err(|e| {
+        log::warn!("[api] invalid json: {e}");
+        ("invalid_json", "Body is not valid json")
+    })?;
+
+    let ssid = setup
+        .get("ssid")
+        .and_then(JsonValue::as_str)
+        .ok_or(("invalid_ssid", "Field `ssid` (string) is required"))?;
+    let psk = match setup.get("psk") {
+        None | Some(JsonValue::Null) => "",
+        Some(psk) => psk
+            .as_str()
+            .ok_or(("invalid_psk", "Field `psk` must be string"))?,
+    };
+    let country = match setup.get("country") {
+        None | Some(JsonValue::Null) => None,
+        Some(country) => Some(
+            country
+                .as_str()
+                .ok_or(("invalid_country", "Field `country` must be string"))?,
+        ),
+    };
+
+    AutoSetupSettings::new(String::from(ssid), String::from(psk), country)
+        .map_err(|e| (e.code(), e.message()))
+}
@@ -0,0 +1,177 @@
+use alloc::{string::String, vec::Vec};
+use esp_wifi_manager_core::gzip::{html_escape, GzipBuilder};
+
+use crate::i18n::{self, Language};
+use crate::WmSettings;
+
+/// File served by provisioning http server
+///
+/// Tables are generated by `build.rs`: built-in portal pages and files from
+/// directory in `WM_ASSETS_DIR` env var (`CUSTOM_ASSETS`), text files are
+/// gzipped at build time. Own table can be set with `WmSettings::assets`.
+pub struct StaticAsset {
+    /// Url path (`/`, `/style.css`)
+    pub path: &'static str,
+    pub content_type: &'static str,
+    pub body: AssetBody,
+}
+
+pub enum AssetBody {
+    /// Sent as is
+    Raw(&'static [u8]),
+
+    /// Gzip stream, sent with `Content-Encoding: gzip`
+    Gzip(&'static [u8]),
+
+    /// Gzip stream assembled per request, `{{device_name}}`, `{{mac}}`,
+    /// `{{version}}`, `{{hostname}}`, `{{lang}}` and `{{t:key}}` placeholders are replaced
+    /// by `AssetVar` values
+    GzipTemplate(&'static [TemplatePart]),
+}
+
+pub enum TemplatePart {
+    /// Raw deflate blocks ending with sync flush (not final block)
+    Deflate {
+        data: &'static [u8],
+
+        /// Uncompressed length and crc32
+        len: u32,
+        crc: u32,
+    },
+    Var(AssetVar),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AssetVar {
+    /// `WmSettings::ssid`
+    DeviceName,
+
+    /// `AA:BB:CC:DD:EE:FF`
+    Mac,
+
+    /// App descriptor version (`WmSettings::firmware_version` as fallback)
+    Version,
+
+    /// `WmSettings::hostname`
+    Hostname,
+
+    /// Code of negotiated language (`en`)
+    Lang,
+
+    /// Text of negotiated language (`{{t:key}}`)
+    Text(&'static str),
+}
+
+/// Files from `WM_ASSETS_DIR` (empty if not set), default of `WmSettings::assets`
+pub const CUSTOM_ASSETS: &[StaticAsset] = include!(concat!(env!("OUT_DIR"), "/custom_assets.rs"));
+
+/// Portal page (`/`), success page (`/done.html`) and favicon
+pub(crate) const BUILTIN_ASSETS: &[StaticAsset] =
+    include!(concat!(env!("OUT_DIR"), "/builtin_assets.rs"));
+
+/// Admin page (`/`) and favicon served on station network
+pub(crate) const ADMIN_ASSETS: &[StaticAsset] =
+    include!(concat!(env!("OUT_DIR"), "/admin_assets.rs"));
+
+impl core::fmt::Debug for StaticAsset {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let body = match self.body {
+            AssetBody::Raw(data) => ("raw", data.len()),
+            AssetBody::Gzip(data) => ("gzip", data.len()),
+            AssetBody::GzipTemplate(parts) => ("template", parts.len()),
+        };
+        f.debug_struct("StaticAsset")
+            .field("path", &self.path)
+            .field("content_type", &self.content_type)
+            .field("body", &body)
+            .finish()
+    }
+}
+
+/// Values substituted into templates
+#[derive(Clone, Debug)]
+pub(crate) struct AssetVars {
+    pub device_name: String,
+    pub mac: String,
+    pub version: String,
+    pub hostname: String,
+    pub languages: Vec<&'static Language>,
+    pub default_language: &'static str,
+}
+
+impl AssetVars {
+    pub fn new(settings: &WmSettings) -> Self {
+        Self {
+            device_name: settings.ssid.clone(),
+            mac: crate::utils::mac_str(&crate::get_efuse_mac().to_be_bytes()[2..]),
+            version: String::from(crate::device_info::firmware_version(
+                &settings.firmware_version,
+            )),
+            hostname: settings.hostname.clone(),
+            languages: settings.languages.clone(),
+            default_language: settings.default_language,
+        }
+    }
+
+    /// Language for `Accept-Language` header
+    pub fn language(&self, accept_language: Option<&str>) -> &'static Language {
+        i18n::select(accept_language, &self.languages, self.default_language)
+    }
+
+    fn get<'a>(&'a self, var: AssetVar, language: &Language) -> &'a str {
+        match var {
+            AssetVar::DeviceName => &self.device_name,
+            AssetVar::Mac => &self.mac,
+            AssetVar::Version => &self.version,
+            AssetVar::Hostname => &self.hostname,
+            AssetVar::Lang => language.code,
+            AssetVar::Text(key) => language.text(key),
+        }
+    }
+}
+
+/// Finds asset for path, `custom` table first
+pub(crate) fn find<'a>(custom: &'a [StaticAsset], path: &str) -> Option<&'a StaticAsset> {
+    let path = if path.is_empty() { "/" } else { path };
+    custom
+        .iter()
+        .chain(BUILTIN_ASSETS)
+        .find(|asset| asset.path == path)
+}
+
+/// Builds gzip stream of template
+///
+/// Static parts are precompressed, values go in between as stored blocks.
+/// Html (and svg) values are escaped.
+pub(crate) fn render_template(
+    parts: &[TemplatePart],
+    content_type: &str,
+    vars: &AssetVars,
+    language: &Language,
+) -> Vec<u8> {
+    let escape = content_type.starts_with("text/html") || content_type.starts_with("image/svg");
+    let mut out = GzipBuilder::with_capacity(
+        10 + 13
+            + parts
+                .iter()
+                .map(|part| match part {
+                    TemplatePart::Deflate { data, .. } => data.len(),
+                    TemplatePart::Var(_) => 64,
+                })
+                .sum::<usize>(),
+    );
+    for part in parts {
+        match part {
+            TemplatePart::Deflate { data, len, crc } => out.deflate(data, *len, *crc),
+            TemplatePart::Var(var) => {
+                let value = vars.get(*var, language);
+                if escape {
+                    out.stored(html_escape(value).as_bytes())
+                } else {
+                    out.stored(value.as_bytes())
+                }
+            }
+        }
+    }
+    out.finish()
+}
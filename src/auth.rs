@@ -0,0 +1,297 @@
+use alloc::{format, string::String, string::ToString, vec::Vec};
+use core::cell::RefCell;
+use core::net::IpAddr;
+use embassy_time::{Duration, Instant};
+use esp_wifi_manager_core::auth::{
+    self, base32, base64_decode, constant_time_eq, hmac_sha256, sha256,
+};
+
+use crate::api::{self, API_PREFIX};
+use crate::http_request::{status_text, HttpMethod, HttpRequest};
+use crate::http_server::ResponseWriter;
+
+/// Authentication of state-changing http requests (everything except `GET` / `HEAD`)
+///
+/// Pages, status and captive portal probes stay public.
+#[derive(Clone, Default, PartialEq)]
+pub enum HttpAuth {
+    #[default]
+    None,
+
+    /// `Authorization: Basic` (browsers ask for it)
+    Basic { user: String, password: String },
+
+    /// `Authorization: Bearer <token>` (portal asks for token)
+    Token(String),
+}
+
+impl core::fmt::Debug for HttpAuth {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::None => f.write_str("None"),
+            // password / token isnt printed
+            Self::Basic { user, .. } => f
+                .debug_struct("Basic")
+                .field("user", user)
+                .finish_non_exhaustive(),
+            Self::Token(_) => f.write_str("Token(..)"),
+        }
+    }
+}
+
+impl HttpAuth {
+    /// Basic auth with password derived from device mac and `secret`
+    ///
+    /// Password is lowercase base32 of first 10 bytes of
+    /// `HMAC-SHA256(secret, mac)`, so it can be computed for label of every device
+    /// (`base64.b32encode(hmac.new(secret, mac, "sha256").digest()[:10]).decode().lower()`).
+    pub fn per_device(user: &str, secret: &[u8]) -> Self {
+        let mac = crate::get_efuse_mac().to_be_bytes();
+        let digest = hmac_sha256(secret, &mac[2..]);
+        Self::Basic {
+            user: String::from(user),
+            password: base32(&digest[..10]),
+        }
+    }
+
+    pub(crate) fn validate(&self) -> bool {
+        match self {
+            Self::None => true,
+            Self::Basic { user, password } => auth::basic_credentials_valid(user, password),
+            Self::Token(token) => auth::token_valid(token),
+        }
+    }
+}
+
+/// Lockout of clients sending wrong credentials
+#[derive(Clone, Copy, Debug)]
+pub struct AuthLockout {
+    /// Failed attempts after which client is locked out
+    pub max_failures: u8,
+
+    /// Time client stays locked out (requests get `429 Too Many Requests`)
+    pub duration: Duration,
+}
+
+impl Default for AuthLockout {
+    fn default() -> Self {
+        Self {
+            max_failures: 5,
+            duration: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Number of clients with failed attempts tracked at once
+const MAX_TRACKED_CLIENTS: usize = 8;
+
+struct ClientFailures {
+    addr: Option<IpAddr>,
+    failures: u8,
+    locked_until: Option<Instant>,
+    last_failure: Instant,
+}
+
+/// Checks credentials, shared by workers of one server
+pub(crate) struct Authenticator {
+    auth: HttpAuth,
+
+    /// Sha256 of expected `user:password` / token (compared in constant time)
+    expected: [u8; 32],
+    lockout: AuthLockout,
+    realm: String,
+    clients: RefCell<Vec<ClientFailures>>,
+}
+
+impl Authenticator {
+    pub fn new(auth: HttpAuth, lockout: AuthLockout, realm: &str) -> Self {
+        let expected = match auth {
+            HttpAuth::None => [0; 32],
+            HttpAuth::Basic {
+                ref user,
+                ref password,
+            } => sha256(format!("{user}:{password}").as_bytes()),
+            HttpAuth::Token(ref token) => sha256(token.as_bytes()),
+        };
+        Self {
+            auth,
+            expected,
+            lockout,
+            // quoted in `WWW-Authenticate`
+            realm: realm.replace('"', "'"),
+            clients: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Credentials are required (`HttpAuth` other than `None`)
+    pub fn enabled(&self) -> bool {
+        self.auth != HttpAuth::None
+    }
+
+    /// Checks credentials of state-changing request (`GET` / `HEAD` always pass)
+    ///
+    /// Writes `401` (or `429` for locked out client) and returns `false` if
+    /// request isnt authorized.
+    pub async fn authorize(
+        &self,
+        request: &HttpRequest,
+        writer: &mut ResponseWriter<'_, '_>,
+    ) -> bool {
+        if matches!(request.method, HttpMethod::Get | HttpMethod::Head) {
+            return true;
+        }
+        self.require(request, writer).await
+    }
+
+    /// Checks credentials of any request (for reads that arent public, like logs)
+    pub async fn require(
+        &self,
+        request: &HttpRequest,
+        writer: &mut ResponseWriter<'_, '_>,
+    ) -> bool {
+        if !self.enabled() {
+            return true;
+        }
+
+        let now = Instant::now();
+        if let Some(locked_until) = self.locked_until(request.remote, now) {
+            let retry_after = format!("{}", (locked_until - now).as_secs() + 1);
+            self.write_error(
+                request,
+                writer,
+                429,
+                "locked_out",
+                "Too many failed attempts",
+                ("Retry-After", &retry_after),
+            )
+            .await;
+            return false;
+        }
+
+        let provided = self.credentials(request);
+        let valid = provided
+            .as_ref()
+            .is_some_and(|provided| constant_time_eq(&sha256(provided), &self.expected));
+        if valid {
+            self.clients
+                .borrow_mut()
+                .retain(|client| client.addr != request.remote);
+            return true;
+        }
+
+        // request without credentials (browser before asking user) isnt a failure
+        if provided.is_some() {
+            log::warn!("[auth] wrong credentials from {:?}", request.remote);
+            self.record_failure(request.remote, now);
+        }
+        let challenge = match self.auth {
+            HttpAuth::Token(_) => format!("Bearer realm=\"{}\"", self.realm),
+            _ => format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm),
+        };
+        self.write_error(
+            request,
+            writer,
+            401,
+            "unauthorized",
+            "Authentication required",
+            ("WWW-Authenticate", &challenge),
+        )
+        .await;
+        false
+    }
+
+    /// Credentials sent by client (`user:password` or token)
+    fn credentials(&self, request: &HttpRequest) -> Option<Vec<u8>> {
+        let (scheme, value) = request.header("Authorization")?.split_once(' ')?;
+        match self.auth {
+            HttpAuth::Basic { .. } if scheme.eq_ignore_ascii_case("Basic") => {
+                base64_decode(value.trim())
+            }
+            HttpAuth::Token(_) if scheme.eq_ignore_ascii_case("Bearer") => {
+                Some(Vec::from(value.trim().as_bytes()))
+            }
+            _ => None,
+        }
+    }
+
+    fn locked_until(&self, addr: Option<IpAddr>, now: Instant) -> Option<Instant> {
+        self.clients
+            .borrow()
+            .iter()
+            .find(|client| client.addr == addr)
+            .and_then(|client| client.locked_until)
+            .filter(|locked_until| *locked_until > now)
+    }
+
+    fn record_failure(&self, addr: Option<IpAddr>, now: Instant) {
+        let mut clients = self.clients.borrow_mut();
+        let pos = match clients.iter().position(|client| client.addr == addr) {
+            Some(pos) => pos,
+            None => {
+                let client = ClientFailures {
+                    addr,
+                    failures: 0,
+                    locked_until: None,
+                    last_failure: now,
+                };
+                if clients.len() < MAX_TRACKED_CLIENTS {
+                    clients.push(client);
+                    clients.len() - 1
+                } else {
+                    // replaces client with the oldest failure, locked out ones last
+                    let oldest = (0..clients.len())
+                        .min_by_key(|&i| {
+                            let locked = clients[i].locked_until.is_some_and(|until| until > now);
+                            (locked, clients[i].last_failure)
+                        })
+                        .unwrap_or_default();
+                    clients[oldest] = client;
+                    oldest
+                }
+            }
+        };
+
+        let client = &mut clients[pos];
+        if client.locked_until.is_some_and(|until| until <= now) {
+            // lockout ended, start counting again
+            client.locked_until = None;
+            client.failures = 0;
+        }
+        client.failures = client.failures.saturating_add(1);
+        client.last_failure = now;
+        if client.failures >= self.lockout.max_failures {
+            log::warn!(
+                "[auth] {addr:?} locked out for {} s",
+                self.lockout.duration.as_secs()
+            );
+            client.locked_until = Some(now + self.lockout.duration);
+        }
+    }
+
+    /// Json error for api paths, reason phrase for others
+    async fn write_error(
+        &self,
+        request: &HttpRequest,
+        writer: &mut ResponseWriter<'_, '_>,
+        status: u16,
+        code: &str,
+        message: &str,
+        header: (&str, &str),
+    ) {
+        if request.path.starts_with(API_PREFIX) {
+            let body = api::error_json(code, message).to_string();
+            writer
+                .write_response_with_headers(status, "application/json", &[header], body.as_bytes())
+                .await;
+        } else {
+            writer
+                .write_response_with_headers(
+                    status,
+                    "text/plain",
+                    &[header],
+                    status_text(status).as_bytes(),
+                )
+                .await;
+        }
+    }
+}
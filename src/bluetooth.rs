@@ -1,11 +1,19 @@
 use crate::{
-    http_server::parse_form_data,
-    structs::{AutoSetupSettings, WmInnerSignals},
+    device_info::DeviceInfo,
+    form,
+    http_server::{parse_form_data, parse_scan_form},
+    i18n::{self, Language},
+    log_capture::LogCapture,
+    ota::{self, Ota, OtaError, OtaSession, PausedOta},
+    structs::{ConnState, ScanResult, WmInnerSignals, WmTaskSignals},
+};
+use alloc::{format, rc::Rc, string::String, vec::Vec};
+use core::{
+    cell::{Cell, RefCell},
+    net::Ipv4Addr,
 };
-use alloc::{rc::Rc, string::String};
-use core::str::FromStr;
 use embassy_futures::select::Either::{First, Second};
-use embassy_time::Timer;
+use embassy_time::{Duration, Instant, Timer};
 use esp_hal::peripherals::BT;
 use esp_radio::{ble::controller::BleConnector, Controller as RadioController};
 use rand_core::OsRng;
@@ -14,9 +22,17 @@ use trouble_host::prelude::*;
 const CONNECTIONS_MAX: usize = 1;
 const L2CAP_CHANNELS_MAX: usize = 2; // Signal + att
 
+/// Received bytes between progress notifications of ota status
+const OTA_PROGRESS_INTERVAL: usize = 16 * 1024;
+
+/// Ota transfer without commands / chunks for this long is paused
+const OTA_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
 #[gatt_server]
 struct Server {
     wifi_service: WifiService,
+    device_info: DeviceInformationService,
+    ota_service: OtaService,
 }
 
 #[gatt_service(uuid = "f254a578-ef88-4372-b5f5-5ecf87e65884")]
@@ -26,14 +42,314 @@ struct WifiService {
 
     #[characteristic(uuid = "22e997b5-0ac5-475d-ab6c-9c9568b6620a", read)]
     wifi_scan_res: heapless::String<512>,
+
+    /// Scan request (urlencoded scan parameters, NUL terminated like setup string)
+    #[characteristic(uuid = "5b7c2f4e-0d51-4c8a-9a8e-6f3c1d2b7e41", write)]
+    scan_request: heapless::String<128>,
+
+    /// `idle`, `scanning`, `done`, `aborted` or `invalid` (notified on change)
+    #[characteristic(uuid = "8e1f6a3d-2c47-4b9e-b5d0-3a7e9c4f1b62", read, notify)]
+    scan_status: heapless::String<16>,
+
+    /// Preferred languages (`Accept-Language` format, NUL terminated)
+    #[characteristic(uuid = "3d9b6e21-7f4a-4c58-8e0b-5a1c2f7d9e34", write)]
+    language: heapless::String<64>,
+
+    /// Human readable status in selected language (notified on change)
+    #[characteristic(uuid = "c4a2e8f1-5b3d-4e6a-9f17-2d8b0c6e4a95", read, notify)]
+    status_message: heapless::String<128>,
+
+    /// Last kept log records (`WmSettings::log_capture`, refreshed on read)
+    #[characteristic(uuid = "9b2d4f6a-1c3e-4a5b-8d7f-0e2c4a6b8d91", read)]
+    logs: heapless::String<512>,
+}
+
+/// Standard device information service (values are set on start)
+#[gatt_service(uuid = service::DEVICE_INFORMATION)]
+struct DeviceInformationService {
+    /// Chip model (`esp32c3`...)
+    #[characteristic(uuid = characteristic::MODEL_NUMBER_STRING, read)]
+    model: heapless::String<16>,
+
+    /// Mac address (`AA:BB:CC:DD:EE:FF`)
+    #[characteristic(uuid = characteristic::SERIAL_NUMBER_STRING, read)]
+    serial: heapless::String<17>,
+
+    /// App descriptor version (`WmSettings::firmware_version` as fallback)
+    #[characteristic(uuid = characteristic::FIRMWARE_REVISION_STRING, read)]
+    firmware: heapless::String<32>,
+
+    /// Wifi manager version
+    #[characteristic(uuid = characteristic::SOFTWARE_REVISION_STRING, read)]
+    software: heapless::String<32>,
+
+    /// `free_heap=<bytes>&uptime=<seconds>` (refreshed on read)
+    #[characteristic(uuid = "d1e5a7c0-3b2f-4e8d-9a61-7c4f0b2e8d53", read)]
+    runtime: heapless::String<64>,
+}
+
+#[gatt_service(uuid = "7a3f0c21-9e4b-4d6a-8c15-2b7e5d9f1a40")]
+struct OtaService {
+    /// `cmd=begin&size=..&digest=..`, `cmd=finish` or `cmd=abort` (NUL terminated)
+    #[characteristic(uuid = "7a3f0c22-9e4b-4d6a-8c15-2b7e5d9f1a40", write)]
+    control: heapless::String<128>,
+
+    /// Image chunks: offset (u32 little endian) followed by data
+    #[characteristic(uuid = "7a3f0c23-9e4b-4d6a-8c15-2b7e5d9f1a40", write_without_response)]
+    data: heapless::Vec<u8, 512>,
+
+    /// `idle`, `receiving <received>/<size>`, `restarting` or `error <code>`
+    #[characteristic(uuid = "7a3f0c24-9e4b-4d6a-8c15-2b7e5d9f1a40", read, notify)]
+    status: heapless::String<64>,
+}
+
+/// Selected language of BLE status messages
+struct BleLanguage {
+    languages: Vec<&'static Language>,
+    default: &'static str,
+    current: Cell<&'static Language>,
+}
+
+impl BleLanguage {
+    fn text(&self, key: &str) -> &'static str {
+        self.current.get().text(key)
+    }
+}
+
+/// Image transfer over BLE, kept across connections (client resumes after reconnect)
+///
+/// Transfer is paused on disconnect and after `OTA_IDLE_TIMEOUT`, so it doesnt
+/// block http updates meanwhile.
+struct BleOta<'a> {
+    ota: Option<&'a Ota>,
+    transfer: RefCell<Option<OtaTransfer<'a>>>,
+    last_activity: Cell<Instant>,
+}
+
+struct OtaTransfer<'a> {
+    session: TransferSession<'a>,
+    digest: [u8; 32],
+}
+
+enum TransferSession<'a> {
+    Active(OtaSession<'a>),
+    Paused(PausedOta<'a>),
+}
+
+impl<'a> TransferSession<'a> {
+    fn received(&self) -> usize {
+        match self {
+            Self::Active(session) => session.received(),
+            Self::Paused(paused) => paused.received(),
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Self::Active(session) => session.size(),
+            Self::Paused(paused) => paused.size(),
+        }
+    }
+
+    fn resume(self) -> Result<OtaSession<'a>, OtaError> {
+        match self {
+            Self::Active(session) => Ok(session),
+            Self::Paused(paused) => paused.resume(),
+        }
+    }
+}
+
+impl BleOta<'_> {
+    fn status(&self) -> String {
+        match &*self.transfer.borrow() {
+            Some(transfer) => format!(
+                "receiving {}/{}",
+                transfer.session.received(),
+                transfer.session.size()
+            ),
+            None => String::from("idle"),
+        }
+    }
+
+    /// Releases ota for other updates, `begin` (or next chunk) resumes the transfer
+    fn pause(&self) {
+        let mut transfer = self.transfer.borrow_mut();
+        if let Some(OtaTransfer {
+            session: TransferSession::Active(session),
+            digest,
+        }) = transfer.take()
+        {
+            log::info!("[ble] pausing ota transfer at {}", session.received());
+            *transfer = Some(OtaTransfer {
+                session: TransferSession::Paused(session.pause()),
+                digest,
+            });
+        }
+    }
+
+    fn pause_if_idle(&self) {
+        if self.last_activity.get().elapsed() >= OTA_IDLE_TIMEOUT {
+            self.pause();
+        }
+    }
+
+    /// Handles control command, returns new status
+    fn command(&self, command: &str) -> String {
+        let Some(ota) = self.ota else {
+            return String::from("error ota_unavailable");
+        };
+        let Ok(params) = form::parse(command) else {
+            return String::from("error invalid_command");
+        };
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+        self.last_activity.set(Instant::now());
+
+        match param("cmd") {
+            // client supplied sha256 would let anybody in range flash own image
+            Some("begin") if !ota.authenticated() => String::from("error verification_required"),
+            Some("begin") => {
+                let size = param("size").and_then(|size| size.parse().ok());
+                let digest = param("digest").and_then(ota::parse_hex_digest);
+                let (Some(size), Some(digest)) = (size, digest) else {
+                    return String::from("error invalid_command");
+                };
+
+                // other transfer is dropped here (releases ota)
+                let mut transfer = self.transfer.borrow_mut();
+                let previous = transfer
+                    .take()
+                    .filter(|t| t.session.size() == size && t.digest == digest);
+                let session = match previous.map(|t| t.session.resume()) {
+                    Some(Ok(session)) => {
+                        log::info!("[ble] resuming ota transfer");
+                        session
+                    }
+                    // partition was used by another update meanwhile
+                    Some(Err(OtaError::Interrupted)) | None => match ota.begin(size) {
+                        Ok(session) => session,
+                        Err(e) => return format!("error {}", e.code()),
+                    },
+                    Some(Err(e)) => return format!("error {}", e.code()),
+                };
+                *transfer = Some(OtaTransfer {
+                    session: TransferSession::Active(session),
+                    digest,
+                });
+                drop(transfer);
+                self.status()
+            }
+            Some("finish") => {
+                let Some(transfer) = self.transfer.take() else {
+                    return String::from("error no_transfer");
+                };
+                match transfer
+                    .session
+                    .resume()
+                    .and_then(|session| session.finish(&transfer.digest))
+                {
+                    Ok(_) => String::from("restarting"),
+                    Err(e) => format!("error {}", e.code()),
+                }
+            }
+            Some("abort") => {
+                self.transfer.take();
+                String::from("idle")
+            }
+            _ => String::from("error invalid_command"),
+        }
+    }
+
+    /// Writes chunk (offset + data), returns status to notify
+    ///
+    /// Chunk at other offset than expected is dropped, client continues from
+    /// offset in the status.
+    fn receive(&self, chunk: &[u8]) -> Option<String> {
+        let mut transfer = self.transfer.borrow_mut();
+        let Some(current) = transfer.take() else {
+            return Some(String::from("error no_transfer"));
+        };
+        self.last_activity.set(Instant::now());
+        let mut session = match current.session.resume() {
+            Ok(session) => session,
+            Err(e) => return Some(format!("error {}", e.code())),
+        };
+        let received = session.received();
+        let offset = chunk
+            .split_at_checked(4)
+            .and_then(|(offset, data)| Some((u32::from_le_bytes(offset.try_into().ok()?), data)));
+        let res = match offset {
+            Some((offset, data)) if offset as usize == received => session.write(data),
+            // client continues from received offset
+            Some(_) => Ok(()),
+            None => {
+                *transfer = Some(OtaTransfer {
+                    session: TransferSession::Active(session),
+                    digest: current.digest,
+                });
+                return None;
+            }
+        };
+        if let Err(e) = res {
+            return Some(format!("error {}", e.code()));
+        }
+
+        let now = session.received();
+        let complete = now == session.size();
+        let progress = now / OTA_PROGRESS_INTERVAL != received / OTA_PROGRESS_INTERVAL;
+        *transfer = Some(OtaTransfer {
+            session: TransferSession::Active(session),
+            digest: current.digest,
+        });
+        drop(transfer);
+        (progress || complete || now == received).then(|| self.status())
+    }
 }
 
 #[embassy_executor::task]
+#[allow(clippy::too_many_arguments)]
 pub async fn bluetooth_task(
     init: &'static RadioController<'static>,
     bt: BT<'static>,
     name: String,
     signals: Rc<WmInnerSignals>,
+    task_signals: Rc<WmTaskSignals>,
+    languages: Vec<&'static Language>,
+    default_language: &'static str,
+    ota: Option<Rc<Ota>>,
+    log_capture: Option<&'static LogCapture>,
+    firmware_version: String,
+) {
+    let language = BleLanguage {
+        current: Cell::new(i18n::select(None, &languages, default_language)),
+        languages,
+        default: default_language,
+    };
+    let ota = BleOta {
+        ota: ota.as_deref(),
+        transfer: RefCell::new(None),
+        last_activity: Cell::new(Instant::now()),
+    };
+    let info = DeviceInfo::read(&firmware_version);
+    run_ble(init, bt, name, signals, &language, &ota, log_capture, &info).await;
+    // connector is dropped here, so ble is deinitialized
+    task_signals.ble_stopped.signal(());
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn run_ble(
+    init: &'static RadioController<'static>,
+    bt: BT<'static>,
+    name: String,
+    signals: Rc<WmInnerSignals>,
+    language: &BleLanguage,
+    ota: &BleOta<'_>,
+    log_capture: Option<&'static LogCapture>,
+    info: &DeviceInfo,
 ) {
     let Ok(connector) = BleConnector::new(init, bt, esp_radio::ble::Config::default()) else {
         log::error!("Cannot init ble connector");
@@ -47,9 +363,11 @@ pub async fn bluetooth_task(
 
     let mut resources: HostResources<DefaultPacketPool, CONNECTIONS_MAX, L2CAP_CHANNELS_MAX> =
         HostResources::new();
+    // no display / keyboard, pairing is "just works" (encrypted, not authenticated)
     let stack = trouble_host::new(controller, &mut resources)
         .set_random_address(address)
-        .set_random_generator_seed(&mut OsRng);
+        .set_random_generator_seed(&mut OsRng)
+        .set_io_capabilities(IoCapabilities::NoInputNoOutput);
 
     let Host {
         mut peripheral,
@@ -62,19 +380,22 @@ pub async fn bluetooth_task(
         appearance: &appearance::power_device::GENERIC_POWER_DEVICE,
     }))
     .unwrap();
+    set_device_info(&server, info);
 
     _ = embassy_futures::select::select3(ble_task(runner), stop_ble_task(&signals), async {
         loop {
             match advertise(&name, &mut peripheral, &server).await {
                 Ok(conn) => {
-                    let a = gatt_events_task(&server, &conn, &signals);
-                    let b = custom_task(&server, &conn, &stack, &signals);
+                    let a = gatt_events_task(&server, &conn, &signals, ota, log_capture);
+                    let b = custom_task(&server, &conn, &stack, &signals, language, ota);
 
                     let res = embassy_futures::select::select(a, b).await;
                     match res {
                         First(_) => {}
                         Second(_) => {}
                     }
+                    // client resumes after reconnect, other updates can run meanwhile
+                    ota.pause();
                 }
                 Err(e) => {
                     log::error!("[adv] error: {e:?}");
@@ -97,31 +418,45 @@ async fn gatt_events_task<P: PacketPool>(
     server: &Server<'_>,
     conn: &GattConnection<'_, '_, P>,
     signals: &Rc<WmInnerSignals>,
+    ota: &BleOta<'_>,
+    log_capture: Option<&'static LogCapture>,
 ) -> Result<(), Error> {
     let reason = loop {
         let event = conn.next().await;
         match event {
             GattConnectionEvent::Disconnected { reason } => break reason,
             GattConnectionEvent::Gatt { event } => {
+                if requires_encryption(server, event_handle(&event)) && !encrypted(conn) {
+                    // client pairs and retries
+                    match event.reject(AttErrorCode::INSUFFICIENT_AUTHENTICATION) {
+                        Ok(reply) => reply.send().await,
+                        Err(e) => log::warn!("[gatt] error sending response: {e:?}"),
+                    };
+                    continue;
+                }
+
+                let mut ota_status = None;
                 match &event {
                     GattEvent::Read(event) => {
                         if event.handle() == server.wifi_service.wifi_scan_res.handle {
                             if let Ok(wifis) = signals.wifi_scan_res.try_lock() {
-                                let wifis = wifis.as_str();
-                                let wifis = if wifis.len() > 512 {
-                                    &wifis[..512]
-                                } else {
-                                    wifis
-                                };
-
-                                _ = server.set(
-                                    &server.wifi_service.wifi_scan_res,
-                                    &heapless::String::from_str(wifis).unwrap(),
-                                );
+                                _ = server
+                                    .set(&server.wifi_service.wifi_scan_res, &scan_list(&wifis));
                             }
+                        } else if event.handle() == server.wifi_service.logs.handle {
+                            if let Some(capture) = log_capture {
+                                _ = server.set(&server.wifi_service.logs, &logs_tail(capture));
+                            }
+                        } else if event.handle() == server.device_info.runtime.handle {
+                            _ = server.set(&server.device_info.runtime, &runtime_info());
+                        }
+                    }
+                    // chunks are handled here, polling the value would lose them
+                    GattEvent::Write(event) => {
+                        if event.handle() == server.ota_service.data.handle {
+                            ota_status = ota.receive(event.data());
                         }
                     }
-                    GattEvent::Write(_) => {}
                     GattEvent::Other(_) => {}
                 };
 
@@ -129,6 +464,9 @@ async fn gatt_events_task<P: PacketPool>(
                     Ok(reply) => reply.send().await,
                     Err(e) => log::warn!("[gatt] error sending response: {e:?}"),
                 };
+                if let Some(status) = ota_status {
+                    set_ota_status(server, conn, &status).await;
+                }
             }
             _ => {}
         }
@@ -137,6 +475,90 @@ async fn gatt_events_task<P: PacketPool>(
     Ok(())
 }
 
+fn event_handle<P: PacketPool>(event: &GattEvent<'_, '_, P>) -> Option<u16> {
+    match event {
+        GattEvent::Read(event) => Some(event.handle()),
+        GattEvent::Write(event) => Some(event.handle()),
+        GattEvent::Other(_) => None,
+    }
+}
+
+/// Ota transfer and logs are only available over encrypted link (paired client)
+fn requires_encryption(server: &Server<'_>, handle: Option<u16>) -> bool {
+    let Some(handle) = handle else {
+        return false;
+    };
+    [
+        server.ota_service.control.handle,
+        server.ota_service.data.handle,
+        server.ota_service.status.handle,
+        server.wifi_service.logs.handle,
+    ]
+    .contains(&handle)
+}
+
+fn encrypted<P: PacketPool>(conn: &GattConnection<'_, '_, P>) -> bool {
+    conn.raw()
+        .security_level()
+        .is_ok_and(|level| level.encrypted())
+}
+
+/// Logs characteristic value (whole records only)
+fn logs_tail(capture: &LogCapture) -> heapless::String<512> {
+    // tail is at most 512 bytes, so it always fits
+    heapless::String::try_from(capture.tail(512).as_str()).unwrap_or_default()
+}
+
+/// Fills device information characteristics that dont change
+fn set_device_info(server: &Server<'_>, info: &DeviceInfo) {
+    fn value<const N: usize>(text: &str) -> heapless::String<N> {
+        let mut value = heapless::String::new();
+        for c in text.chars() {
+            if value.push(c).is_err() {
+                break;
+            }
+        }
+        value
+    }
+
+    let service = &server.device_info;
+    _ = server.set(&service.model, &value(info.chip));
+    _ = server.set(&service.serial, &value(&info.mac));
+    _ = server.set(&service.firmware, &value(&info.firmware_version));
+    _ = server.set(&service.software, &value(info.manager_version));
+    _ = server.set(&service.runtime, &runtime_info());
+}
+
+fn runtime_info() -> heapless::String<64> {
+    let mut value = heapless::String::new();
+    _ = core::fmt::write(
+        &mut value,
+        format_args!(
+            "free_heap={}&uptime={}",
+            esp_alloc::HEAP.free(),
+            Instant::now().as_secs()
+        ),
+    );
+    value
+}
+
+/// Scan results characteristic value (`"{ssid}: {rssi}\n"` per network)
+///
+/// Only whole lines are included, networks that dont fit are dropped.
+fn scan_list(wifis: &[ScanResult]) -> heapless::String<512> {
+    let mut list = heapless::String::new();
+    for wifi in wifis {
+        let mut line = heapless::String::<64>::new();
+        if core::fmt::write(&mut line, format_args!("{}: {}\n", wifi.ssid, wifi.rssi)).is_err() {
+            continue;
+        }
+        if list.push_str(&line).is_err() {
+            break;
+        }
+    }
+    list
+}
+
 async fn advertise<'values, 'server, C: Controller>(
     name: &'values str,
     peripheral: &mut Peripheral<'values, C, DefaultPacketPool>,
@@ -168,23 +590,65 @@ async fn advertise<'values, 'server, C: Controller>(
 
 async fn custom_task<C: Controller, P: PacketPool>(
     server: &Server<'_>,
-    _conn: &GattConnection<'_, '_, P>,
+    conn: &GattConnection<'_, '_, P>,
     _stack: &Stack<'_, C, P>,
     signals: &Rc<WmInnerSignals>,
+    language: &BleLanguage,
+    ota: &BleOta<'_>,
+) {
+    set_status_message(server, conn, language.text("ble_waiting")).await;
+
+    // scan waits for completion, so it has its own loop (setup string is still handled)
+    embassy_futures::join::join3(
+        setup_task(server, conn, signals, language),
+        scan_task(server, conn, signals, language),
+        ota_task(server, conn, ota),
+    )
+    .await;
+}
+
+async fn setup_task<P: PacketPool>(
+    server: &Server<'_>,
+    conn: &GattConnection<'_, '_, P>,
+    signals: &Rc<WmInnerSignals>,
+    language: &BleLanguage,
 ) {
     let setup_string = server.wifi_service.setup_string.clone();
+    let language_char = server.wifi_service.language.clone();
+    let mut last_state = signals.conn_state.get();
     loop {
+        if let Ok(accept) = language_char.get(server) {
+            if let Some((accept, _)) = accept.split_once('\0') {
+                let selected = i18n::select(Some(accept), &language.languages, language.default);
+                log::info!("[ble] language: {}", selected.code);
+                language.current.set(selected);
+                _ = language_char.set(server, &heapless::String::new());
+            }
+        }
+
         let setup = setup_string.get(server);
         if let Ok(setup) = setup {
-            if setup.ends_with('\0') {
-                let (ssid, pwd) = parse_form_data(setup.as_str());
-                signals
-                    .wifi_conn_info_sig
-                    .signal(AutoSetupSettings { ssid, psk: pwd });
+            // values are urlencoded, so raw NUL can only be the terminator
+            if let Some((setup, _)) = setup.split_once('\0') {
+                let setup = parse_form_data(setup);
                 _ = setup_string.set(server, &heapless::String::new());
+                match setup {
+                    Ok(setup) => signals.submit_credentials(setup),
+                    Err(e) => {
+                        log::warn!("[ble] invalid setup string: {e:?}");
+                        let text = language.text("ble_invalid_setup");
+                        set_status_message(server, conn, text).await;
+                    }
+                }
             }
         }
 
+        let state = signals.conn_state.get();
+        if state != last_state {
+            last_state = state;
+            notify_conn_state(server, conn, signals, language, state).await;
+        }
+
         /*
         if let Ok(rssi) = conn.raw().rssi(stack).await {
             log::info!("[custom_task] RSSI: {:?}", rssi);
@@ -198,12 +662,165 @@ async fn custom_task<C: Controller, P: PacketPool>(
     }
 }
 
-async fn stop_ble_task(signals: &Rc<WmInnerSignals>) {
+async fn scan_task<P: PacketPool>(
+    server: &Server<'_>,
+    conn: &GattConnection<'_, '_, P>,
+    signals: &Rc<WmInnerSignals>,
+    language: &BleLanguage,
+) {
+    let scan_request = server.wifi_service.scan_request.clone();
+    set_scan_status(server, conn, "idle").await;
+
     loop {
-        let wifi_connected = signals.wifi_conn_res_sig.wait().await;
-        if wifi_connected {
-            log::debug!("Stopping ble task!");
-            return;
+        let request = scan_request.get(server);
+        if let Ok(request) = request {
+            if let Some((request, _)) = request.split_once('\0') {
+                let request = String::from(request);
+                _ = scan_request.set(server, &heapless::String::new());
+
+                match parse_scan_form(&request) {
+                    Some(scan) => {
+                        set_scan_status(server, conn, "scanning").await;
+                        set_status_message(server, conn, language.text("ble_scanning")).await;
+                        let completed = signals.scan(scan).await;
+                        let (status, message) = if completed {
+                            ("done", "ble_scan_done")
+                        } else {
+                            ("aborted", "ble_scan_aborted")
+                        };
+                        set_scan_status(server, conn, status).await;
+                        set_status_message(server, conn, language.text(message)).await;
+                    }
+                    None => {
+                        log::warn!("[ble] invalid scan request: {request}");
+                        set_scan_status(server, conn, "invalid").await;
+                        set_status_message(server, conn, language.text("ble_invalid_scan")).await;
+                    }
+                }
+            }
         }
+
+        Timer::after_millis(250).await;
     }
 }
+
+async fn ota_task<P: PacketPool>(
+    server: &Server<'_>,
+    conn: &GattConnection<'_, '_, P>,
+    ota: &BleOta<'_>,
+) {
+    let control = server.ota_service.control.clone();
+    // reconnected client continues from received offset
+    set_ota_status(server, conn, &ota.status()).await;
+
+    loop {
+        ota.pause_if_idle();
+        if let Ok(command) = control.get(server) {
+            if let Some((command, _)) = command.split_once('\0') {
+                let command = String::from(command);
+                _ = control.set(server, &heapless::String::new());
+
+                // writes are rejected before pairing, this only guards against stale value
+                let status = if encrypted(conn) {
+                    ota.command(&command)
+                } else {
+                    String::from("error insufficient_authentication")
+                };
+                log::info!("[ble] ota {command}: {status}");
+                set_ota_status(server, conn, &status).await;
+                if status == "restarting" {
+                    Timer::after_millis(1000).await;
+                    esp_hal::system::software_reset();
+                }
+            }
+        }
+
+        Timer::after_millis(250).await;
+    }
+}
+
+async fn set_ota_status<P: PacketPool>(
+    server: &Server<'_>,
+    conn: &GattConnection<'_, '_, P>,
+    status: &str,
+) {
+    let status = heapless::String::try_from(status).unwrap_or_default();
+    if let Err(e) = server.ota_service.status.notify(conn, &status).await {
+        log::warn!("[ble] ota status notify error: {e:?}");
+    }
+}
+
+async fn set_scan_status<P: PacketPool>(
+    server: &Server<'_>,
+    conn: &GattConnection<'_, '_, P>,
+    status: &str,
+) {
+    let status = heapless::String::try_from(status).unwrap_or_default();
+    if let Err(e) = server.wifi_service.scan_status.notify(conn, &status).await {
+        log::warn!("[ble] scan status notify error: {e:?}");
+    }
+}
+
+/// Notifies provisioning progress, final result marks it as fetched
+async fn notify_conn_state<P: PacketPool>(
+    server: &Server<'_>,
+    conn: &GattConnection<'_, '_, P>,
+    signals: &Rc<WmInnerSignals>,
+    language: &BleLanguage,
+    state: ConnState,
+) {
+    let mut text = String::from(match state {
+        ConnState::Idle => return,
+        ConnState::Pending => language.text("status_pending"),
+        ConnState::Connecting => language.text("status_connecting"),
+        ConnState::ObtainingIp => language.text("status_obtaining_ip"),
+        ConnState::Connected(_) => language.text("status_connected"),
+        ConnState::Failed(_) => language.text("status_failed"),
+    });
+    match state {
+        ConnState::Connected(ip) => {
+            _ = core::fmt::write(
+                &mut text,
+                format_args!(" {}: {}", language.text("label_ip"), Ipv4Addr::from(ip)),
+            );
+        }
+        ConnState::Failed(reason) => {
+            text.push_str(": ");
+            text.push_str(language.text(reason.text_key()));
+        }
+        _ => {}
+    }
+
+    set_status_message(server, conn, &text).await;
+    if let ConnState::Connected(_) = state {
+        signals.result_fetched.signal(());
+    }
+}
+
+/// Notifies status message (cut at char boundary if too long)
+async fn set_status_message<P: PacketPool>(
+    server: &Server<'_>,
+    conn: &GattConnection<'_, '_, P>,
+    text: &str,
+) {
+    let mut message = heapless::String::<128>::new();
+    for c in text.chars() {
+        if message.push(c).is_err() {
+            break;
+        }
+    }
+    if let Err(e) = server
+        .wifi_service
+        .status_message
+        .notify(conn, &message)
+        .await
+    {
+        log::warn!("[ble] status message notify error: {e:?}");
+    }
+}
+
+/// Waits until provisioning ends (after grace period, so client gets the result)
+async fn stop_ble_task(signals: &Rc<WmInnerSignals>) {
+    signals.end_signalled().await;
+    log::debug!("Stopping ble task!");
+}
@@ -0,0 +1,81 @@
+/// What should be sent back for OS connectivity probe
+#[derive(Debug, PartialEq)]
+pub enum ProbeResponse {
+    /// Redirect to the portal (opens captive sheet)
+    Portal,
+
+    /// `204 No Content` (Android "online" answer)
+    NoContent,
+
+    /// `200 OK` with given body (iOS / Windows / Firefox "online" answer)
+    Online {
+        content_type: &'static str,
+        body: &'static str,
+    },
+}
+
+const APPLE_SUCCESS: &str = "<HTML><HEAD><TITLE>Success</TITLE></HEAD><BODY>Success</BODY></HTML>";
+
+/// Checks if path is connectivity probe of some OS
+///
+/// Until provisioning succeeds every probe is redirected to the portal,
+/// after that probes get the answer OS expects when it is online,
+/// so captive sheet closes itself.
+pub fn probe_response(path: &str, provisioned: bool) -> Option<ProbeResponse> {
+    let online = match path {
+        // Android / ChromeOS
+        "/generate_204" | "/gen_204" => ProbeResponse::NoContent,
+
+        // iOS / macOS
+        "/hotspot-detect.html" | "/library/test/success.html" => ProbeResponse::Online {
+            content_type: "text/html",
+            body: APPLE_SUCCESS,
+        },
+
+        // Windows
+        "/connecttest.txt" => ProbeResponse::Online {
+            content_type: "text/plain",
+            body: "Microsoft Connect Test",
+        },
+        "/ncsi.txt" => ProbeResponse::Online {
+            content_type: "text/plain",
+            body: "Microsoft NCSI",
+        },
+        "/redirect" => ProbeResponse::Portal,
+
+        // Firefox
+        "/success.txt" => ProbeResponse::Online {
+            content_type: "text/plain",
+            body: "success\n",
+        },
+        "/canonical.html" => ProbeResponse::Online {
+            content_type: "text/html",
+            body: "<meta http-equiv=\"refresh\" content=\"0;url=https://support.mozilla.org/kb/captive-portal\"/>",
+        },
+
+        _ => return None,
+    };
+
+    if provisioned {
+        Some(online)
+    } else {
+        Some(ProbeResponse::Portal)
+    }
+}
+
+/// RFC 8908 captive portal API path
+pub const CAPTIVE_API_PATH: &str = "/captive-portal/api";
+
+/// RFC 8908 captive portal API content type
+pub const CAPTIVE_API_CONTENT_TYPE: &str = "application/captive+json";
+
+/// RFC 8908 captive portal API response body
+///
+/// Dhcp server advertises this API to clients with option 114 (RFC 8910).
+pub fn captive_api_body(gw_ip: &str, provisioned: bool) -> alloc::string::String {
+    alloc::format!(
+        "{{\"captive\":{},\"user-portal-url\":\"http://{}/\"}}",
+        !provisioned,
+        gw_ip
+    )
+}
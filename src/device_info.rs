@@ -0,0 +1,84 @@
+use alloc::string::String;
+use embassy_time::{Duration, Instant};
+use esp_bootloader_esp_idf::EspAppDesc;
+
+use crate::json::JsonValue;
+use crate::utils::mac_str;
+
+#[cfg(feature = "esp32")]
+const CHIP_MODEL: &str = "esp32";
+#[cfg(feature = "esp32s2")]
+const CHIP_MODEL: &str = "esp32s2";
+#[cfg(feature = "esp32s3")]
+const CHIP_MODEL: &str = "esp32s3";
+#[cfg(feature = "esp32c2")]
+const CHIP_MODEL: &str = "esp32c2";
+#[cfg(feature = "esp32c3")]
+const CHIP_MODEL: &str = "esp32c3";
+#[cfg(feature = "esp32c6")]
+const CHIP_MODEL: &str = "esp32c6";
+
+extern "Rust" {
+    /// Defined by `esp_app_desc!()` in application
+    #[link_name = "esp_app_desc"]
+    static ESP_APP_DESC: EspAppDesc;
+}
+
+/// Firmware version from app descriptor (`esp_app_desc!()`, package version by default),
+/// `fallback` (`WmSettings::firmware_version`) when descriptor has no version
+pub(crate) fn firmware_version(fallback: &str) -> &str {
+    // written once by the macro, never changes
+    let version = unsafe { ESP_APP_DESC.version() };
+    if version.is_empty() {
+        fallback
+    } else {
+        version
+    }
+}
+
+/// Device details for support (`/api/v1/device`, BLE device information service)
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    /// `AA:BB:CC:DD:EE:FF` (`get_efuse_mac`)
+    pub mac: String,
+
+    /// Chip model (`esp32c3`...)
+    pub chip: &'static str,
+
+    /// Version from app descriptor (`fallback` when it has none)
+    pub firmware_version: String,
+
+    /// Free bytes in `esp-alloc` heap
+    pub free_heap: usize,
+
+    /// Time since boot
+    pub uptime: Duration,
+
+    /// Version of this crate
+    pub manager_version: &'static str,
+}
+
+impl DeviceInfo {
+    /// `fallback` is used when app descriptor has no version (`WmSettings::firmware_version`)
+    pub fn read(fallback: &str) -> Self {
+        Self {
+            mac: mac_str(&crate::get_efuse_mac().to_be_bytes()[2..]),
+            chip: CHIP_MODEL,
+            firmware_version: String::from(firmware_version(fallback)),
+            free_heap: esp_alloc::HEAP.free(),
+            uptime: Duration::from_ticks(Instant::now().as_ticks()),
+            manager_version: env!("CARGO_PKG_VERSION"),
+        }
+    }
+
+    /// `{"mac":"..","chip":"esp32c3","firmware_version":"1.0.0","manager_version":"0.3.2","free_heap":81920,"uptime_s":42}`
+    pub(crate) fn to_json(&self) -> JsonValue {
+        JsonValue::object()
+            .with("mac", self.mac.as_str())
+            .with("chip", self.chip)
+            .with("firmware_version", self.firmware_version.as_str())
+            .with("manager_version", self.manager_version)
+            .with("free_heap", self.free_heap)
+            .with("uptime_s", self.uptime.as_secs())
+    }
+}
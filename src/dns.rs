@@ -0,0 +1,57 @@
+use alloc::rc::Rc;
+use core::net::Ipv4Addr;
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    Stack,
+};
+
+use esp_wifi_manager_core::dns::build_dns_response;
+
+use crate::structs::WmInnerSignals;
+
+const DNS_PORT: u16 = 53;
+
+/// Captive portal dns server, answers every A query with gateway ip
+#[embassy_executor::task]
+pub async fn run_dns_server(ap_stack: Stack<'static>, ip: Ipv4Addr, signals: Rc<WmInnerSignals>) {
+    let _running = signals.ap_task_running();
+    embassy_futures::select::select(serve(ap_stack, ip), signals.end_signalled()).await;
+    log::info!("dns server stopped");
+}
+
+async fn serve(ap_stack: Stack<'static>, ip: Ipv4Addr) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0u8; 512];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0u8; 512];
+    let mut socket = UdpSocket::new(
+        ap_stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    if let Err(e) = socket.bind(DNS_PORT) {
+        log::error!("dns server bind failed! ({e:?})");
+        return;
+    }
+
+    let mut query = [0u8; 512];
+    let mut response = [0u8; 512];
+    loop {
+        let (len, meta) = match socket.recv_from(&mut query).await {
+            Ok(res) => res,
+            Err(e) => {
+                log::warn!("dns recv error: {e:?}");
+                continue;
+            }
+        };
+
+        let Some(res_len) = build_dns_response(&query[..len], ip, &mut response) else {
+            continue;
+        };
+        if let Err(e) = socket.send_to(&response[..res_len], meta).await {
+            log::warn!("dns send error: {e:?}");
+        }
+    }
+}
@@ -1,34 +1,54 @@
-use crate::{http_server::start_http_server, structs::WmInnerSignals};
+use crate::{
+    assets::AssetVars,
+    auth::Authenticator,
+    http_server::{start_http_server, HttpServerConfig},
+    structs::{Result, WmInnerSignals},
+    ws::WS_MAX_CLIENTS,
+    WmSettings,
+};
 use alloc::rc::Rc;
 use embassy_executor::Spawner;
 use embassy_net::Stack;
 
-// #[embassy_executor::task(pool_size = WEB_TASK_POOL_SIZE)]
-// async fn web_task(stack: embassy_net::Stack<'static>, signals: Rc<WmInnerSignals>) {
-//     let gw_ip_addr_str = "192.168.4.1";
-//     start_http_server(stack, signals, gw_ip_addr_str).await;
-// }
-
 pub async fn run_http_server(
     spawner: &Spawner,
     ap_stack: Stack<'static>,
     signals: Rc<WmInnerSignals>,
-) {
-    // loop {
-    //     if ap_stack.is_link_up() {
-    //         log::info!("AP link up");
-    //         break;
-    //     }
-    //     Timer::after(Duration::from_millis(500)).await;
-    //     log::info!("AP link not up");
-    // }
-    // // let port = 80;
-
-    // while !ap_stack.is_config_up() {
-    //     Timer::after(Duration::from_millis(100)).await
-    // }
+    settings: &WmSettings,
+) -> Result<()> {
     ap_stack
         .config_v4()
         .inspect(|c| log::info!("ipv4 config: {c:?}"));
-    spawner.must_spawn(start_http_server(ap_stack, signals));
+
+    let config = HttpServerConfig {
+        timeout: settings.http_timeout,
+        keep_alive: settings.http_keep_alive,
+        limits: settings.http_limits,
+    };
+    // websocket clients keep their worker busy, one is left for requests
+    signals
+        .ws_slots
+        .set(WS_MAX_CLIENTS.min(settings.http_workers.saturating_sub(1)));
+    let vars = AssetVars::new(settings);
+    let auth = Rc::new(Authenticator::new(
+        settings.http_auth.clone(),
+        settings.auth_lockout,
+        &settings.ssid,
+    ));
+    for worker_id in 0..settings.http_workers {
+        spawner.spawn(start_http_server(
+            worker_id,
+            ap_stack,
+            settings.gw_ip_addr,
+            signals.clone(),
+            config,
+            settings.http_router.clone(),
+            settings.assets,
+            vars.clone(),
+            auth.clone(),
+            settings.ota.clone(),
+            settings.log_capture,
+        ))?;
+    }
+    Ok(())
 }
@@ -1,100 +1,192 @@
-use core::str;
-
+use core::net::Ipv4Addr;
 use embassy_net::tcp::TcpSocket;
 use embassy_net::IpListenEndpoint;
-use embassy_time::{Duration, Timer};
+use embassy_time::{with_timeout, Duration, Timer};
 use embedded_io_async::Write;
 extern crate alloc;
-use alloc::vec::Vec;
-use alloc::{rc::Rc, string::String};
-use esp_println::{print, println};
+use alloc::{boxed::Box, rc::Rc, string::String, vec, vec::Vec};
 
-use crate::structs::{AutoSetupSettings, WmInnerSignals};
+use crate::api;
+use crate::assets::{self, AssetBody, AssetVars, StaticAsset};
+use crate::auth::Authenticator;
+use crate::captive::{self, ProbeResponse};
+use crate::form::{self, FormError};
+use crate::http_request::{status_text, HttpError, HttpLimits, HttpMethod, HttpRequest};
+use crate::i18n::Language;
+use crate::json::JsonValue;
+use crate::log_capture::{self, LogCapture};
+use crate::ota::{self, Ota};
+use crate::router::{HandlerFuture, HttpHandler, HttpRouter};
+use crate::structs::{AutoSetupSettings, ScanResult, ScanSettings, WmInnerSignals};
 
-#[derive(Debug, PartialEq)]
-pub enum HttpMethod {
-    Get,
-    Post,
-}
+/// Max number of http worker tasks (`WmSettings::http_workers`)
+pub const HTTP_MAX_WORKERS: usize = 4;
+
+#[derive(Clone, Copy, Debug)]
+pub struct HttpServerConfig {
+    /// Socket inactivity timeout
+    pub timeout: Duration,
 
-pub struct HttpRequest {
-    pub method: HttpMethod,
-    pub path: String,
-    pub body: String,
-    pub headers: Vec<(String, String)>,
+    /// Idle time after which keep-alive connection is closed (`None` - close after every response)
+    pub keep_alive: Option<Duration>,
+
+    pub limits: HttpLimits,
 }
 
-impl HttpRequest {
-    pub fn new() -> Self {
+impl Default for HttpServerConfig {
+    fn default() -> Self {
         Self {
-            method: HttpMethod::Get,
-            path: String::new(),
-            body: String::new(),
-            headers: Vec::new(),
+            timeout: Duration::from_secs(10),
+            keep_alive: Some(Duration::from_secs(5)),
+            limits: HttpLimits::default(),
         }
     }
+}
 
-    pub fn parse(data: &[u8]) -> Option<Self> {
-        let data_str = str::from_utf8(data).ok()?;
-        let mut request = Self::new();
-        log::info!("parse: size:={:?} ,data={:?}", data.len(), data_str);
-
-        if let Some((request_line, rest)) = data_str.split_once("\r\n") {
-            let parts: Vec<&str> = request_line.split_whitespace().collect();
-            if parts.len() >= 2 {
-                request.method = match parts[0] {
-                    "GET" => HttpMethod::Get,
-                    "POST" => HttpMethod::Post,
-                    _ => return None,
-                };
-                // log::info!("parse request line:{:?} {:?}", request.method, parts[1]);
-                request.path = String::try_from(parts[1]).ok()?;
-            }
+/// Http worker, every worker owns one socket listening on the same port
+#[embassy_executor::task(pool_size = HTTP_MAX_WORKERS)]
+#[allow(clippy::too_many_arguments)]
+pub async fn start_http_server(
+    worker_id: usize,
+    stack: embassy_net::Stack<'static>,
+    gw_ip_addr: Ipv4Addr,
+    states: Rc<WmInnerSignals>,
+    config: HttpServerConfig,
+    router: HttpRouter,
+    assets: &'static [StaticAsset],
+    vars: AssetVars,
+    auth: Rc<Authenticator>,
+    ota: Option<Rc<Ota>>,
+    log_capture: Option<&'static LogCapture>,
+) {
+    let _running = states.ap_task_running();
+    embassy_futures::select::select(
+        serve(
+            worker_id,
+            stack,
+            gw_ip_addr,
+            &states,
+            config,
+            &router,
+            assets,
+            &vars,
+            &auth,
+            ota.as_deref(),
+            log_capture,
+        ),
+        states.end_signalled(),
+    )
+    .await;
+    log::info!("http worker {worker_id} stopped");
+}
 
-            if let Some((header_str, body)) = rest.split_once("\r\n\r\n") {
-                let header_iter = header_str.split("\r\n");
-                for line in header_iter {
-                    if let Some((key, value)) = line.split_once(": ") {
-                        // log::info!("parse header: {:?} = {:?}", key, value);
-                        request
-                            .headers
-                            .push((String::try_from(key).ok()?, String::try_from(value).ok()?));
-                    }
+/// Provisioning pages, application routes are checked first
+struct ProvisioningSite<'s> {
+    states: &'s Rc<WmInnerSignals>,
+    gw_ip_addr: Ipv4Addr,
+    router: &'s HttpRouter,
+    assets: &'static [StaticAsset],
+    vars: &'s AssetVars,
+    auth: &'s Authenticator,
+    ota: Option<&'s Ota>,
+    log_capture: Option<&'static LogCapture>,
+}
+
+impl HttpHandler for ProvisioningSite<'_> {
+    fn handle<'a>(
+        &'a self,
+        request: &'a HttpRequest,
+        writer: &'a mut ResponseWriter<'_, '_>,
+    ) -> HandlerFuture<'a> {
+        Box::pin(async move {
+            // application routes are protected too
+            if !self.auth.authorize(request, writer).await {
+                return;
+            }
+            if self.router.dispatch(request, writer).await {
+                return;
+            }
+            if let Some(ota) = self.ota {
+                if ota::handle_api(ota, self.auth.enabled(), request, writer).await {
+                    return;
                 }
-                // log::info!("parse header str: {:?}", header_str);
-                // log::info!("parse body: {:?}", body);
-                if body.is_empty() {
-                    request.body = String::new();
-                } else {
-                    request.body = String::try_from(body).ok()?;
+            }
+            if let Some(capture) = self.log_capture {
+                if log_capture::handle_api(capture, self.auth, request, writer).await {
+                    return;
                 }
             }
-        }
+            handle_request(request, writer, self).await;
+        })
+    }
 
-        Some(request)
+    fn streams_body(&self, request: &HttpRequest) -> bool {
+        self.ota.is_some() && ota::is_upload(request)
     }
 }
-#[embassy_executor::task]
-pub async fn start_http_server(
+
+#[allow(clippy::too_many_arguments)]
+async fn serve(
+    worker_id: usize,
     stack: embassy_net::Stack<'static>,
-    states: Rc<WmInnerSignals>,
+    gw_ip_addr: Ipv4Addr,
+    states: &Rc<WmInnerSignals>,
+    config: HttpServerConfig,
+    router: &HttpRouter,
+    assets: &'static [StaticAsset],
+    vars: &AssetVars,
+    auth: &Authenticator,
+    ota: Option<&Ota>,
+    log_capture: Option<&'static LogCapture>,
 ) -> ! {
-    let mut rx_buffer = [0u8; 1536];
-    let mut tx_buffer = [0u8; 1536];
     loop {
         if stack.is_link_up() {
             log::info!("AP link up");
             break;
-        } 
+        }
         Timer::after(Duration::from_millis(500)).await;
         log::info!("AP link not up");
     }
     let port = 80;
-    let gw_ip_addr_str = states.gw_ip_addr_str.lock().await;
-    log::info!(
-        "Connect to the AP and point your browser to http://{gw_ip_addr_str}:{port}/",
-        gw_ip_addr_str = gw_ip_addr_str.as_str()
-    );
+    if worker_id == 0 {
+        log::info!("Connect to the AP and point your browser to http://{gw_ip_addr}:{port}/");
+    }
+
+    let site = ProvisioningSite {
+        states,
+        gw_ip_addr,
+        router,
+        assets,
+        vars,
+        auth,
+        ota,
+        log_capture,
+    };
+    serve_connections(worker_id, stack, port, config, &site).await
+}
+
+/// Serves `router` on given stack and port (for example on `WmReturn::sta_stack`)
+///
+/// Handles one connection at a time, spawn more tasks running it
+/// to serve connections in parallel. Unknown paths get `404 Not Found`.
+pub async fn serve_http(
+    stack: embassy_net::Stack<'static>,
+    port: u16,
+    router: &HttpRouter,
+    config: HttpServerConfig,
+) -> ! {
+    serve_connections(0, stack, port, config, router).await
+}
+
+pub(crate) async fn serve_connections(
+    worker_id: usize,
+    stack: embassy_net::Stack<'static>,
+    port: u16,
+    config: HttpServerConfig,
+    handler: &dyn HttpHandler,
+) -> ! {
+    let mut rx_buffer = [0u8; 1536];
+    let mut tx_buffer = [0u8; 1536];
     while !stack.is_config_up() {
         Timer::after(Duration::from_millis(100)).await
     }
@@ -103,12 +195,12 @@ pub async fn start_http_server(
         .inspect(|c| log::info!("ipv4 config: {c:?}"));
 
     let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
-    socket.set_timeout(Some(embassy_time::Duration::from_secs(10)));
     // let socket = mk_static!(TcpSocket<'static>, socket);
     loop {
-        log::info!("Wait for connection...");
+        socket.set_timeout(Some(config.timeout));
+        log::info!("[{worker_id}] Wait for connection...");
         let r = socket.accept(IpListenEndpoint { addr: None, port }).await;
-        log::info!("Connected...");
+        log::info!("[{worker_id}] Connected...");
 
         if let Err(e) = r {
             log::info!("connect error: {:?}", e);
@@ -116,167 +208,486 @@ pub async fn start_http_server(
             continue;
         }
 
-        let mut buffer = [0u8; 1536];
-        let mut pos = 0;
-
+        let mut buffer = vec![0u8; config.limits.max_header_len];
+        let mut filled = 0;
+        let mut first_request = true;
         loop {
-            match socket.read(&mut buffer[pos..]).await {
-                Ok(0) => {
-                    log::info!("read EOF");
+            // waiting for next request on keep-alive connection
+            let idle_timeout = match (first_request, config.keep_alive) {
+                (true, _) => config.timeout,
+                (false, Some(keep_alive)) => keep_alive,
+                (false, None) => break,
+            };
+            first_request = false;
+
+            let read = read_request(
+                &mut socket,
+                &mut buffer,
+                &mut filled,
+                &config.limits,
+                handler,
+            );
+            let (request, body) = match with_timeout(idle_timeout, read).await {
+                Ok(Ok(Some(request))) => request,
+                Ok(Ok(None)) => break,
+                Ok(Err(e)) => {
+                    log::warn!("[{worker_id}] bad request: {e:?}");
+                    let mut writer = ResponseWriter::new(&mut socket, false, StreamedBody::None);
+                    writer.write_error(e).await;
                     break;
                 }
-                Ok(len) => {
-                    let to_print =
-                        unsafe { core::str::from_utf8_unchecked(&buffer[..(pos + len)]) };
-                    pos += len;
-                    if to_print.contains("\r\n\r\n") {
-                        print!("reciv: {}", to_print);
-                        println!();
-                        break;
-                    }
-                }
-                Err(e) => {
-                    log::error!("read error: {:?}", e);
+                Err(_) => {
+                    log::info!("[{worker_id}] idle timeout");
                     break;
                 }
             };
-        }
-        let size = pos;
-
-        if let Some(request) = HttpRequest::parse(&buffer[..size]) {
-            log::info!("{:?} {:?}", request.method, request.path);
-            if request.method == HttpMethod::Get
-                && (request.path.as_str() == "/" || request.path.as_str() == "")
-            {
-                write_html_200(&mut socket, IDX_HTML_DATA).await;
-            } else if request.method == HttpMethod::Get && request.path.as_str() == "/list" {
-                let resp_res = states.wifi_scan_res.try_lock();
-                let resp = match resp_res {
-                    Ok(ref resp) => alloc::string::ToString::to_string(resp),
-                    Err(_) => String::new(),
-                };
-                write_text_200(&mut socket, resp.as_bytes()).await;
-                log::info!("resp: {:?}", resp);
-            } else if request.method == HttpMethod::Post && request.path.as_str() == "/setup" {
-                let (ssid, pwd) = parse_form_data(request.body.as_str());
-                log::info!("{}/{}", ssid, pwd);
-                states
-                    .wifi_conn_info_sig
-                    .signal(AutoSetupSettings { ssid, psk: pwd });
-                write_html_200(&mut socket, SUCCESS_HTML.as_bytes()).await;
-            } else if request.method == HttpMethod::Get && request.path.as_str() == "/favicon.ico" {
-            } else if request.method == HttpMethod::Get && request.path.as_str() == "/done.html" {
-                write_html_200(&mut socket, SUCCESS_HTML.as_bytes()).await;
-            } else {
-                write_302(&mut socket).await;
+
+            // HTTP/1.1 is keep-alive by default
+            let keep_alive = config.keep_alive.is_some() && request.keep_alive();
+            let mut writer = ResponseWriter::new(&mut socket, keep_alive, body);
+            handler.handle(&request, &mut writer).await;
+
+            // unread part of streamed body would be parsed as next request
+            if !writer.keep_alive() {
+                break;
             }
-        } else {
-            log::info!("unknown request");
         }
 
         socket.close();
-        Timer::after(Duration::from_millis(200)).await;
+        _ = socket.flush().await;
         socket.abort();
     }
 }
 
-pub fn generate_res_header(status: u16, content_len: usize, content_type: &str) -> String {
-    let response = alloc::format!(
-        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
-        status,
-        content_type,
-        content_len,
-    );
+/// Body of request read by handler (`HttpHandler::streams_body`)
+enum StreamedBody {
+    None,
 
-    response
+    /// Part received together with headers and length of the rest
+    Pending {
+        received: Vec<u8>,
+        remaining: usize,
+    },
 }
 
-pub fn generate_response(status: u16, content: &str, content_type: &str) -> String {
-    let response = alloc::format!(
-        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+/// Reads one request (headers and `Content-Length` bytes of body)
+///
+/// `buffer[..filled]` holds bytes received from connection but not parsed yet,
+/// bytes of next (pipelined) request are left there. Returns `Ok(None)` on EOF.
+/// Body of streamed requests is left for the handler.
+async fn read_request(
+    socket: &mut TcpSocket<'_>,
+    buffer: &mut [u8],
+    filled: &mut usize,
+    limits: &HttpLimits,
+    handler: &dyn HttpHandler,
+) -> Result<Option<(HttpRequest, StreamedBody)>, HttpError> {
+    let (mut request, head_len) = loop {
+        if let Some(res) = HttpRequest::parse_head(&buffer[..*filled], limits)? {
+            break res;
+        }
+
+        match socket.read(&mut buffer[*filled..]).await {
+            Ok(0) => {
+                log::info!("read EOF");
+                return Ok(None);
+            }
+            Ok(len) => *filled += len,
+            Err(e) => {
+                log::error!("read error: {:?}", e);
+                return Ok(None);
+            }
+        }
+    };
+
+    request.remote = socket
+        .remote_endpoint()
+        .map(|endpoint| endpoint.addr.into());
+
+    let content_length = request.content_length();
+    let streamed = handler.streams_body(&request);
+    if content_length > limits.max_body_len && !streamed {
+        return Err(HttpError::PayloadTooLarge);
+    }
+    if content_length > 0
+        && request
+            .header("Expect")
+            .is_some_and(|e| e.eq_ignore_ascii_case("100-continue"))
+    {
+        _ = socket.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await;
+    }
+
+    // part of the body could arrive together with headers
+    let available = (*filled - head_len).min(content_length);
+    let received = buffer[head_len..head_len + available].to_vec();
+    buffer.copy_within(head_len + available..*filled, 0);
+    *filled -= head_len + available;
+    if streamed {
+        let body = StreamedBody::Pending {
+            received,
+            remaining: content_length - available,
+        };
+        return Ok(Some((request, body)));
+    }
+    request.body = received;
+
+    while request.body.len() < content_length {
+        let start = request.body.len();
+        request.body.resize(content_length, 0);
+        match socket.read(&mut request.body[start..]).await {
+            Ok(0) => return Err(HttpError::BadRequest),
+            Ok(len) => request.body.truncate(start + len),
+            Err(e) => {
+                log::error!("read error: {:?}", e);
+                return Ok(None);
+            }
+        }
+    }
+
+    Ok(Some((request, StreamedBody::None)))
+}
+
+/// Paths served by provisioning server (others get 404 or redirect to portal)
+const KNOWN_PATHS: &[&str] = &["/", "/list", "/setup", "/favicon.ico", "/done.html"];
+
+async fn handle_request(
+    request: &HttpRequest,
+    writer: &mut ResponseWriter<'_, '_>,
+    site: &ProvisioningSite<'_>,
+) {
+    let states = site.states;
+    // host of portal urls (redirects, captive portal api)
+    let gw_ip_addr_str = &alloc::format!("{}", site.gw_ip_addr);
+    log::info!("{:?} {:?}", request.method, request.path);
+    let provisioned = states.provisioned.get();
+    let asset = match request.method {
+        HttpMethod::Get => assets::find(site.assets, &request.path),
+        _ => None,
+    };
+    let language = site.vars.language(request.header("Accept-Language"));
+    let wants_captive_api = request
+        .header("Accept")
+        .is_some_and(|accept| accept.contains(captive::CAPTIVE_API_CONTENT_TYPE));
+
+    if let Some(probe) = captive::probe_response(request.path.as_str(), provisioned) {
+        match probe {
+            ProbeResponse::Portal => writer.write_302(gw_ip_addr_str).await,
+            ProbeResponse::NoContent => writer.write_response(204, "text/plain", &[]).await,
+            ProbeResponse::Online { content_type, body } => {
+                writer
+                    .write_response(200, content_type, body.as_bytes())
+                    .await
+            }
+        }
+    } else if request.method == HttpMethod::Get
+        && (request.path.as_str() == captive::CAPTIVE_API_PATH || wants_captive_api)
+    {
+        let body = captive::captive_api_body(gw_ip_addr_str, provisioned);
+        writer
+            .write_response(200, captive::CAPTIVE_API_CONTENT_TYPE, body.as_bytes())
+            .await;
+    } else if request.path.starts_with(api::API_PREFIX) {
+        api::handle_api(request, writer, states, site.gw_ip_addr, site.vars).await;
+    } else if let Some(asset) = asset {
+        writer.write_asset(asset, site.vars, language).await;
+    } else if request.method == HttpMethod::Get && request.path.as_str() == "/list" {
+        // `/list?refresh` (or with scan parameters) waits for fresh scan
+        if !request.query.is_empty() {
+            let Some(scan) = parse_scan_form(&request.query) else {
+                writer.write_error(HttpError::BadRequest).await;
+                return;
+            };
+            states.scan(scan).await;
+        }
+        let resp = match states.wifi_scan_res.try_lock() {
+            Ok(wifis) => scan_list(&wifis),
+            Err(_) => String::new(),
+        };
+        writer.write_text_200(resp.as_bytes()).await;
+    } else if request.method == HttpMethod::Post && request.path.as_str() == "/setup" {
+        let Some(Ok(setup)) = request.body_str().map(parse_form_data) else {
+            writer.write_error(HttpError::BadRequest).await;
+            return;
+        };
+        log::info!("[http] setup: {}", setup.ssid);
+        states.submit_credentials(setup);
+        // `/done.html` is always in built-in table
+        if let Some(done) = assets::find(site.assets, "/done.html") {
+            writer.write_asset(done, site.vars, language).await;
+        }
+    } else if KNOWN_PATHS.contains(&request.path.as_str()) {
+        writer.write_error(HttpError::MethodNotAllowed).await;
+    } else if request
+        .header("Host")
+        .is_some_and(|host| host.split(':').next() == Some(gw_ip_addr_str.as_str()))
+    {
+        writer.write_error(HttpError::NotFound).await;
+    } else {
+        // foreign host - captive portal redirect
+        writer.write_302(gw_ip_addr_str).await;
+    }
+}
+
+pub fn generate_res_header(
+    status: u16,
+    content_len: usize,
+    content_type: &str,
+    keep_alive: bool,
+    extra_headers: &[(&str, &str)],
+) -> String {
+    let mut response = alloc::format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: {}\r\n",
         status,
+        status_text(status),
         content_type,
-        content.len(),
-        content
+        content_len,
+        if keep_alive { "keep-alive" } else { "close" },
     );
+    for (key, value) in extra_headers {
+        _ = core::fmt::write(&mut response, format_args!("{key}: {value}\r\n"));
+    }
+    response.push_str("\r\n");
 
     response
 }
 
-pub fn parse_form_data(data: &str) -> (String, String) {
-    let mut ssid = String::new();
-    let mut pwd = String::new();
-    for pair in data.split('&') {
-        if let Some((key, value)) = pair.split_once('=') {
-            if key == "ssid" {
-                ssid.push_str(value);
-            }
-            if key == "psk" {
-                pwd.push_str(value);
-            }
+/// Parses urlencoded setup form (`ssid`, `psk` and optional `country`)
+///
+/// Validated like json setup, empty `country` is ignored.
+pub fn parse_form_data(data: &str) -> Result<AutoSetupSettings, FormError> {
+    let (mut ssid, mut psk, mut country) = (String::new(), String::new(), None);
+    for (key, value) in form::parse(data)? {
+        match key.as_str() {
+            "ssid" => ssid = value,
+            "psk" => psk = value,
+            "country" if !value.is_empty() => country = Some(value),
+            _ => {}
         }
     }
-    (ssid, pwd)
+    AutoSetupSettings::new(ssid, psk, country.as_deref()).map_err(FormError::InvalidSetup)
 }
 
-pub async fn write_302<'d>(socket: &mut embassy_net::tcp::TcpSocket<'d>) {
-    let full_response = alloc::format!(
-        "HTTP/1.1 302 Found\r\nLocation: /\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
-    );
-    let redirect_response = generate_response(302, full_response.as_str(), "text/html");
+/// Parses urlencoded scan request (`passive=1&dwell_ms=120&channels=1,6,11&show_hidden=1`)
+///
+/// Missing keys keep defaults, unknown keys are ignored. Returns `None` for invalid values.
+pub fn parse_scan_form(data: &str) -> Option<ScanSettings> {
+    fn flag(value: &str) -> Option<bool> {
+        match value {
+            "" | "1" | "true" => Some(true),
+            "0" | "false" => Some(false),
+            _ => None,
+        }
+    }
 
-    let r = socket.write_all(redirect_response.as_bytes()).await;
-    if let Err(e) = r {
-        log::error!("write chunk error: {:?}", e);
+    let mut scan = ScanSettings::default();
+    for (key, value) in form::parse(data).ok()? {
+        match key.as_str() {
+            "passive" => scan.passive = flag(&value)?,
+            "show_hidden" => scan.show_hidden = flag(&value)?,
+            "dwell_ms" => scan.dwell_time = Some(Duration::from_millis(value.parse().ok()?)),
+            "channels" => {
+                let mut channels = alloc::vec::Vec::new();
+                for channel in value.split(',') {
+                    channels.push(channel.trim().parse().ok()?);
+                }
+                scan = scan.with_channels(&channels);
+            }
+            _ => {}
+        }
     }
+
+    scan.validate().ok()?;
+    Some(scan)
 }
-pub const IDX_HTML_DATA: &[u8] = include_bytes!("panel.html");
 
-pub async fn write_html_200<'d>(socket: &mut embassy_net::tcp::TcpSocket<'d>, content: &[u8]) {
-    write_response(socket, 200, "text/html", content).await;
+/// `/list` format used by portal (`"{ssid}: {rssi}\n"` per network)
+fn scan_list(wifis: &[ScanResult]) -> String {
+    let mut list = String::new();
+    for wifi in wifis {
+        _ = core::fmt::write(&mut list, format_args!("{}: {}\n", wifi.ssid, wifi.rssi));
+    }
+    list
 }
 
-pub async fn write_text_200<'d>(socket: &mut embassy_net::tcp::TcpSocket<'d>, content: &[u8]) {
-    write_response(socket, 200, "text/plain", content).await;
+pub struct ResponseWriter<'a, 'd> {
+    socket: &'a mut TcpSocket<'d>,
+    keep_alive: bool,
+    body: StreamedBody,
 }
-pub async fn write_response<'d>(
-    socket: &mut embassy_net::tcp::TcpSocket<'d>,
-    status: u16,
-    content_type: &str,
-    content: &[u8],
-) {
-    let res_header = generate_res_header(status, content.len(), content_type);
 
-    //write_all
-    let r = socket.write_all(res_header.as_bytes()).await;
-    if let Err(e) = r {
-        log::error!("write header error: {:?}", e);
+impl<'a, 'd> ResponseWriter<'a, 'd> {
+    fn new(socket: &'a mut TcpSocket<'d>, keep_alive: bool, body: StreamedBody) -> Self {
+        Self {
+            socket,
+            keep_alive,
+            body,
+        }
     }
 
-    let r = socket.write_all(content).await;
-    if let Err(e) = r {
-        log::error!("write chunk error: {:?}", e);
+    /// Reads next part of streamed request body (`HttpHandler::streams_body`),
+    /// `Ok(0)` at the end
+    ///
+    /// Connection is closed after response if body isnt read to the end.
+    pub async fn read_body(&mut self, buf: &mut [u8]) -> Result<usize, HttpError> {
+        let StreamedBody::Pending {
+            received,
+            remaining,
+        } = &mut self.body
+        else {
+            return Ok(0);
+        };
+        if !received.is_empty() {
+            let len = buf.len().min(received.len());
+            buf[..len].copy_from_slice(&received[..len]);
+            received.drain(..len);
+            return Ok(len);
+        }
+        if *remaining == 0 {
+            return Ok(0);
+        }
+
+        let len = buf.len().min(*remaining);
+        match self.socket.read(&mut buf[..len]).await {
+            Ok(0) => Err(HttpError::BadRequest),
+            Ok(len) => {
+                *remaining -= len;
+                Ok(len)
+            }
+            Err(e) => {
+                log::error!("read error: {:?}", e);
+                Err(HttpError::BadRequest)
+            }
+        }
     }
 
-    let r = socket.flush().await;
-    if let Err(e) = r {
-        log::error!("flush  error: {:?}", e);
+    /// Takes over connection (after `101 Switching Protocols`), it is closed
+    /// when handler returns
+    pub(crate) fn upgrade(&mut self) -> &mut TcpSocket<'d> {
+        self.keep_alive = false;
+        self.socket
+    }
+
+    /// Whether connection stays open after response (unread streamed body closes it)
+    fn keep_alive(&mut self) -> bool {
+        if let StreamedBody::Pending {
+            received,
+            remaining,
+        } = &self.body
+        {
+            if !received.is_empty() || *remaining > 0 {
+                self.keep_alive = false;
+            }
+        }
+        self.keep_alive
+    }
+
+    /// Redirects to the portal (absolute url, probes are sent with foreign `Host`)
+    pub async fn write_302(&mut self, gw_ip_addr_str: &str) {
+        let redirect_response = alloc::format!(
+            "HTTP/1.1 302 Found\r\nLocation: http://{gw_ip_addr_str}/\r\nContent-Length: 0\r\nConnection: {}\r\n\r\n",
+            if self.keep_alive() { "keep-alive" } else { "close" },
+        );
+
+        let r = self.socket.write_all(redirect_response.as_bytes()).await;
+        if let Err(e) = r {
+            log::error!("write chunk error: {:?}", e);
+        }
+    }
+
+    pub async fn write_html_200(&mut self, content: &[u8]) {
+        self.write_response(200, "text/html", content).await;
+    }
+
+    pub async fn write_text_200(&mut self, content: &[u8]) {
+        self.write_response(200, "text/plain", content).await;
     }
-}
 
-// 配置成功页面
-const SUCCESS_HTML: &str = r#"<!DOCTYPE html>
-<html>
-<head>
-    <title>配置成功</title>
-    <meta charset="UTF-8">
-    <style>
-        body { font-family: Arial, sans-serif; max-width: 400px; margin: 0 auto; padding: 20px; text-align: center; }
-        h1 { color: #4CAF50; }
-    </style>
-</head>
-<body>
-    <h1>配置成功！</h1>
-    <p>设备将重新启动并连接到配置的WiFi网络。</p>
-</body>
-</html>"#;
+    /// Error response with reason phrase as body
+    pub async fn write_error(&mut self, error: HttpError) {
+        let status = error.status();
+        let headers: &[(&str, &str)] = if error == HttpError::MethodNotAllowed {
+            &[("Allow", "GET, POST")]
+        } else {
+            &[]
+        };
+        self.write_response_with_headers(
+            status,
+            "text/plain",
+            headers,
+            status_text(status).as_bytes(),
+        )
+        .await;
+    }
+
+    pub async fn write_json(&mut self, status: u16, value: &JsonValue) {
+        let body = alloc::string::ToString::to_string(value);
+        self.write_response(status, "application/json", body.as_bytes())
+            .await;
+    }
+
+    /// Static file (gzipped ones with `Content-Encoding: gzip`)
+    ///
+    /// Templates are rendered in `language`.
+    pub(crate) async fn write_asset(
+        &mut self,
+        asset: &StaticAsset,
+        vars: &AssetVars,
+        language: &Language,
+    ) {
+        const GZIP: &[(&str, &str)] = &[("Content-Encoding", "gzip")];
+        match asset.body {
+            AssetBody::Raw(data) => self.write_response(200, asset.content_type, data).await,
+            AssetBody::Gzip(data) => {
+                self.write_response_with_headers(200, asset.content_type, GZIP, data)
+                    .await
+            }
+            AssetBody::GzipTemplate(parts) => {
+                let data = assets::render_template(parts, asset.content_type, vars, language);
+                let headers = [
+                    ("Content-Encoding", "gzip"),
+                    ("Content-Language", language.code),
+                    ("Vary", "Accept-Language"),
+                ];
+                self.write_response_with_headers(200, asset.content_type, &headers, &data)
+                    .await
+            }
+        }
+    }
+
+    pub async fn write_response(&mut self, status: u16, content_type: &str, content: &[u8]) {
+        self.write_response_with_headers(status, content_type, &[], content)
+            .await;
+    }
+
+    pub async fn write_response_with_headers(
+        &mut self,
+        status: u16,
+        content_type: &str,
+        extra_headers: &[(&str, &str)],
+        content: &[u8],
+    ) {
+        let res_header = generate_res_header(
+            status,
+            content.len(),
+            content_type,
+            self.keep_alive(),
+            extra_headers,
+        );
+
+        //write_all
+        let r = self.socket.write_all(res_header.as_bytes()).await;
+        if let Err(e) = r {
+            log::error!("write header error: {:?}", e);
+        }
+
+        let r = self.socket.write_all(content).await;
+        if let Err(e) = r {
+            log::error!("write chunk error: {:?}", e);
+        }
+
+        let r = self.socket.flush().await;
+        if let Err(e) = r {
+            log::error!("flush  error: {:?}", e);
+        }
+    }
+}
@@ -8,24 +8,40 @@ compile_error!("enable at least one feature (\"ble\", \"ap\", \"env\")!");
 compile_error!("ESP32-S2 doesnt support BLE!");
 
 extern crate alloc;
+use alloc::boxed::Box;
 use alloc::rc::Rc;
 use alloc::string::String;
-use core::ops::DerefMut;
+use alloc::vec::Vec;
 use embassy_executor::Spawner;
 use embassy_net::{Config, Runner, StackResources};
 use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex};
 use embassy_sync::signal::Signal;
-use embassy_time::{Duration, Instant, Timer};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
 use esp_hal::{peripherals::WIFI, rng::Rng};
 use esp_nvs::platform::EspFlash;
 use esp_nvs::Key;
 use esp_radio::{
-    wifi::{WifiController, WifiDevice, WifiEvent, WifiStaState},
+    wifi::{Interfaces, WifiController, WifiDevice, WifiError, WifiEvent, WifiStaState},
     Controller,
 };
-use structs::{AutoSetupSettings, Result, WmInnerSignals, WmReturn};
-
-pub use structs::{WmError, WmSettings};
+use esp_wifi_manager_core::{form, http_request, i18n, json};
+use portable_atomic::{AtomicPtr, Ordering};
+use structs::{AutoSetupSettings, ConnState, FailReason, Result, WmInnerSignals, WmTaskSignals};
+
+pub use admin::{AdminSettings, SharedNvs};
+pub use assets::{AssetBody, AssetVar, StaticAsset, TemplatePart, CUSTOM_ASSETS};
+pub use auth::{AuthLockout, HttpAuth};
+pub use device_info::DeviceInfo;
+pub use http_request::{HttpError, HttpLimits, HttpMethod, HttpRequest};
+pub use http_server::{serve_http, HttpServerConfig, ResponseWriter, HTTP_MAX_WORKERS};
+pub use i18n::{negotiate as negotiate_language, Language, CHINESE, ENGLISH};
+pub use log_capture::{LogCapture, SyslogSettings};
+pub use ota::{Ota, OtaError, OtaStatus, OtaVerify};
+pub use router::{HandlerFuture, HttpHandler, HttpRouter};
+pub use structs::{
+    ApInfo, ApPassword, RadioPeripherals, RegulatorySettings, ScanResult, ScanSettings,
+    SettingsError, UnusedInterfaces, WmError, WmReturn, WmSettings, WmSettingsBuilder,
+};
 pub use utils::get_efuse_mac;
 
 #[cfg(feature = "ap")]
@@ -34,23 +50,53 @@ mod http;
 #[cfg(feature = "ap")]
 mod ap;
 
+#[cfg(feature = "ap")]
+mod dns;
+
+mod admin;
+mod api;
+mod assets;
+mod auth;
 #[cfg(feature = "ble")]
 mod bluetooth;
+mod captive;
+mod device_info;
 mod http_server;
+mod log_capture;
 pub mod nvs;
+mod ota;
+mod regulatory;
+mod router;
 mod structs;
 mod utils;
+mod ws;
 pub const NAMESPACE_WIFI: &Key = &Key::from_str("WIFI");
 pub const KEY_SSID: &Key = &Key::from_str("SSID");
 pub const KEY_PASSWORD: &Key = &Key::from_str("PASSWORD");
+pub const KEY_COUNTRY: &Key = &Key::from_str("COUNTRY");
+pub const KEY_REPROVISION: &Key = &Key::from_str("REPROVISION");
+
+/// Max number of networks kept in nvs
+pub const MAX_SAVED_NETWORKS: usize = 4;
 
-macro_rules! mk_static {
-    ($t:ty,$val:expr) => {{
-        static STATIC_CELL: static_cell::StaticCell<$t> = static_cell::StaticCell::new();
-        #[deny(unused_attributes)]
-        let x = STATIC_CELL.uninit().write(($val));
-        x
-    }};
+/// Radio controller created by first `init_wm` call.
+///
+/// It is never dropped, so `WmReturn::wifi_init` stays valid after
+/// `WmReturn::deinit()` and next `init_wm` call reuses it.
+static RADIO_CONTROLLER: AtomicPtr<Controller<'static>> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Station stack resources, reused by every `init_wm` call
+static STA_RESOURCES: AtomicPtr<StackResources<3>> = AtomicPtr::new(core::ptr::null_mut());
+
+fn radio_controller() -> Result<&'static Controller<'static>> {
+    let ptr = RADIO_CONTROLLER.load(Ordering::Acquire);
+    if !ptr.is_null() {
+        return Ok(unsafe { &*ptr });
+    }
+
+    let init: &'static mut Controller<'static> = Box::leak(Box::new(esp_radio::init()?));
+    RADIO_CONTROLLER.store(init, Ordering::Release);
+    Ok(init)
 }
 
 pub async fn start_wifi(
@@ -65,8 +111,8 @@ pub async fn start_wifi(
         &mut wm_settings.ssid,
         format_args!("ESP-{:X}", get_efuse_mac()),
     );
-    wm_settings.wifi_conn_timeout = 30000;
-    wm_settings.esp_reset_timeout = Some(300000); // 5min
+    wm_settings.wifi_conn_timeout = Duration::from_secs(30);
+    wm_settings.esp_reset_timeout = Some(Duration::from_secs(300)); // 5min
     let rng = esp_hal::rng::Rng::new();
     let wifi_res = init_wm(
         wm_settings,
@@ -86,46 +132,180 @@ pub async fn init_wm(
     settings: WmSettings,
     spawner: &Spawner,
     nvs: &mut esp_nvs::Nvs<'static, EspFlash<'static>>,
-    mut rng: Rng,
+    rng: Rng,
     wifi: WIFI<'static>,
     #[cfg(feature = "ble")] bt: esp_hal::peripherals::BT<'static>,
-    ap_start_signal: Option<Rc<Signal<NoopRawMutex, ()>>>,
+    ap_start_signal: Option<Rc<Signal<NoopRawMutex, ApInfo>>>,
 ) -> Result<WmReturn> {
+    let init = radio_controller()?;
+    let (controller, interfaces) = esp_radio::wifi::new(init, wifi, Default::default())?;
+
+    let (wm_return, _unused) = init_wm_inner(
+        settings,
+        spawner,
+        nvs,
+        rng,
+        init,
+        controller,
+        interfaces,
+        #[cfg(feature = "ble")]
+        Some(bt),
+        ap_start_signal,
+        true,
+    )
+    .await?;
+    Ok(wm_return)
+}
+
+/// Same as `init_wm`, but uses radio controller and wifi interfaces
+/// initialized by application (for example when it also uses ESP-NOW).
+///
+/// `bt` is optional here, pass `None` if application runs its own ble stack.
+/// Interfaces not used by wifimanager are returned back.
+#[allow(clippy::too_many_arguments)]
+pub async fn init_wm_with_controller(
+    settings: WmSettings,
+    spawner: &Spawner,
+    nvs: &mut esp_nvs::Nvs<'static, EspFlash<'static>>,
+    rng: Rng,
+    init: &'static Controller<'static>,
+    controller: WifiController<'static>,
+    interfaces: Interfaces<'static>,
+    #[cfg(feature = "ble")] bt: Option<esp_hal::peripherals::BT<'static>>,
+    ap_start_signal: Option<Rc<Signal<NoopRawMutex, ApInfo>>>,
+) -> Result<(WmReturn, UnusedInterfaces)> {
+    init_wm_inner(
+        settings,
+        spawner,
+        nvs,
+        rng,
+        init,
+        controller,
+        interfaces,
+        #[cfg(feature = "ble")]
+        bt,
+        ap_start_signal,
+        false,
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn init_wm_inner(
+    settings: WmSettings,
+    spawner: &Spawner,
+    nvs: &mut esp_nvs::Nvs<'static, EspFlash<'static>>,
+    mut rng: Rng,
+    init: &'static Controller<'static>,
+    mut controller: WifiController<'static>,
+    interfaces: Interfaces<'static>,
+    #[cfg(feature = "ble")] bt: Option<esp_hal::peripherals::BT<'static>>,
+    ap_start_signal: Option<Rc<Signal<NoopRawMutex, ApInfo>>>,
+    owns_radio: bool,
+) -> Result<(WmReturn, UnusedInterfaces)> {
+    settings.validate()?;
     let generated_ssid = settings.ssid.clone();
+    let task_signals = Rc::new(WmTaskSignals::new());
+
+    let sta_interface = interfaces.sta;
+    #[cfg(feature = "ap")]
+    let ap_interface = interfaces.ap;
+    let unused = UnusedInterfaces {
+        #[cfg(not(feature = "ap"))]
+        ap: interfaces.ap,
+        #[cfg(feature = "esp-now")]
+        esp_now: interfaces.esp_now,
+    };
+
+    #[cfg(feature = "ble")]
+    let ble_enabled = bt.is_some();
+    #[cfg(not(feature = "ble"))]
+    let ble_enabled = false;
+
+    let mut dhcp_config = embassy_net::DhcpConfig::default();
+    dhcp_config.hostname = settings.hostname.as_str().try_into().ok();
+    // runner of previous stack stopped in `deinit()`
+    let resources = unsafe { utils::reused(&STA_RESOURCES, StackResources::new()) };
+    let (sta_stack, runner) = embassy_net::new(
+        sta_interface,
+        Config::dhcpv4(dhcp_config),
+        resources,
+        rng.random() as u64,
+    );
+    // runs during provisioning too, so its result includes ip address
+    spawner.spawn(sta_task(runner, task_signals.clone()))?;
 
-    let init = &*mk_static!(Controller<'static>, esp_radio::init()?);
-    let (mut controller, interfaces) = esp_radio::wifi::new(init, wifi, Default::default())?;
     controller.set_power_saving(esp_radio::wifi::PowerSaveMode::None)?;
     //调试
     // clear_wifi(nvs)?;
-    let wifi_setup = get_wifi_settings(nvs);
+    let networks = if take_reprovisioning_request(nvs) {
+        log::info!("Reprovisioning requested, skipping saved networks");
+        Vec::new()
+    } else {
+        saved_networks(nvs)
+    };
 
-    let mut wifi_connected = false;
+    let mut wifi_setup = None;
     let mut controller_started = false;
-    //nvs中存在wifi ssid和密码，直接开始连接wifi
-    if let Some(ref wifi_setup) = wifi_setup {
-        let client_config = wifi_setup.to_client_conf()?;
-        log::info!("Connecting to wifi: {wifi_setup:?}");
+    //nvs中存在wifi ssid和密码，按顺序尝试连接
+    for network in networks {
+        //nvs中保存的国家码优先于settings
+        let regulatory =
+            regulatory::resolve(network.country.as_ref(), settings.regulatory.as_ref());
+        if let Some(ref regulatory) = regulatory {
+            regulatory::apply_country(regulatory)?;
+        }
+
+        let client_config = network.to_client_conf()?;
+        log::info!("Connecting to wifi: {network:?}");
         controller.set_config(&esp_radio::wifi::ModeConfig::Client(client_config))?;
-        controller.start_async().await?;
-        controller_started = true;
+        if !controller_started {
+            controller.start_async().await?;
+            controller_started = true;
+        }
+        if let Some(ref regulatory) = regulatory {
+            regulatory::apply_tx_power(regulatory)?;
+        }
 
-        wifi_connected =
-            utils::try_to_wifi_connect(&mut controller, settings.wifi_conn_timeout).await;
+        if utils::try_to_wifi_connect(&mut controller, settings.wifi_conn_timeout).await {
+            wifi_setup = Some(network);
+            break;
+        }
     }
+    let mut regulatory = regulatory::resolve(
+        wifi_setup.as_ref().and_then(|s| s.country.as_ref()),
+        settings.regulatory.as_ref(),
+    );
+    if wifi_setup.is_none() {
+        // replaces country of last tried network
+        if let Some(ref regulatory) = regulatory {
+            regulatory::apply_country(regulatory)?;
+        }
+    }
+
+    let wifi_connected = wifi_setup.is_some();
     //连接失败或者nvs中不存在wifi ssid和密码，开始启动ap和httpserver
     if !wifi_connected {
         log::info!("Starting wifimanager with ssid: {generated_ssid}");
 
         let wm_signals = Rc::new(WmInnerSignals::new());
+
+        #[cfg(feature = "ap")]
+        let ap_password = utils::ap_password(&settings, &mut rng);
+        #[cfg(not(feature = "ap"))]
+        let ap_password = None;
+
         if let Some(ap_start_signal) = ap_start_signal {
-            ap_start_signal.signal(());
+            ap_start_signal.signal(ApInfo {
+                ssid: generated_ssid.clone(),
+                password: ap_password.clone(),
+            });
         }
 
         #[cfg(feature = "ap")]
         let configuration = esp_radio::wifi::ModeConfig::ApSta(
             Default::default(),
-            esp_radio::wifi::AccessPointConfig::default().with_ssid(generated_ssid.clone()),
+            utils::ap_config(&settings, ap_password),
         );
 
         #[cfg(not(feature = "ap"))]
@@ -139,7 +319,7 @@ pub async fn init_wm(
             spawner,
             wm_signals.clone(),
             settings.clone(),
-            interfaces.ap,
+            ap_interface,
         )
         .await?;
 
@@ -149,27 +329,44 @@ pub async fn init_wm(
             .signal(env!("WM_CONN").as_bytes().to_vec());
 
         #[cfg(feature = "ble")]
-        spawner.spawn(bluetooth::bluetooth_task(
-            init,
-            bt,
-            generated_ssid,
-            wm_signals.clone(),
-        ))?;
+        if let Some(bt) = bt {
+            spawner.spawn(bluetooth::bluetooth_task(
+                init,
+                bt,
+                generated_ssid,
+                wm_signals.clone(),
+                task_signals.clone(),
+                settings.languages.clone(),
+                settings.default_language,
+                settings.ota.clone(),
+                settings.log_capture,
+                settings.firmware_version.clone(),
+            ))?;
+        }
 
         if !controller_started {
             controller.start_async().await?;
+            if let Some(ref regulatory) = regulatory {
+                regulatory::apply_tx_power(regulatory)?;
+            }
         }
 
-        let wifi_setup = wifi_connection_worker(
+        let provisioned = wifi_connection_worker(
             settings.clone(),
             wm_signals,
             nvs,
             &mut controller,
+            sta_stack,
             configuration,
         )
         .await?;
 
-        controller.set_config(&wifi_setup.to_configuration()?)?;
+        controller.set_config(&provisioned.to_configuration()?)?;
+        if provisioned.country.is_some() {
+            regulatory =
+                regulatory::resolve(provisioned.country.as_ref(), settings.regulatory.as_ref());
+        }
+        wifi_setup = Some(provisioned);
         if settings.esp_restart_after_connection {
             log::info!("Wifimanager reset after succesfull first connection...");
             Timer::after_millis(1000).await;
@@ -177,67 +374,188 @@ pub async fn init_wm(
         }
     };
 
-    let sta_config = Config::dhcpv4(Default::default());
-    let (sta_stack, runner) = embassy_net::new(
-        interfaces.sta,
-        sta_config,
-        {
-            static STATIC_CELL: static_cell::StaticCell<StackResources<3>> =
-                static_cell::StaticCell::new();
-            STATIC_CELL.uninit().write(StackResources::<3>::new())
-        },
-        rng.random() as u64,
-    );
+    // ble task is spawned only when provisioning was needed
+    let ble_spawned = ble_enabled && !wifi_connected;
+    if !ble_spawned {
+        task_signals.ble_stopped.signal(());
+    }
 
     let stop_signal = Rc::new(Signal::new());
     spawner.spawn(connection(
         settings.wifi_reconnect_time,
         controller,
         stop_signal.clone(),
+        task_signals.clone(),
+        regulatory,
     ))?;
-    spawner.spawn(sta_task(runner))?;
     let (ssid, password) = if let Some(ref wifi_setup) = wifi_setup {
         (wifi_setup.ssid.clone(), wifi_setup.psk.clone())
     } else {
         (String::new(), String::new())
     };
 
-    Ok(WmReturn {
+    let wm_return = WmReturn {
         wifi_init: init,
         sta_stack,
         ip_address: utils::wifi_wait_for_ip(&sta_stack).await,
         ssid,
         password,
         stop_signal,
-    })
+        task_signals,
+        asset_vars: assets::AssetVars::new(&settings),
+        owns_radio,
+    };
+    Ok((wm_return, unused))
 }
 
-fn get_wifi_settings(
+/// Nvs keys (ssid, password, country) of saved network slots, most preferred first
+///
+/// First slot uses keys of single network storage, so credentials saved
+/// by older versions keep working.
+const NETWORK_KEYS: [(&Key, &Key, &Key); MAX_SAVED_NETWORKS] = [
+    (KEY_SSID, KEY_PASSWORD, KEY_COUNTRY),
+    (
+        &Key::from_str("SSID1"),
+        &Key::from_str("PASSWORD1"),
+        &Key::from_str("COUNTRY1"),
+    ),
+    (
+        &Key::from_str("SSID2"),
+        &Key::from_str("PASSWORD2"),
+        &Key::from_str("COUNTRY2"),
+    ),
+    (
+        &Key::from_str("SSID3"),
+        &Key::from_str("PASSWORD3"),
+        &Key::from_str("COUNTRY3"),
+    ),
+];
+
+/// Saved networks tried on boot, most preferred first
+pub(crate) fn saved_networks(
     nvs: &mut esp_nvs::Nvs<'static, EspFlash<'static>>,
-) -> Option<AutoSetupSettings> {
-    let ssid = if let Ok(ssid) = nvs.get(NAMESPACE_WIFI, KEY_SSID) {
-        ssid
-    } else {
-        String::new()
-    };
-    let password = if let Ok(password) = nvs.get(NAMESPACE_WIFI, KEY_PASSWORD) {
-        password
-    } else {
-        String::new()
-    };
-    let wifi_setup = if ssid.is_empty() || password.is_empty() {
+) -> Vec<AutoSetupSettings> {
+    let mut networks = Vec::new();
+    for (key_ssid, key_password, key_country) in NETWORK_KEYS {
+        let Ok(ssid) = nvs.get::<String>(NAMESPACE_WIFI, key_ssid) else {
+            continue;
+        };
+        if ssid.is_empty() {
+            continue;
+        }
+        // open networks have no password
+        let psk = nvs.get(NAMESPACE_WIFI, key_password).unwrap_or_default();
+        let country: Option<String> = nvs.get(NAMESPACE_WIFI, key_country).ok();
+        let country = country.and_then(|c| RegulatorySettings::for_country(c.as_str()));
+        networks.push(AutoSetupSettings { ssid, psk, country });
+    }
+
+    if networks.is_empty() {
         log::info!("No wifi_setup found in flash");
-        None
     } else {
-        log::info!("Read wifi_setup from flash:ssid= {ssid:?}, password= {password:?}");
-        Some(AutoSetupSettings {
-            ssid,
-            psk: password,
-        })
-    };
-    wifi_setup
+        log::info!(
+            "Read {} saved network(s) from flash: {:?}",
+            networks.len(),
+            networks.iter().map(|n| n.ssid.as_str()).collect::<Vec<_>>()
+        );
+    }
+    networks
 }
 
+/// Saves network, `preferred` puts it first (dropping the last one if storage
+/// is full), otherwise it is added last
+///
+/// Already saved network with the same ssid is replaced. Returns `false` if
+/// network isnt `preferred` and all slots are taken.
+pub(crate) fn save_network(
+    nvs: &mut esp_nvs::Nvs<'static, EspFlash<'static>>,
+    network: AutoSetupSettings,
+    preferred: bool,
+) -> Result<bool> {
+    let mut networks = saved_networks(nvs);
+    if let Some(pos) = networks.iter().position(|n| n.ssid == network.ssid) {
+        if preferred {
+            networks.remove(pos);
+            networks.insert(0, network);
+        } else {
+            networks[pos] = network;
+        }
+    } else if preferred {
+        networks.insert(0, network);
+        networks.truncate(MAX_SAVED_NETWORKS);
+    } else if networks.len() < MAX_SAVED_NETWORKS {
+        networks.push(network);
+    } else {
+        return Ok(false);
+    }
+
+    write_networks(nvs, &networks)?;
+    Ok(true)
+}
+
+/// Removes saved network, returns `false` if it wasnt saved
+pub(crate) fn forget_network(
+    nvs: &mut esp_nvs::Nvs<'static, EspFlash<'static>>,
+    ssid: &str,
+) -> Result<bool> {
+    let mut networks = saved_networks(nvs);
+    let len = networks.len();
+    networks.retain(|n| n.ssid != ssid);
+    if networks.len() == len {
+        return Ok(false);
+    }
+
+    write_networks(nvs, &networks)?;
+    Ok(true)
+}
+
+fn write_networks(
+    nvs: &mut esp_nvs::Nvs<'static, EspFlash<'static>>,
+    networks: &[AutoSetupSettings],
+) -> Result<()> {
+    for (i, (key_ssid, key_password, key_country)) in NETWORK_KEYS.into_iter().enumerate() {
+        let Some(network) = networks.get(i) else {
+            _ = nvs.delete(NAMESPACE_WIFI, key_ssid);
+            _ = nvs.delete(NAMESPACE_WIFI, key_password);
+            _ = nvs.delete(NAMESPACE_WIFI, key_country);
+            continue;
+        };
+
+        nvs.set(NAMESPACE_WIFI, key_ssid, network.ssid.as_str())
+            .map_err(|e| WmError::NvsError(e))?;
+        nvs.set(NAMESPACE_WIFI, key_password, network.psk.as_str())
+            .map_err(|e| WmError::NvsError(e))?;
+        match network.country {
+            Some(ref country) => {
+                let cc = core::str::from_utf8(&country.country_code).unwrap_or_default();
+                nvs.set(NAMESPACE_WIFI, key_country, cc)
+                    .map_err(|e| WmError::NvsError(e))?;
+            }
+            None => _ = nvs.delete(NAMESPACE_WIFI, key_country),
+        }
+    }
+    Ok(())
+}
+
+/// Makes next boot start provisioning (saved networks are kept,
+/// they are used again after provisioning times out)
+pub fn request_reprovisioning(
+    nvs: &mut esp_nvs::Nvs<'static, EspFlash<'static>>,
+) -> core::result::Result<(), WmError> {
+    nvs.set(NAMESPACE_WIFI, KEY_REPROVISION, "1")
+        .map_err(|e| WmError::NvsError(e))
+}
+
+/// Checks and clears reprovisioning request
+fn take_reprovisioning_request(nvs: &mut esp_nvs::Nvs<'static, EspFlash<'static>>) -> bool {
+    if nvs.get::<String>(NAMESPACE_WIFI, KEY_REPROVISION).is_err() {
+        return false;
+    }
+    _ = nvs.delete(NAMESPACE_WIFI, KEY_REPROVISION);
+    true
+}
+
+/// Removes all saved networks
 pub fn clear_wifi(
     nvs: &mut esp_nvs::Nvs<'static, EspFlash<'static>>,
 ) -> core::result::Result<(), WmError> {
@@ -245,26 +563,37 @@ pub fn clear_wifi(
         .map_err(|e| WmError::NvsError(e))?;
     nvs.delete(NAMESPACE_WIFI, KEY_PASSWORD)
         .map_err(|e| WmError::NvsError(e))?;
-    Ok(())
+    _ = nvs.delete(NAMESPACE_WIFI, KEY_COUNTRY);
+    write_networks(nvs, &[])
 }
 async fn wifi_connection_worker(
     settings: WmSettings,
     wm_signals: Rc<WmInnerSignals>,
     nvs: &mut esp_nvs::Nvs<'static, EspFlash<'static>>,
     controller: &mut WifiController<'static>,
+    sta_stack: embassy_net::Stack<'static>,
     mut configuration: esp_radio::wifi::ModeConfig,
 ) -> Result<AutoSetupSettings> {
     let start_time = Instant::now();
     let mut last_scan = Instant::MIN;
     log::info!(
         "Waiting for config wifi 。。。The system will be restarted in {:?} milliseconds.",
-        settings.esp_reset_timeout
+        settings.esp_reset_timeout.map(|t| t.as_millis())
     );
     loop {
         if wm_signals.wifi_conn_info_sig.signaled() {
             let setup_info = wm_signals.wifi_conn_info_sig.wait().await;
 
             log::info!("recived http post,trying to connect to: {setup_info:?}");
+            // errors come from client data, they fail the attempt instead of the worker
+            let mut prepared = Ok(());
+            if let Some(ref country) =
+                regulatory::resolve(setup_info.country.as_ref(), settings.regulatory.as_ref())
+            {
+                prepared = regulatory::apply_country(country)
+                    .and_then(|_| regulatory::apply_tx_power(country))
+                    .map_err(|_| FailReason::CountryRejected);
+            }
             #[cfg(feature = "ap")]
             {
                 let esp_radio::wifi::ModeConfig::ApSta(ref mut client_conf, _) = configuration
@@ -284,40 +613,85 @@ async fn wifi_connection_worker(
                 *client_conf = setup_info.to_client_conf()?;
             }
 
-            controller.set_config(&configuration)?;
-
-            let wifi_connected =
-                utils::try_to_wifi_connect(controller, settings.wifi_conn_timeout).await;
-
-            wm_signals.wifi_conn_res_sig.signal(wifi_connected);
-
-            if wifi_connected {
-                log::info!("Wifi connected to: {setup_info:?}");
-                nvs.set(NAMESPACE_WIFI, KEY_SSID, setup_info.ssid.as_str())
-                    .map_err(|e| WmError::NvsError(e))?;
-                nvs.set(NAMESPACE_WIFI, KEY_PASSWORD, setup_info.psk.as_str())
-                    .map_err(|e| WmError::NvsError(e))?;
-                log::info!("Wifi password saved to nvs");
-
-                #[cfg(feature = "ap")]
-                esp_hal_dhcp_server::dhcp_close();
+            let prepared = prepared.and_then(|_| {
+                controller.set_config(&configuration).map_err(|e| {
+                    log::warn!("Wifi config rejected: {e:?}");
+                    FailReason::ConfigRejected
+                })
+            });
+
+            let result = match prepared {
+                Ok(()) => {
+                    wm_signals.set_conn_state(ConnState::Connecting);
+                    connect_provisioned(
+                        controller,
+                        sta_stack,
+                        &wm_signals,
+                        &setup_info,
+                        settings.wifi_conn_timeout,
+                    )
+                    .await
+                }
+                Err(reason) => Err(reason),
+            };
+            wm_signals.provisioned.set(result.is_ok());
+            wm_signals.set_conn_state(match result {
+                Ok(ip) => ConnState::Connected(ip),
+                Err(reason) => ConnState::Failed(reason),
+            });
+
+            match result {
+                Err(reason) => log::warn!("Provisioning failed: {}", reason.as_str()),
+                Ok(_) => {
+                    log::info!("Wifi connected to: {setup_info:?}");
+                    save_network(nvs, setup_info.clone(), true)?;
+                    log::info!("Wifi password saved to nvs");
+
+                    // AP stays up until client learns the result (or grace period ends)
+                    if with_timeout(
+                        settings.provisioning_grace_period,
+                        wm_signals.result_fetched.wait(),
+                    )
+                    .await
+                    .is_err()
+                    {
+                        log::info!("Provisioning result not fetched, stopping AP");
+                    }
 
-                Timer::after_millis(1000).await;
-                wm_signals.signal_end();
-                return Ok(setup_info);
+                    Timer::after_millis(1000).await;
+                    wm_signals.signal_end().await;
+                    return Ok(setup_info);
+                }
             }
         }
 
-        if last_scan.elapsed().as_millis() >= settings.wifi_scan_interval {
-            let scan_res = controller.scan_with_config_async(Default::default()).await;
-            let mut wifis = wm_signals.wifi_scan_res.lock().await;
-            wifis.clear();
-            if let Ok(aps) = scan_res {
-                for ap in aps {
-                    _ = core::fmt::write(
-                        wifis.deref_mut(),
-                        format_args!("{}: {}\n", ap.ssid, ap.signal_strength),
-                    );
+        let scan_settings = if wm_signals.scan_request_sig.signaled() {
+            Some(wm_signals.scan_request_sig.wait().await)
+        } else if last_scan.elapsed() >= settings.wifi_scan_interval {
+            Some(settings.scan.clone())
+        } else {
+            None
+        };
+        if let Some(scan_settings) = scan_settings {
+            wm_signals.signal_scan_started();
+            // credentials arriving during scan abort it
+            let res = embassy_futures::select::select(
+                utils::scan(controller, &scan_settings),
+                wm_signals.wifi_conn_info_sig.wait(),
+            )
+            .await;
+
+            match res {
+                embassy_futures::select::Either::First(scan_res) => {
+                    *wm_signals.wifi_scan_res.lock().await = scan_res.unwrap_or_default();
+                    wm_signals.signal_scan_done(true);
+                }
+                embassy_futures::select::Either::Second(setup_info) => {
+                    log::info!("Scan aborted, credentials received");
+                    utils::abort_scan();
+                    wm_signals.signal_scan_done(false);
+                    // handled in next iteration
+                    wm_signals.wifi_conn_info_sig.signal(setup_info);
                 }
             }
 
@@ -325,7 +699,7 @@ async fn wifi_connection_worker(
         }
 
         if let Some(reset_timeout) = settings.esp_reset_timeout {
-            if start_time.elapsed().as_millis() >= reset_timeout {
+            if start_time.elapsed() >= reset_timeout {
                 log::info!("Wifimanager esp reset timeout reached! Resetting..");
                 Timer::after_millis(1000).await;
                 esp_hal::system::software_reset();
@@ -336,27 +710,88 @@ async fn wifi_connection_worker(
     }
 }
 
+/// Connects with provisioned credentials and waits for dhcp address
+async fn connect_provisioned(
+    controller: &mut WifiController<'static>,
+    sta_stack: embassy_net::Stack<'static>,
+    wm_signals: &WmInnerSignals,
+    setup_info: &AutoSetupSettings,
+    timeout: Duration,
+) -> core::result::Result<[u8; 4], FailReason> {
+    if !utils::try_to_wifi_connect(controller, timeout).await {
+        let found = wm_signals
+            .wifi_scan_res
+            .lock()
+            .await
+            .iter()
+            .any(|wifi| wifi.ssid == setup_info.ssid);
+        // hidden networks arent in scan results, so this is only a hint
+        return Err(if found || setup_info.ssid.is_empty() {
+            FailReason::ConnectFailed
+        } else {
+            FailReason::NetworkNotFound
+        });
+    }
+
+    wm_signals.set_conn_state(ConnState::ObtainingIp);
+    match with_timeout(timeout, utils::wifi_wait_for_ip(&sta_stack)).await {
+        Ok(ip) => Ok(ip),
+        Err(_) => {
+            _ = controller.disconnect_async().await;
+            Err(FailReason::NoIp)
+        }
+    }
+}
+
 #[embassy_executor::task]
 async fn connection(
-    wifi_reconnect_time: u64,
+    wifi_reconnect_time: Duration,
     mut controller: WifiController<'static>,
     stop_signal: Rc<Signal<CriticalSectionRawMutex, bool>>,
+    task_signals: Rc<WmTaskSignals>,
+    regulatory: Option<RegulatorySettings>,
     //stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>,
 ) {
     log::info!("WIFI Device capabilities: {:?}", controller.capabilities());
 
+    embassy_futures::select::select(
+        keep_connected(
+            wifi_reconnect_time,
+            &mut controller,
+            &stop_signal,
+            &task_signals,
+            &regulatory,
+        ),
+        task_signals.shutdown_connection.wait(),
+    )
+    .await;
+
+    _ = controller.disconnect_async().await;
+    _ = controller.stop_async().await;
+    log::info!("WIFI connection task stopped!");
+    task_signals.controller_return.signal(controller);
+}
+
+async fn keep_connected(
+    wifi_reconnect_time: Duration,
+    controller: &mut WifiController<'static>,
+    stop_signal: &Signal<CriticalSectionRawMutex, bool>,
+    task_signals: &WmTaskSignals,
+    regulatory: &Option<RegulatorySettings>,
+) {
     loop {
         if esp_radio::wifi::sta_state() == WifiStaState::Connected {
             // wait until we're no longer connected
-            let res = embassy_futures::select::select(
+            let res = embassy_futures::select::select3(
                 controller.wait_for_event(WifiEvent::StaDisconnected),
                 stop_signal.wait(),
+                task_signals.scan_request.wait(),
             )
             .await;
 
             match res {
-                embassy_futures::select::Either::First(_) => {}
-                embassy_futures::select::Either::Second(val) => {
+                embassy_futures::select::Either3::First(_) => {}
+                embassy_futures::select::Either3::Second(val) => {
                     if val {
                         _ = controller.disconnect_async().await;
                         _ = controller.stop_async().await;
@@ -371,29 +806,59 @@ async fn connection(
                         }
 
                         _ = controller.start_async().await;
+                        if let Some(ref regulatory) = regulatory {
+                            _ = regulatory::apply_tx_power(regulatory);
+                        }
                         log::info!("WIFI radio restarted!");
                     } else {
                         continue;
                     }
                 }
+                embassy_futures::select::Either3::Third((id, scan_settings)) => {
+                    let res = utils::scan(controller, &scan_settings).await;
+                    task_signals.scan_done.signal((id, res));
+                    continue;
+                }
             }
 
-            Timer::after(Duration::from_millis(wifi_reconnect_time)).await
+            wait_disconnected(wifi_reconnect_time, task_signals).await
         }
 
+        // request which raced with disconnect
+        if let Some((id, _)) = task_signals.scan_request.try_take() {
+            task_signals
+                .scan_done
+                .signal((id, Err(WifiError::Disconnected)));
+        }
         match controller.connect_async().await {
             Ok(_) => {
                 log::info!("Wifi connected!");
             }
             Err(e) => {
                 log::info!("Failed to connect to wifi: {e:?}");
-                Timer::after(Duration::from_millis(wifi_reconnect_time)).await
+                wait_disconnected(wifi_reconnect_time, task_signals).await
             }
         }
     }
 }
 
+/// Waits before reconnecting, scan requests fail meanwhile (instead of waiting for connection)
+async fn wait_disconnected(duration: Duration, task_signals: &WmTaskSignals) {
+    let deadline = Instant::now() + duration;
+    while let embassy_futures::select::Either::Second((id, _)) =
+        embassy_futures::select::select(Timer::at(deadline), task_signals.scan_request.wait()).await
+    {
+        task_signals
+            .scan_done
+            .signal((id, Err(WifiError::Disconnected)));
+    }
+}
+
 #[embassy_executor::task]
-async fn sta_task(mut runner: Runner<'static, WifiDevice<'static>>) {
-    runner.run().await
+async fn sta_task(
+    mut runner: Runner<'static, WifiDevice<'static>>,
+    task_signals: Rc<WmTaskSignals>,
+) {
+    embassy_futures::select::select(runner.run(), task_signals.shutdown_sta.wait()).await;
+    task_signals.sta_stopped.signal(());
 }
@@ -0,0 +1,366 @@
+use alloc::{boxed::Box, rc::Rc, string::String, vec, vec::Vec};
+use core::cell::RefCell;
+use core::fmt::Write;
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    IpEndpoint, Stack,
+};
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    signal::Signal,
+};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::api::write_error;
+use crate::auth::Authenticator;
+use crate::http_request::{HttpMethod, HttpRequest};
+use crate::http_server::ResponseWriter;
+use crate::structs::WmTaskSignals;
+
+pub(crate) const LOGS_PATH: &str = "/api/v1/logs";
+
+/// Longer records are cut (so every record fits into buffer)
+const MAX_LINE_LEN: usize = 256;
+
+/// Syslog messages are kept under minimum size receivers have to accept (RFC 5426)
+const MAX_SYSLOG_LEN: usize = 480;
+
+/// `log::Log` printing records like `esp_println` and keeping the last ones in RAM
+///
+/// Records are kept as lines `<uptime ms> <E|W|I|D|T> <target>: <message>`,
+/// the oldest lines are dropped when buffer is full.
+pub struct LogCapture {
+    level: LevelFilter,
+    ring: Mutex<CriticalSectionRawMutex, RefCell<LogRing>>,
+
+    /// Signalled on every record (wakes syslog forwarding)
+    written: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl LogCapture {
+    /// Installs capture as global logger, keeping up to `capacity` bytes of records
+    ///
+    /// Replaces `esp_println::logger::init_logger*`, call it after heap is initialized.
+    pub fn init(level: LevelFilter, capacity: usize) -> Result<&'static Self, log::SetLoggerError> {
+        let capacity = capacity.max(MAX_LINE_LEN + 1);
+        let capture: &'static Self = Box::leak(Box::new(Self {
+            level,
+            ring: Mutex::new(RefCell::new(LogRing {
+                data: vec![0; capacity].into_boxed_slice(),
+                start: 0,
+                len: 0,
+                end_pos: 0,
+            })),
+            written: Signal::new(),
+        }));
+        log::set_logger(capture)?;
+        log::set_max_level(level);
+        Ok(capture)
+    }
+
+    /// All kept records
+    pub fn read(&self) -> String {
+        self.read_since(0).0
+    }
+
+    /// Last records that fit into `max_len` bytes (whole lines only)
+    pub fn tail(&self, max_len: usize) -> String {
+        let logs = self.read();
+        let Some(cut) = logs.len().checked_sub(max_len).filter(|&cut| cut > 0) else {
+            return logs;
+        };
+        // first line starting at `cut` or later
+        match logs.as_bytes()[cut - 1..].iter().position(|&b| b == b'\n') {
+            Some(line_end) => String::from(&logs[cut + line_end..]),
+            None => String::new(),
+        }
+    }
+
+    pub fn clear(&self) {
+        self.ring.lock(|ring| {
+            let mut ring = ring.borrow_mut();
+            ring.start = 0;
+            ring.len = 0;
+        });
+    }
+
+    /// Records written after position `pos` (or the oldest kept ones) and position of their end
+    fn read_since(&self, pos: u64) -> (String, u64) {
+        let (bytes, end_pos) = self.ring.lock(|ring| {
+            let ring = ring.borrow();
+            let skip = pos.saturating_sub(ring.end_pos - ring.len as u64) as usize;
+            let bytes: Vec<u8> = (skip.min(ring.len)..ring.len)
+                .map(|i| ring.data[(ring.start + i) % ring.data.len()])
+                .collect();
+            (bytes, ring.end_pos)
+        });
+        // records can be cut in the middle of utf-8 character
+        (String::from_utf8_lossy(&bytes).into_owned(), end_pos)
+    }
+}
+
+impl core::fmt::Debug for LogCapture {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("LogCapture")
+            .field("level", &self.level)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Log for LogCapture {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        esp_println::println!("{} - {}", record.level(), record.args());
+
+        let uptime = esp_hal::time::Instant::now()
+            .duration_since_epoch()
+            .as_millis();
+        // formatted before locking, so interrupts arent blocked by formatting
+        let mut line = LineWriter {
+            buf: [0; MAX_LINE_LEN],
+            len: 0,
+        };
+        _ = write!(
+            line,
+            "{uptime} {} {}: {}",
+            level_letter(record.level()),
+            record.target(),
+            record.args()
+        );
+        self.ring.lock(|ring| {
+            let mut ring = ring.borrow_mut();
+            for &byte in &line.buf[..line.len] {
+                ring.push(byte);
+            }
+            ring.push(b'\n');
+        });
+        self.written.signal(());
+    }
+
+    fn flush(&self) {}
+}
+
+fn level_letter(level: Level) -> char {
+    match level {
+        Level::Error => 'E',
+        Level::Warn => 'W',
+        Level::Info => 'I',
+        Level::Debug => 'D',
+        Level::Trace => 'T',
+    }
+}
+
+/// Bytes of the last records, oldest lines are dropped to make space
+struct LogRing {
+    data: Box<[u8]>,
+
+    /// Index of the oldest byte
+    start: usize,
+    len: usize,
+
+    /// Bytes written since start (readers keep their position in it)
+    end_pos: u64,
+}
+
+impl LogRing {
+    fn push(&mut self, byte: u8) {
+        if self.len == self.data.len() {
+            self.drop_line();
+        }
+        let end = (self.start + self.len) % self.data.len();
+        self.data[end] = byte;
+        self.len += 1;
+        self.end_pos += 1;
+    }
+
+    fn drop_line(&mut self) {
+        while self.len > 0 {
+            let byte = self.data[self.start];
+            self.start = (self.start + 1) % self.data.len();
+            self.len -= 1;
+            if byte == b'\n' {
+                break;
+            }
+        }
+    }
+}
+
+/// Writes one record line (line breaks in message are replaced, long lines are cut)
+struct LineWriter {
+    buf: [u8; MAX_LINE_LEN],
+    len: usize,
+}
+
+impl Write for LineWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for &byte in s.as_bytes() {
+            if self.len == MAX_LINE_LEN {
+                return Ok(());
+            }
+            self.buf[self.len] = match byte {
+                b'\n' | b'\r' => b' ',
+                byte => byte,
+            };
+            self.len += 1;
+        }
+        Ok(())
+    }
+}
+
+/// `GET /api/v1/logs` (kept records as text) and `DELETE /api/v1/logs` (clears them)
+///
+/// Both need auth when it is enabled. Returns `false` for other paths.
+pub(crate) async fn handle_api(
+    capture: &LogCapture,
+    auth: &Authenticator,
+    request: &HttpRequest,
+    writer: &mut ResponseWriter<'_, '_>,
+) -> bool {
+    if request.path != LOGS_PATH {
+        return false;
+    }
+    // logs can contain ssids and addresses, so even `GET` needs auth
+    if !auth.require(request, writer).await {
+        return true;
+    }
+    match request.method {
+        HttpMethod::Get => {
+            let logs = capture.read();
+            writer
+                .write_response(200, "text/plain; charset=utf-8", logs.as_bytes())
+                .await;
+        }
+        HttpMethod::Delete => {
+            capture.clear();
+            writer.write_response(204, "text/plain", &[]).await;
+        }
+        _ => write_error(writer, 405, "method_not_allowed", "Method not allowed").await,
+    }
+    true
+}
+
+/// Syslog forwarding (`WmReturn::start_syslog`)
+#[derive(Clone, Debug)]
+pub struct SyslogSettings {
+    /// Collector address (udp, usually port 514)
+    pub collector: IpEndpoint,
+
+    /// Syslog facility (`1` user-level by default, `16`-`23` local0-7)
+    pub facility: u8,
+
+    /// `APP-NAME` field
+    pub app_name: String,
+}
+
+impl SyslogSettings {
+    pub fn new(collector: IpEndpoint, app_name: &str) -> Self {
+        Self {
+            collector,
+            facility: 1,
+            app_name: String::from(app_name),
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub(crate) async fn syslog_task(
+    stack: Stack<'static>,
+    capture: &'static LogCapture,
+    settings: SyslogSettings,
+    hostname: String,
+    task_signals: Rc<WmTaskSignals>,
+) {
+    embassy_futures::select::select(
+        forward(stack, capture, &settings, &hostname),
+        task_signals.shutdown_syslog.wait(),
+    )
+    .await;
+    log::info!("syslog forwarding stopped");
+    task_signals.syslog_stopped.signal(());
+}
+
+async fn forward(
+    stack: Stack<'static>,
+    capture: &LogCapture,
+    settings: &SyslogSettings,
+    hostname: &str,
+) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0u8; 16];
+    let mut tx_meta = [PacketMetadata::EMPTY; 8];
+    let mut tx_buffer = [0u8; 2048];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    if let Err(e) = socket.bind(0) {
+        log::error!("syslog bind failed! ({e:?})");
+        return;
+    }
+    log::info!("forwarding logs to syslog at {}", settings.collector);
+
+    // records kept from before connecting are sent too
+    let mut pos = 0;
+    loop {
+        let (records, end_pos) = capture.read_since(pos);
+        pos = end_pos;
+        for record in records.lines() {
+            let message = syslog_message(settings, hostname, record);
+            // errors arent logged, every log record would cause another one
+            _ = socket.send_to(message.as_bytes(), settings.collector).await;
+        }
+        capture.written.wait().await;
+    }
+}
+
+/// RFC 5424 message for kept record line (no clock, so timestamp is nil)
+fn syslog_message(settings: &SyslogSettings, hostname: &str, record: &str) -> String {
+    let mut parts = record.splitn(3, ' ');
+    let (uptime, level, text) = (parts.next(), parts.next(), parts.next());
+    let severity = match level {
+        Some("E") => 3,
+        Some("W") => 4,
+        Some("I") => 6,
+        _ => 7,
+    };
+    // hundredths of second
+    let uptime = uptime.and_then(|ms| ms.parse::<u64>().ok()).unwrap_or(0) / 10;
+    let mut message = alloc::format!(
+        "<{}>1 - {} {} - - [meta sysUpTime=\"{uptime}\"] {}",
+        settings.facility as u16 * 8 + severity,
+        field(hostname, 255),
+        field(&settings.app_name, 48),
+        text.unwrap_or_default()
+    );
+    if message.len() > MAX_SYSLOG_LEN {
+        let mut cut = MAX_SYSLOG_LEN;
+        while !message.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        message.truncate(cut);
+    }
+    message
+}
+
+/// Header field (printable ascii without spaces), `-` if empty
+fn field(value: &str, max_len: usize) -> String {
+    let value: String = value
+        .chars()
+        .filter(|c| c.is_ascii_graphic())
+        .take(max_len)
+        .collect();
+    if value.is_empty() {
+        String::from("-")
+    } else {
+        value
+    }
+}
@@ -1,8 +1,29 @@
 use esp_bootloader_esp_idf::partitions;
 use esp_nvs::{error::Error, platform::EspFlash};
 
+use crate::ota::{Ota, OtaVerify};
+
 pub fn new_nvs(
     flash_per: esp_hal::peripherals::FLASH<'static>,
+) -> Result<esp_nvs::Nvs<'static, EspFlash<'static>>, Error> {
+    nvs_on(&flash_per)
+}
+
+/// Nvs and `Ota` sharing one flash peripheral
+///
+/// Use it instead of `new_nvs` when ota is enabled, flash peripheral cant be
+/// passed to both. Nvs and ota write to own partitions and flash operations are
+/// blocking, so they dont interleave.
+pub fn new_nvs_with_ota(
+    flash_per: esp_hal::peripherals::FLASH<'static>,
+    verify: OtaVerify,
+) -> Result<(esp_nvs::Nvs<'static, EspFlash<'static>>, Ota), Error> {
+    let nvs = nvs_on(&flash_per)?;
+    Ok((nvs, Ota::new(flash_per, verify)))
+}
+
+fn nvs_on(
+    flash_per: &esp_hal::peripherals::FLASH<'static>,
 ) -> Result<esp_nvs::Nvs<'static, EspFlash<'static>>, Error> {
     use static_cell::StaticCell;
     let mut flash = esp_storage::FlashStorage::new(unsafe { flash_per.clone_unchecked() });
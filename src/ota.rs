@@ -0,0 +1,588 @@
+use alloc::{format, vec::Vec};
+use core::cell::{Cell, RefCell};
+use embassy_time::Timer;
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use esp_bootloader_esp_idf::{
+    ota::OtaImageState,
+    ota_updater::OtaUpdater,
+    partitions::{self, AppPartitionSubType, PartitionType},
+};
+use esp_storage::{FlashStorage, FlashStorageError};
+use esp_wifi_manager_core::auth::{constant_time_eq, hmac_finalize, hmac_inner};
+use esp_wifi_manager_core::ota::{ota_allowed, OtaEndpoint};
+use sha2::{Digest, Sha256};
+
+use crate::api::{write_error, API_PREFIX};
+use crate::http_request::{HttpMethod, HttpRequest};
+use crate::http_server::ResponseWriter;
+use crate::json::JsonValue;
+
+/// Path of ota endpoints (`/api/v1/ota`, `/api/v1/ota/valid`, `/api/v1/ota/rollback`)
+pub(crate) const OTA_PATH: &str = "/api/v1/ota";
+
+/// First byte of esp app image
+const IMAGE_MAGIC: u8 = 0xe9;
+
+/// Chip id in extended image header (`esp_chip_id_t`)
+#[cfg(feature = "esp32")]
+const CHIP_ID: u16 = 0;
+#[cfg(feature = "esp32s2")]
+const CHIP_ID: u16 = 2;
+#[cfg(feature = "esp32c3")]
+const CHIP_ID: u16 = 5;
+#[cfg(feature = "esp32s3")]
+const CHIP_ID: u16 = 9;
+#[cfg(feature = "esp32c2")]
+const CHIP_ID: u16 = 12;
+#[cfg(feature = "esp32c6")]
+const CHIP_ID: u16 = 13;
+
+/// Flash sector (`NorFlash::ERASE_SIZE`), image is erased and written sector by sector
+const SECTOR_SIZE: usize = 4096;
+
+/// How uploaded image is verified before boot partition is switched
+///
+/// There is no default: `Sha256` only catches corrupted uploads, so it is
+/// accepted only on servers with `HttpAuth` other than `None` (BLE requires `HmacSha256`).
+#[derive(Clone)]
+pub enum OtaVerify {
+    /// Sha256 of image (hex, `X-Image-Sha256` header), catches corrupted uploads
+    Sha256,
+
+    /// HMAC-SHA256 of image with `key` (hex, `X-Image-Signature` header),
+    /// only holders of the key can update
+    HmacSha256(Vec<u8>),
+}
+
+impl core::fmt::Debug for OtaVerify {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Sha256 => f.write_str("Sha256"),
+            // key isnt printed
+            Self::HmacSha256(_) => f.write_str("HmacSha256(..)"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum OtaError {
+    /// Partition table without ota data / second ota app partition
+    Partition(partitions::Error),
+    MissingPartition,
+    Flash(FlashStorageError),
+
+    /// Another update is in progress
+    Busy,
+
+    /// Image is larger than ota partition
+    TooLarge,
+
+    /// Image isnt esp app image for this chip
+    InvalidImage,
+
+    /// Missing or wrong hash / signature
+    VerificationFailed,
+
+    /// Less data than announced image size
+    Incomplete,
+
+    /// Paused transfer cant continue, another update used the partition meanwhile
+    Interrupted,
+}
+
+impl OtaError {
+    /// Error code of http responses and BLE status
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            Self::Busy => "ota_busy",
+            Self::TooLarge => "image_too_large",
+            Self::InvalidImage => "invalid_image",
+            Self::VerificationFailed => "verification_failed",
+            Self::Incomplete => "incomplete_image",
+            Self::Interrupted => "ota_interrupted",
+            Self::Partition(_) | Self::MissingPartition => "ota_unavailable",
+            Self::Flash(_) => "flash_error",
+        }
+    }
+}
+
+impl From<partitions::Error> for OtaError {
+    fn from(value: partitions::Error) -> Self {
+        Self::Partition(value)
+    }
+}
+
+impl From<FlashStorageError> for OtaError {
+    fn from(value: FlashStorageError) -> Self {
+        Self::Flash(value)
+    }
+}
+
+/// Running / next app partition and state of running image
+#[derive(Clone, Copy, Debug)]
+pub struct OtaStatus {
+    pub running: AppPartitionSubType,
+
+    /// Partition next update is written to
+    pub next: AppPartitionSubType,
+    pub state: OtaImageState,
+}
+
+/// Firmware updates written to inactive ota partition
+///
+/// Shared by portal (`WmSettings::ota`), admin server (`AdminSettings::ota`)
+/// and application, which calls `mark_valid` after new image works
+/// (or `rollback` if it doesnt).
+pub struct Ota {
+    flash: RefCell<FlashStorage<'static>>,
+    verify: OtaVerify,
+    busy: Cell<bool>,
+
+    /// Incremented by every `begin`, paused transfer continues only if it is unchanged
+    generation: Cell<u32>,
+}
+
+impl core::fmt::Debug for Ota {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Ota")
+            .field("verify", &self.verify)
+            .field("busy", &self.busy.get())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Ota {
+    /// Created together with nvs (`nvs::new_nvs_with_ota`), which owns the flash peripheral
+    pub(crate) fn new(flash_per: esp_hal::peripherals::FLASH<'static>, verify: OtaVerify) -> Self {
+        Self {
+            flash: RefCell::new(FlashStorage::new(flash_per)),
+            verify,
+            busy: Cell::new(false),
+            generation: Cell::new(0),
+        }
+    }
+
+    /// Only holders of the key can update (`HmacSha256` with non-empty key)
+    pub(crate) fn authenticated(&self) -> bool {
+        matches!(&self.verify, OtaVerify::HmacSha256(key) if !key.is_empty())
+    }
+
+    pub fn status(&self) -> Result<OtaStatus, OtaError> {
+        self.with_updater(|ota| {
+            let running = ota.selected_partition()?;
+            let state = ota.current_ota_state()?;
+            let (_, next) = ota.next_partition()?;
+            Ok(OtaStatus {
+                running,
+                next,
+                state,
+            })
+        })
+    }
+
+    /// Confirms running image, so bootloader doesnt roll it back
+    ///
+    /// Call after application checked that new image works (for example after
+    /// connecting to wifi). Does nothing if image is already valid.
+    pub fn mark_valid(&self) -> Result<(), OtaError> {
+        self.with_updater(|ota| {
+            let state = ota.current_ota_state()?;
+            if matches!(state, OtaImageState::New | OtaImageState::PendingVerify) {
+                log::info!("[ota] marking running image valid");
+                ota.set_current_ota_state(OtaImageState::Valid)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Marks running image invalid and selects the other ota partition
+    ///
+    /// Takes effect after restart (`esp_hal::system::software_reset`).
+    pub fn rollback(&self) -> Result<(), OtaError> {
+        self.with_updater(|ota| {
+            log::warn!("[ota] rolling back running image");
+            ota.set_current_ota_state(OtaImageState::Invalid)?;
+            ota.activate_next_partition()?;
+            Ok(())
+        })
+    }
+
+    /// Starts writing image of `size` bytes into inactive partition
+    pub(crate) fn begin(&self, size: usize) -> Result<OtaSession<'_>, OtaError> {
+        if self.busy.replace(true) {
+            return Err(OtaError::Busy);
+        }
+        // releases `busy` on errors below
+        let busy = BusyGuard(&self.busy);
+        let generation = self.generation.get().wrapping_add(1);
+        self.generation.set(generation);
+
+        let subtype = self.with_updater(|ota| Ok(ota.next_partition()?.1))?;
+        let mut buffer = [0u8; partitions::PARTITION_TABLE_MAX_LEN];
+        let mut flash = self.flash.borrow_mut();
+        let pt = partitions::read_partition_table(&mut *flash, &mut buffer)?;
+        let partition = pt
+            .find_partition(PartitionType::App(subtype))?
+            .ok_or(OtaError::MissingPartition)?;
+        if size > partition.len() as usize {
+            return Err(OtaError::TooLarge);
+        }
+
+        log::info!("[ota] writing {size} bytes to {subtype:?}");
+        let hasher = match &self.verify {
+            OtaVerify::Sha256 => Sha256::new(),
+            OtaVerify::HmacSha256(key) => hmac_inner(key),
+        };
+        Ok(OtaSession {
+            ota: self,
+            _busy: Some(busy),
+            generation,
+            subtype,
+            offset: partition.offset(),
+            size,
+            written: 0,
+            sector: Vec::with_capacity(SECTOR_SIZE),
+            hasher,
+        })
+    }
+
+    fn with_updater<R>(
+        &self,
+        f: impl FnOnce(&mut OtaUpdater<'_, FlashStorage<'static>>) -> Result<R, partitions::Error>,
+    ) -> Result<R, OtaError> {
+        let mut buffer = [0u8; partitions::PARTITION_TABLE_MAX_LEN];
+        let mut flash = self.flash.borrow_mut();
+        let mut ota = OtaUpdater::new(&mut *flash, &mut buffer)?;
+        f(&mut ota).map_err(OtaError::from)
+    }
+}
+
+struct BusyGuard<'a>(&'a Cell<bool>);
+
+impl Drop for BusyGuard<'_> {
+    fn drop(&mut self) {
+        self.0.set(false);
+    }
+}
+
+/// Image being written, dropping it without `finish` leaves boot partition as is
+pub(crate) struct OtaSession<'a> {
+    ota: &'a Ota,
+
+    /// `None` while paused
+    _busy: Option<BusyGuard<'a>>,
+    generation: u32,
+    subtype: AppPartitionSubType,
+
+    /// Flash offset of target partition
+    offset: u32,
+    size: usize,
+    written: usize,
+    sector: Vec<u8>,
+    hasher: Sha256,
+}
+
+/// Session which doesnt block other updates (`OtaSession::pause`)
+pub(crate) struct PausedOta<'a>(OtaSession<'a>);
+
+impl<'a> PausedOta<'a> {
+    pub fn size(&self) -> usize {
+        self.0.size
+    }
+
+    pub fn received(&self) -> usize {
+        self.0.received()
+    }
+
+    /// Continues paused session, fails if another update started meanwhile
+    pub fn resume(self) -> Result<OtaSession<'a>, OtaError> {
+        let mut session = self.0;
+        let ota = session.ota;
+        if ota.busy.replace(true) {
+            return Err(OtaError::Busy);
+        }
+        let busy = BusyGuard(&ota.busy);
+        if ota.generation.get() != session.generation {
+            return Err(OtaError::Interrupted);
+        }
+        session._busy = Some(busy);
+        Ok(session)
+    }
+}
+
+impl<'a> OtaSession<'a> {
+    /// Releases ota for other updates, received data is kept for `PausedOta::resume`
+    pub fn pause(mut self) -> PausedOta<'a> {
+        self._busy = None;
+        PausedOta(self)
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Bytes received so far
+    pub fn received(&self) -> usize {
+        self.written + self.sector.len()
+    }
+
+    /// Adds next part of image (erases and writes every full sector)
+    pub fn write(&mut self, mut data: &[u8]) -> Result<(), OtaError> {
+        if self.received() + data.len() > self.size {
+            return Err(OtaError::TooLarge);
+        }
+        if self.received() == 0 && data.first().is_some_and(|&b| b != IMAGE_MAGIC) {
+            return Err(OtaError::InvalidImage);
+        }
+
+        self.hasher.update(data);
+        while !data.is_empty() {
+            let len = data.len().min(SECTOR_SIZE - self.sector.len());
+            self.sector.extend_from_slice(&data[..len]);
+            data = &data[len..];
+            if self.sector.len() == SECTOR_SIZE {
+                self.flush_sector()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Verifies image against `expected` hash / signature and makes it boot partition
+    ///
+    /// New image boots after restart and has to be confirmed by `Ota::mark_valid`.
+    pub fn finish(mut self, expected: &[u8; 32]) -> Result<AppPartitionSubType, OtaError> {
+        if self.received() != self.size {
+            return Err(OtaError::Incomplete);
+        }
+        if !self.sector.is_empty() {
+            self.flush_sector()?;
+        }
+
+        let hasher = core::mem::take(&mut self.hasher);
+        let computed: [u8; 32] = match &self.ota.verify {
+            OtaVerify::Sha256 => hasher.finalize().into(),
+            OtaVerify::HmacSha256(key) => hmac_finalize(key, hasher),
+        };
+        if !constant_time_eq(&computed, expected) {
+            return Err(OtaError::VerificationFailed);
+        }
+        self.check_header()?;
+
+        self.ota.with_updater(|ota| {
+            ota.activate_next_partition()?;
+            ota.set_current_ota_state(OtaImageState::New)
+        })?;
+        log::info!("[ota] {:?} verified and selected for boot", self.subtype);
+        Ok(self.subtype)
+    }
+
+    fn flush_sector(&mut self) -> Result<(), OtaError> {
+        let offset = self.offset + self.written as u32;
+        let len = self.sector.len();
+        // write size is word aligned, erased flash reads as 0xff
+        self.sector.resize(len.next_multiple_of(4), 0xff);
+
+        let mut flash = self.ota.flash.borrow_mut();
+        flash.erase(offset, offset + SECTOR_SIZE as u32)?;
+        flash.write(offset, &self.sector)?;
+        self.written += len;
+        self.sector.clear();
+        Ok(())
+    }
+
+    /// Checks chip id of written image (first byte was checked while receiving)
+    fn check_header(&self) -> Result<(), OtaError> {
+        let mut header = [0u8; 24];
+        if self.size < header.len() {
+            return Err(OtaError::InvalidImage);
+        }
+        self.ota.flash.borrow_mut().read(self.offset, &mut header)?;
+        let chip_id = u16::from_le_bytes([header[12], header[13]]);
+        if header[0] != IMAGE_MAGIC || chip_id != CHIP_ID {
+            log::warn!("[ota] image is for chip {chip_id}, expected {CHIP_ID}");
+            return Err(OtaError::InvalidImage);
+        }
+        Ok(())
+    }
+}
+
+/// Lowercase or uppercase hex of 32 bytes
+pub(crate) fn parse_hex_digest(hex: &str) -> Option<[u8; 32]> {
+    let hex = hex.trim().as_bytes();
+    if hex.len() != 64 || !hex.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    let mut digest = [0u8; 32];
+    for (byte, pair) in digest.iter_mut().zip(hex.chunks(2)) {
+        let pair = core::str::from_utf8(pair).ok()?;
+        *byte = u8::from_str_radix(pair, 16).ok()?;
+    }
+    Some(digest)
+}
+
+/// Image upload, its body is streamed into flash
+pub(crate) fn is_upload(request: &HttpRequest) -> bool {
+    request.method == HttpMethod::Post && request.path == OTA_PATH
+}
+
+/// Ota endpoints of portal and admin server, `false` if path isnt ota one
+///
+/// `http_auth` tells if server requires credentials (`ota_allowed`).
+pub(crate) async fn handle_api(
+    ota: &Ota,
+    http_auth: bool,
+    request: &HttpRequest,
+    writer: &mut ResponseWriter<'_, '_>,
+) -> bool {
+    let Some(endpoint) = request.path.strip_prefix(API_PREFIX) else {
+        return false;
+    };
+    let checked = match (request.method, endpoint) {
+        (HttpMethod::Post, "ota") => Some(OtaEndpoint::Upload),
+        (HttpMethod::Post, "ota/valid") => Some(OtaEndpoint::MarkValid),
+        (HttpMethod::Post, "ota/rollback") => Some(OtaEndpoint::Rollback),
+        _ => None,
+    };
+    if checked.is_some_and(|checked| !ota_allowed(checked, http_auth, ota.authenticated())) {
+        log::warn!(
+            "[ota] {:?} refused on server without http auth",
+            request.path
+        );
+        write_error(
+            writer,
+            403,
+            "auth_required",
+            "Endpoint needs server with http auth",
+        )
+        .await;
+        return true;
+    }
+    match (request.method, endpoint) {
+        (HttpMethod::Get, "ota") => match ota.status() {
+            Ok(status) => writer.write_json(200, &status_json(&status)).await,
+            Err(e) => {
+                log::error!("[ota] cannot read ota data: {e:?}");
+                write_error(writer, 500, "ota_unavailable", "Cannot read ota partitions").await
+            }
+        },
+        (HttpMethod::Post, "ota") => upload(ota, request, writer).await,
+        (HttpMethod::Post, "ota/valid") => match ota.mark_valid().and_then(|_| ota.status()) {
+            Ok(status) => writer.write_json(200, &status_json(&status)).await,
+            Err(e) => {
+                log::error!("[ota] cannot mark image valid: {e:?}");
+                write_error(writer, 500, "flash_error", "Cannot write ota data").await
+            }
+        },
+        (HttpMethod::Post, "ota/rollback") => {
+            if let Err(e) = ota.rollback() {
+                log::error!("[ota] rollback failed: {e:?}");
+                write_error(writer, 500, "flash_error", "Cannot write ota data").await;
+                return true;
+            }
+            restart(writer).await;
+        }
+        (_, "ota" | "ota/valid" | "ota/rollback") => {
+            write_error(writer, 405, "method_not_allowed", "Method not allowed").await
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// Streams request body into inactive partition, restarts into it when verified
+async fn upload(ota: &Ota, request: &HttpRequest, writer: &mut ResponseWriter<'_, '_>) {
+    let header = match ota.verify {
+        OtaVerify::Sha256 => "X-Image-Sha256",
+        OtaVerify::HmacSha256(_) => "X-Image-Signature",
+    };
+    let Some(digest) = request.header(header).and_then(parse_hex_digest) else {
+        let message = format!("`{header}` header with hex digest is required");
+        write_error(writer, 400, "missing_digest", &message).await;
+        return;
+    };
+    let size = request.content_length();
+    if size == 0 {
+        write_error(
+            writer,
+            411,
+            "invalid_image",
+            "Image with `Content-Length` is required",
+        )
+        .await;
+        return;
+    }
+
+    let mut session = match ota.begin(size) {
+        Ok(session) => session,
+        Err(e) => {
+            write_ota_error(writer, e).await;
+            return;
+        }
+    };
+    let mut chunk = [0u8; 1024];
+    while session.received() < session.size() {
+        let len = match writer.read_body(&mut chunk).await {
+            Ok(0) | Err(_) => {
+                write_ota_error(writer, OtaError::Incomplete).await;
+                return;
+            }
+            Ok(len) => len,
+        };
+        if let Err(e) = session.write(&chunk[..len]) {
+            write_ota_error(writer, e).await;
+            return;
+        }
+    }
+
+    match session.finish(&digest) {
+        Ok(_) => restart(writer).await,
+        Err(e) => write_ota_error(writer, e).await,
+    }
+}
+
+async fn write_ota_error(writer: &mut ResponseWriter<'_, '_>, error: OtaError) {
+    log::warn!("[ota] update failed: {error:?}");
+    let (status, message) = match error {
+        OtaError::Busy => (409, "Another update is in progress"),
+        OtaError::TooLarge => (413, "Image is larger than ota partition"),
+        OtaError::InvalidImage => (400, "Not an app image for this chip"),
+        OtaError::VerificationFailed => (422, "Image hash / signature mismatch"),
+        OtaError::Incomplete => (400, "Upload ended early"),
+        OtaError::Interrupted => (409, "Paused transfer was replaced by another update"),
+        OtaError::Partition(_) | OtaError::MissingPartition => (500, "Cannot read ota partitions"),
+        OtaError::Flash(_) => (500, "Cannot write to flash"),
+    };
+    write_error(writer, status, error.code(), message).await;
+}
+
+async fn restart(writer: &mut ResponseWriter<'_, '_>) {
+    log::info!("[ota] restarting...");
+    let res = JsonValue::object().with("status", "restarting");
+    writer.write_json(202, &res).await;
+    Timer::after_millis(1000).await;
+    esp_hal::system::software_reset();
+}
+
+/// `{"running":"ota_0","next":"ota_1","state":"valid"}`
+fn status_json(status: &OtaStatus) -> JsonValue {
+    let state = match status.state {
+        OtaImageState::New => "new",
+        OtaImageState::PendingVerify => "pending_verify",
+        OtaImageState::Valid => "valid",
+        OtaImageState::Invalid => "invalid",
+        OtaImageState::Aborted => "aborted",
+        OtaImageState::Undefined => "undefined",
+    };
+    JsonValue::object()
+        .with("running", partition_name(status.running))
+        .with("next", partition_name(status.next))
+        .with("state", state)
+}
+
+fn partition_name(subtype: AppPartitionSubType) -> alloc::string::String {
+    match subtype {
+        AppPartitionSubType::Factory => "factory".into(),
+        AppPartitionSubType::Test => "test".into(),
+        ota => format!("ota_{}", ota as u8 - AppPartitionSubType::Ota0 as u8),
+    }
+}
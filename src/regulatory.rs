@@ -0,0 +1,100 @@
+use crate::structs::{RegulatorySettings, Result, WmError};
+
+/// Mirror of esp-idf `wifi_country_t`
+///
+/// esp-radio 0.17.0 has no country or tx power api, so the driver functions are
+/// declared here. Layout checked against headers of wifi blobs linked by esp-radio
+/// 0.17.0 (`char cc[3]; uint8_t schan; uint8_t nchan; int8_t max_tx_power;
+/// wifi_country_policy_t policy;`, newer IDF adds 5 GHz `wifi_5g_channel_mask`).
+/// esp-radio is pinned to `=0.17.0` in Cargo.toml because of this, recheck the
+/// layout (and the asserts below) before bumping it.
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct wifi_country_t {
+    cc: [u8; 3],
+    schan: u8,
+    nchan: u8,
+    max_tx_power: i8,
+    policy: u32,
+}
+
+const _: () = assert!(core::mem::size_of::<wifi_country_t>() == 12);
+const _: () = assert!(core::mem::align_of::<wifi_country_t>() == 4);
+const _: () = assert!(core::mem::offset_of!(wifi_country_t, policy) == 8);
+
+/// `WIFI_COUNTRY_POLICY_MANUAL` - always use the configured country info
+const WIFI_COUNTRY_POLICY_MANUAL: u32 = 1;
+
+// `esp_err_t esp_wifi_set_country(const wifi_country_t *)`,
+// `esp_err_t esp_wifi_set_max_tx_power(int8_t)` (esp-radio 0.17.0 blobs)
+extern "C" {
+    fn esp_wifi_set_country(country: *const wifi_country_t) -> i32;
+    fn esp_wifi_set_max_tx_power(power: i8) -> i32;
+}
+
+/// Picks regulatory settings to use.
+///
+/// Country received during provisioning wins, but max tx power from
+/// `WmSettings` is kept as the firmware limit.
+pub fn resolve(
+    provisioned: Option<&RegulatorySettings>,
+    settings: Option<&RegulatorySettings>,
+) -> Option<RegulatorySettings> {
+    match (provisioned, settings) {
+        (Some(provisioned), settings) => {
+            let mut res = provisioned.clone();
+            if res.max_tx_power.is_none() {
+                res.max_tx_power = settings.and_then(|s| s.max_tx_power);
+            }
+            Some(res)
+        }
+        (None, settings) => settings.cloned(),
+    }
+}
+
+/// Sets country code and allowed channel range.
+///
+/// Must be called after wifi driver init (`esp_radio::wifi::new`) and before
+/// scanning or starting the AP.
+pub fn apply_country(settings: &RegulatorySettings) -> Result<()> {
+    let country = wifi_country_t {
+        cc: [settings.country_code[0], settings.country_code[1], b' '],
+        schan: settings.start_channel,
+        nchan: settings.channel_count,
+        max_tx_power: settings.max_tx_power.unwrap_or(84),
+        policy: WIFI_COUNTRY_POLICY_MANUAL,
+    };
+
+    let res = unsafe { esp_wifi_set_country(&country) };
+    if res != 0 {
+        log::error!("esp_wifi_set_country failed: {res}");
+        return Err(WmError::RegulatoryError(res));
+    }
+
+    log::info!(
+        "Wifi country set to {}{} (channels {}-{})",
+        settings.country_code[0] as char,
+        settings.country_code[1] as char,
+        settings.start_channel,
+        settings.start_channel + settings.channel_count - 1
+    );
+    Ok(())
+}
+
+/// Sets max TX power (in 0.25 dBm units).
+///
+/// Driver only accepts this after `start_async()`, so it has to be reapplied
+/// after every radio start.
+pub fn apply_tx_power(settings: &RegulatorySettings) -> Result<()> {
+    let Some(power) = settings.max_tx_power else {
+        return Ok(());
+    };
+
+    let res = unsafe { esp_wifi_set_max_tx_power(power) };
+    if res != 0 {
+        log::error!("esp_wifi_set_max_tx_power failed: {res}");
+        return Err(WmError::RegulatoryError(res));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use alloc::{boxed::Box, rc::Rc, string::String, vec::Vec};
+use core::{future::Future, pin::Pin};
+
+use crate::http_request::{status_text, HttpMethod, HttpRequest};
+use crate::http_server::ResponseWriter;
+
+/// Future returned by http handlers
+pub type HandlerFuture<'a> = Pin<Box<dyn Future<Output = ()> + 'a>>;
+
+/// Async http handler
+///
+/// Implemented for functions like:
+/// ```rust,ignore
+/// fn hello<'a>(req: &'a HttpRequest, res: &'a mut ResponseWriter<'_, '_>) -> HandlerFuture<'a> {
+///     Box::pin(async move { res.write_text_200(b"hello").await })
+/// }
+/// ```
+/// Implement it on own struct if handler needs state.
+pub trait HttpHandler {
+    fn handle<'a>(
+        &'a self,
+        request: &'a HttpRequest,
+        writer: &'a mut ResponseWriter<'_, '_>,
+    ) -> HandlerFuture<'a>;
+
+    /// Returns `true` if handler reads body of this request itself
+    /// (`ResponseWriter::read_body`), for uploads above `HttpLimits::max_body_len`
+    fn streams_body(&self, _request: &HttpRequest) -> bool {
+        false
+    }
+}
+
+impl<F> HttpHandler for F
+where
+    F: for<'a, 'b, 'c> Fn(&'a HttpRequest, &'a mut ResponseWriter<'b, 'c>) -> HandlerFuture<'a>,
+{
+    fn handle<'a>(
+        &'a self,
+        request: &'a HttpRequest,
+        writer: &'a mut ResponseWriter<'_, '_>,
+    ) -> HandlerFuture<'a> {
+        self(request, writer)
+    }
+}
+
+#[derive(Clone)]
+struct Route {
+    method: HttpMethod,
+
+    /// Exact path, or prefix if registered with trailing `*`
+    path: String,
+    prefix: bool,
+    handler: Rc<dyn HttpHandler>,
+}
+
+impl Route {
+    fn matches_path(&self, path: &str) -> bool {
+        if self.prefix {
+            path.starts_with(self.path.as_str())
+        } else {
+            path == self.path
+        }
+    }
+}
+
+/// Routes requests to registered handlers
+///
+/// Used by provisioning server (`WmSettings::http_router`, checked before
+/// built-in pages, so it can override them) and by `serve_http` on any stack.
+/// Cloning is cheap (handlers are shared).
+#[derive(Clone, Default)]
+pub struct HttpRouter {
+    routes: Vec<Route>,
+}
+
+impl HttpRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers handler for method and path
+    ///
+    /// Path ending with `*` matches every path with that prefix (`/static/*`).
+    pub fn route(
+        mut self,
+        method: HttpMethod,
+        path: &str,
+        handler: impl HttpHandler + 'static,
+    ) -> Self {
+        let (path, prefix) = match path.strip_suffix('*') {
+            Some(prefix) => (prefix, true),
+            None => (path, false),
+        };
+        self.routes.push(Route {
+            method,
+            path: String::from(path),
+            prefix,
+            handler: Rc::new(handler),
+        });
+        self
+    }
+
+    pub fn get(self, path: &str, handler: impl HttpHandler + 'static) -> Self {
+        self.route(HttpMethod::Get, path, handler)
+    }
+
+    pub fn post(self, path: &str, handler: impl HttpHandler + 'static) -> Self {
+        self.route(HttpMethod::Post, path, handler)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// Calls matching handler
+    ///
+    /// Path registered only for other methods gets `405 Method Not Allowed`
+    /// (with `Allow` header). Returns `false` if no route matches the path.
+    pub async fn dispatch(
+        &self,
+        request: &HttpRequest,
+        writer: &mut ResponseWriter<'_, '_>,
+    ) -> bool {
+        let mut allowed = String::new();
+        for route in self.routes.iter().filter(|r| r.matches_path(&request.path)) {
+            if route.method == request.method {
+                route.handler.handle(request, writer).await;
+                return true;
+            }
+
+            if !allowed.is_empty() {
+                allowed.push_str(", ");
+            }
+            allowed.push_str(route.method.as_str());
+        }
+
+        if allowed.is_empty() {
+            return false;
+        }
+        writer
+            .write_response_with_headers(
+                405,
+                "text/plain",
+                &[("Allow", allowed.as_str())],
+                status_text(405).as_bytes(),
+            )
+            .await;
+        true
+    }
+}
+
+impl HttpHandler for HttpRouter {
+    /// Dispatches request, unknown paths get `404 Not Found`
+    fn handle<'a>(
+        &'a self,
+        request: &'a HttpRequest,
+        writer: &'a mut ResponseWriter<'_, '_>,
+    ) -> HandlerFuture<'a> {
+        Box::pin(async move {
+            if !self.dispatch(request, writer).await {
+                writer
+                    .write_response(404, "text/plain", status_text(404).as_bytes())
+                    .await;
+            }
+        })
+    }
+}
+
+impl core::fmt::Debug for HttpRouter {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_list()
+            .entries(
+                self.routes
+                    .iter()
+                    .map(|r| (r.method, r.path.as_str(), r.prefix)),
+            )
+            .finish()
+    }
+}
@@ -1,17 +1,35 @@
-use crate::get_efuse_mac;
-use alloc::{rc::Rc, string::String};
-use embassy_executor::SpawnError;
+use crate::{
+    admin::{admin_server, AdminSettings, AdminSite, SharedNvs},
+    assets::{AssetVars, StaticAsset, CUSTOM_ASSETS},
+    auth::{AuthLockout, Authenticator, HttpAuth},
+    form::SetupError,
+    get_efuse_mac,
+    http_request::HttpLimits,
+    http_server::HTTP_MAX_WORKERS,
+    i18n::{Language, CHINESE, ENGLISH},
+    log_capture::{syslog_task, LogCapture, SyslogSettings},
+    ota::Ota,
+    router::HttpRouter,
+    ws::WS_MAX_CLIENTS,
+};
+use alloc::{rc::Rc, string::String, vec::Vec};
+use core::{cell::Cell, net::Ipv4Addr};
+use embassy_executor::{SpawnError, Spawner};
 use embassy_net::Stack;
 use embassy_sync::{
     blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex},
     mutex::Mutex,
     pubsub::PubSubChannel,
     signal::Signal,
+    watch::Watch,
 };
+use embassy_time::Duration;
+use esp_hal::peripherals::WIFI;
 use esp_radio::{
-    wifi::{ClientConfig, ModeConfig, WifiError},
+    wifi::{AuthMethod, ClientConfig, ModeConfig, WifiController, WifiError, WifiStaState},
     Controller, InitializationError,
 };
+use esp_wifi_manager_core::ota::OtaEndpoint;
 
 pub type Result<T> = core::result::Result<T, WmError>;
 
@@ -26,6 +44,12 @@ pub enum WmError {
     TaskSpawnError,
     NvsError(esp_nvs::error::Error),
 
+    /// Wifi driver rejected country / tx power settings (esp-idf error code)
+    RegulatoryError(i32),
+
+    /// `WmSettings` validation failed
+    InvalidSettings(SettingsError),
+
     Other,
 }
 
@@ -59,43 +83,389 @@ impl From<core::convert::Infallible> for WmError {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SettingsError {
+    /// SSID is empty or longer than 32 bytes
+    InvalidSsid,
+
+    /// Duration that must be non zero is zero
+    ZeroDuration,
+
+    /// `wifi_scan_interval` isnt shorter than `esp_reset_timeout`
+    ScanIntervalAboveResetTimeout,
+
+    /// `wifi_conn_timeout` isnt shorter than `esp_reset_timeout`
+    ConnTimeoutAboveResetTimeout,
+
+    /// AP gateway ip / prefix doesnt describe usable private subnet
+    InvalidApSubnet,
+
+    /// Country code, channel range or tx power out of range
+    InvalidRegulatory,
+
+    /// AP password length, auth method, channel or max clients out of range
+    InvalidApConfig,
+
+    /// `http_workers` is zero or above `HTTP_MAX_WORKERS`
+    InvalidHttpWorkers,
+
+    /// `http_limits.max_header_len` too small for typical browser request
+    InvalidHttpLimits,
+
+    /// Scan dwell time zero or above 1500ms, or channel outside 1..=14
+    InvalidScanSettings,
+
+    /// Default language isnt in `WmSettings::languages`
+    InvalidLanguage,
+
+    /// Hostname empty, longer than 32 chars or with chars other than letters, digits and `-`
+    InvalidHostname,
+
+    /// Empty password / token, `:` in basic auth user or zero `max_failures`
+    InvalidAuth,
+
+    /// Ota with `OtaVerify::Sha256` on server with `HttpAuth::None`
+    /// (anybody could upload firmware)
+    UnauthenticatedOta,
+}
+
+/// Ota upload needs http auth or image signature (sha256 alone isnt authentication),
+/// `/api/v1/ota/valid` and `/rollback` are refused per request without http auth
+fn ota_allowed(ota: Option<&Ota>, auth: &HttpAuth) -> bool {
+    ota.is_none_or(|ota| {
+        esp_wifi_manager_core::ota::ota_allowed(
+            OtaEndpoint::Upload,
+            *auth != HttpAuth::None,
+            ota.authenticated(),
+        )
+    })
+}
+
+impl From<SettingsError> for WmError {
+    fn from(value: SettingsError) -> Self {
+        Self::InvalidSettings(value)
+    }
+}
+
+/// Use `WmSettings::builder()` to get validated settings
 #[derive(Clone, Debug)]
 pub struct WmSettings {
     /// SSID and ble name
     pub ssid: String,
 
-    /// Max time WiFi will try to connect (in ms)
-    pub wifi_conn_timeout: u64,
+    /// Max time WiFi will try to connect
+    pub wifi_conn_timeout: Duration,
 
-    /// Delay on wifi reconnection after connection loss (in ms)
-    pub wifi_reconnect_time: u64,
+    /// Delay on wifi reconnection after connection loss
+    pub wifi_reconnect_time: Duration,
 
-    /// WiFi scan inverval (in ms)
-    pub wifi_scan_interval: u64,
+    /// WiFi scan inverval
+    pub wifi_scan_interval: Duration,
 
-    /// Time after which esp will restart while waiting for wifi setup (in ms)
-    pub esp_reset_timeout: Option<u64>,
+    /// Parameters of periodic provisioning scans
+    pub scan: ScanSettings,
+
+    /// Time after which esp will restart while waiting for wifi setup
+    pub esp_reset_timeout: Option<Duration>,
 
     /// Indicates if esp should restart after succesfull first connection
     pub esp_restart_after_connection: bool,
 
-    pub gw_ip_addr_str: String,
+    /// AP (and dhcp server) ip address
+    pub gw_ip_addr: Ipv4Addr,
+
+    /// AP subnet prefix length
+    pub ap_prefix_len: u8,
+
+    /// Country code, channel range and max tx power applied before scanning
+    /// or starting the AP (`None` keeps driver defaults)
+    ///
+    /// Country received during provisioning takes precedence over this
+    pub regulatory: Option<RegulatorySettings>,
+
+    /// Provisioning AP password
+    pub ap_password: ApPassword,
+
+    /// Provisioning AP auth method (ignored for `ApPassword::Open`)
+    pub ap_auth_method: AuthMethod,
+
+    /// Provisioning AP channel
+    pub ap_channel: u8,
+
+    /// Max number of clients connected to provisioning AP (1..=10)
+    pub ap_max_clients: u16,
+
+    /// Hides provisioning AP SSID
+    pub ap_hidden: bool,
+
+    /// Lease time given to provisioning AP clients
+    pub dhcp_lease_time: Duration,
+
+    /// Number of addresses dhcp server can lease (taken from AP subnet,
+    /// right after gateway ip)
+    pub dhcp_pool_size: u8,
+
+    /// DNS server sent to clients (`None` - gateway ip, needed for captive portal)
+    pub dhcp_dns: Option<Ipv4Addr>,
+
+    /// Number of http worker tasks (parallel connections), max `HTTP_MAX_WORKERS`
+    pub http_workers: usize,
+
+    /// Http connection inactivity timeout
+    pub http_timeout: Duration,
+
+    /// Http keep-alive idle timeout (`None` - close connection after every response)
+    pub http_keep_alive: Option<Duration>,
+
+    /// Max http header and body sizes
+    pub http_limits: HttpLimits,
+
+    /// Application pages and endpoints served by provisioning http server
+    /// (checked before built-in ones)
+    pub http_router: HttpRouter,
+
+    /// Authentication of state-changing requests to provisioning server
+    /// (`/setup`, `/api/v1/setup`, application `POST` routes...)
+    pub http_auth: HttpAuth,
+
+    /// Lockout after repeated wrong credentials
+    pub auth_lockout: AuthLockout,
+
+    /// Firmware upload endpoint on provisioning server (`/api/v1/ota`)
+    pub ota: Option<Rc<Ota>>,
+
+    /// Kept log records on provisioning server (`/api/v1/logs`) and BLE
+    pub log_capture: Option<&'static LogCapture>,
+
+    /// Static files served by provisioning http server, checked before
+    /// built-in portal pages (defaults to files from `WM_ASSETS_DIR`)
+    pub assets: &'static [StaticAsset],
+
+    /// Firmware version shown by portal (`{{version}}`), `/api/v1/device` and
+    /// BLE device information service when app descriptor (`esp_app_desc!()`)
+    /// has no version
+    pub firmware_version: String,
+
+    /// Station dhcp hostname (reported by provisioning status)
+    pub hostname: String,
+
+    /// Max time provisioning AP stays up after successful connection,
+    /// waiting for client to fetch the result (`/api/v1/status`, BLE status message)
+    pub provisioning_grace_period: Duration,
+
+    /// Languages of portal pages and BLE status messages (English and Chinese built in)
+    pub languages: Vec<&'static Language>,
+
+    /// Code of language used when `Accept-Language` matches none of `languages`
+    /// (and for BLE until client writes language characteristic)
+    pub default_language: &'static str,
+}
+
+/// Wifi scan parameters
+///
+/// Periodic provisioning scans use `WmSettings::scan`, clients can request
+/// scan with their own parameters (http `/api/v1/scan`, ble scan characteristic).
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct ScanSettings {
+    /// Only listen for beacons (active scan sends probe requests)
+    pub passive: bool,
+
+    /// Time spent on every channel (`None` - driver default)
+    pub dwell_time: Option<Duration>,
+
+    /// Channels to scan, bit `n` is channel `n` (`0` - all channels)
+    pub channels: u16,
+
+    /// Include hidden networks (listed with empty ssid)
+    pub show_hidden: bool,
 }
 
-#[derive(Debug, Clone)]
+impl ScanSettings {
+    /// Max dwell time (longer scan keeps provisioning AP off channel too long)
+    pub const MAX_DWELL_TIME: Duration = Duration::from_millis(1500);
+
+    /// Scans only given channels
+    ///
+    /// Channel outside 1..=14 makes `validate()` fail.
+    pub fn with_channels(mut self, channels: &[u8]) -> Self {
+        self.channels = channels.iter().fold(0, |mask, &ch| match ch {
+            1..=14 => mask | 1 << ch,
+            // bit 0 isnt valid channel
+            _ => mask | 1,
+        });
+        self
+    }
+
+    pub fn validate(&self) -> core::result::Result<(), SettingsError> {
+        if let Some(dwell_time) = self.dwell_time {
+            if dwell_time == Duration::from_ticks(0) || dwell_time > Self::MAX_DWELL_TIME {
+                return Err(SettingsError::InvalidScanSettings);
+            }
+        }
+        // bits 1..=14
+        if self.channels & !0x7FFE != 0 {
+            return Err(SettingsError::InvalidScanSettings);
+        }
+        Ok(())
+    }
+
+    /// Channels selected by `channels` mask (empty if all channels should be scanned)
+    pub(crate) fn channel_list(&self) -> impl Iterator<Item = u8> + '_ {
+        (1..=14u8).filter(|ch| self.channels & (1 << ch) != 0)
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub enum ApPassword {
+    /// Open AP (anyone nearby can connect)
+    Open,
+
+    /// Fixed password (8..=63 chars)
+    Fixed(String),
+
+    /// Random password generated on every boot, it is printed to serial console
+    /// (not through `log`, so `LogCapture` and syslog dont keep it) and passed
+    /// in `ApInfo` (ap start signal), so app can show it on a display
+    RandomPerBoot,
+}
+
+impl core::fmt::Debug for ApPassword {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Open => f.write_str("Open"),
+            // password isnt printed
+            Self::Fixed(_) => f.write_str("Fixed(..)"),
+            Self::RandomPerBoot => f.write_str("RandomPerBoot"),
+        }
+    }
+}
+
+/// Passed through `ap_start_signal` when provisioning starts
+#[derive(Clone)]
+pub struct ApInfo {
+    pub ssid: String,
+
+    /// `None` for open AP (or if `ap` feature is disabled)
+    pub password: Option<String>,
+}
+
+impl core::fmt::Debug for ApInfo {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ApInfo")
+            .field("ssid", &self.ssid)
+            .field("password", &self.password.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegulatorySettings {
+    /// ISO 3166-1 alpha-2 country code (for example `*b"JP"`)
+    pub country_code: [u8; 2],
+
+    /// First allowed 2.4GHz channel
+    pub start_channel: u8,
+
+    /// Number of allowed channels (starting from `start_channel`)
+    pub channel_count: u8,
+
+    /// Max TX power in 0.25 dBm units (8..=84), `None` keeps driver default
+    pub max_tx_power: Option<i8>,
+}
+
+impl RegulatorySettings {
+    /// Settings with channel range commonly allowed in given country
+    ///
+    /// Returns `None` if `country_code` isnt two ascii letters
+    pub fn for_country(country_code: &str) -> Option<Self> {
+        let cc = country_code.as_bytes();
+        if cc.len() != 2 || !cc.iter().all(|c| c.is_ascii_alphabetic()) {
+            return None;
+        }
+        let country_code = [cc[0].to_ascii_uppercase(), cc[1].to_ascii_uppercase()];
+
+        let channel_count = match &country_code {
+            b"JP" => 14,
+            b"US" | b"CA" | b"TW" | b"MX" => 11,
+            _ => 13,
+        };
+
+        Some(Self {
+            country_code,
+            start_channel: 1,
+            channel_count,
+            max_tx_power: None,
+        })
+    }
+
+    pub fn validate(&self) -> core::result::Result<(), SettingsError> {
+        let cc_valid = self.country_code.iter().all(|c| c.is_ascii_uppercase());
+        let last_channel = self.start_channel as u16 + self.channel_count as u16;
+        let channels_valid =
+            self.start_channel >= 1 && self.channel_count >= 1 && last_channel <= 15;
+        let power_valid = self.max_tx_power.is_none_or(|p| (8..=84).contains(&p));
+
+        if !cc_valid || !channels_valid || !power_valid {
+            return Err(SettingsError::InvalidRegulatory);
+        }
+        Ok(())
+    }
+
+    /// Limits max TX power (in dBm)
+    pub fn with_max_tx_power_dbm(mut self, dbm: u8) -> Self {
+        self.max_tx_power = Some((dbm.clamp(2, 21) * 4) as i8);
+        self
+    }
+}
+
+#[derive(Clone)]
 pub(crate) struct AutoSetupSettings {
     pub ssid: String,
     pub psk: String,
+    /// Optional country code sent together with credentials
+    pub country: Option<RegulatorySettings>,
+}
+
+impl core::fmt::Debug for AutoSetupSettings {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // psk isnt printed
+        f.debug_struct("AutoSetupSettings")
+            .field("ssid", &self.ssid)
+            .field("country", &self.country)
+            .finish_non_exhaustive()
+    }
 }
 impl Default for AutoSetupSettings {
     fn default() -> Self {
         Self {
             ssid: String::new(),
             psk: String::new(),
+            country: None,
         }
     }
 }
 impl AutoSetupSettings {
+    /// Validated setup, same rules for http form, json api and BLE
+    pub fn new(
+        ssid: String,
+        psk: String,
+        country: Option<&str>,
+    ) -> core::result::Result<Self, SetupError> {
+        if ssid.is_empty() || ssid.len() > 32 {
+            return Err(SetupError::InvalidSsid);
+        }
+        if !psk.is_empty() && !(8..=64).contains(&psk.len()) {
+            return Err(SetupError::InvalidPsk);
+        }
+        let country = match country {
+            None => None,
+            Some(country) => {
+                Some(RegulatorySettings::for_country(country).ok_or(SetupError::InvalidCountry)?)
+            }
+        };
+        Ok(Self { ssid, psk, country })
+    }
+
     pub fn to_configuration(&self) -> Result<ModeConfig> {
         Ok(ModeConfig::Client(self.to_client_conf()?))
     }
@@ -114,13 +484,376 @@ impl Default for WmSettings {
     fn default() -> Self {
         Self {
             ssid: alloc::format!("ESP-{:X}", get_efuse_mac()),
-            wifi_reconnect_time: 1000,
-            wifi_conn_timeout: 15000,
-            wifi_scan_interval: 15000,
-            esp_reset_timeout: Some(5000),
+            wifi_reconnect_time: Duration::from_millis(1000),
+            wifi_conn_timeout: Duration::from_millis(15000),
+            wifi_scan_interval: Duration::from_millis(15000),
+            scan: ScanSettings::default(),
+            esp_reset_timeout: Some(Duration::from_secs(300)),
             esp_restart_after_connection: true,
-            gw_ip_addr_str: "192.168.4.1".into(),
+            gw_ip_addr: Ipv4Addr::new(192, 168, 4, 1),
+            ap_prefix_len: 24,
+            regulatory: None,
+            ap_password: ApPassword::Open,
+            ap_auth_method: AuthMethod::Wpa2Personal,
+            ap_channel: 1,
+            ap_max_clients: 4,
+            ap_hidden: false,
+            dhcp_lease_time: Duration::from_secs(3600),
+            dhcp_pool_size: 16,
+            dhcp_dns: None,
+            http_workers: 3,
+            http_timeout: Duration::from_secs(10),
+            http_keep_alive: Some(Duration::from_secs(5)),
+            http_limits: HttpLimits::default(),
+            http_router: HttpRouter::new(),
+            http_auth: HttpAuth::None,
+            auth_lockout: AuthLockout::default(),
+            ota: None,
+            log_capture: None,
+            assets: CUSTOM_ASSETS,
+            firmware_version: String::new(),
+            hostname: alloc::format!("esp-{:x}", get_efuse_mac()),
+            provisioning_grace_period: Duration::from_secs(30),
+            languages: alloc::vec![&ENGLISH, &CHINESE],
+            default_language: ENGLISH.code,
+        }
+    }
+}
+
+impl WmSettings {
+    pub fn builder() -> WmSettingsBuilder {
+        WmSettingsBuilder {
+            settings: Self::default(),
+        }
+    }
+
+    /// Checks if settings combination makes sense (called by `init_wm`)
+    pub fn validate(&self) -> core::result::Result<(), SettingsError> {
+        if self.ssid.is_empty() || self.ssid.len() > 32 {
+            return Err(SettingsError::InvalidSsid);
+        }
+
+        if self.wifi_conn_timeout == Duration::from_ticks(0)
+            || self.wifi_scan_interval == Duration::from_ticks(0)
+            || self.http_timeout == Duration::from_ticks(0)
+        {
+            return Err(SettingsError::ZeroDuration);
+        }
+
+        if let Some(reset_timeout) = self.esp_reset_timeout {
+            if self.wifi_scan_interval >= reset_timeout {
+                return Err(SettingsError::ScanIntervalAboveResetTimeout);
+            }
+            if self.wifi_conn_timeout >= reset_timeout {
+                return Err(SettingsError::ConnTimeoutAboveResetTimeout);
+            }
+        }
+
+        if !(8..=30).contains(&self.ap_prefix_len) || !self.gw_ip_addr.is_private() {
+            return Err(SettingsError::InvalidApSubnet);
+        }
+        let ip = u32::from(self.gw_ip_addr);
+        let host_mask = u32::MAX >> self.ap_prefix_len;
+        if ip & host_mask == 0 || ip & host_mask == host_mask {
+            // network or broadcast address
+            return Err(SettingsError::InvalidApSubnet);
+        }
+        if self.dhcp_pool_size == 0 || self.dhcp_pool().is_none() {
+            return Err(SettingsError::InvalidApSubnet);
+        }
+
+        if !(1..=HTTP_MAX_WORKERS).contains(&self.http_workers) {
+            return Err(SettingsError::InvalidHttpWorkers);
+        }
+        if self.http_limits.max_header_len < 256 {
+            return Err(SettingsError::InvalidHttpLimits);
+        }
+
+        self.scan.validate()?;
+
+        if !self.http_auth.validate() || self.auth_lockout.max_failures == 0 {
+            return Err(SettingsError::InvalidAuth);
+        }
+        if !ota_allowed(self.ota.as_deref(), &self.http_auth) {
+            return Err(SettingsError::UnauthenticatedOta);
+        }
+
+        let hostname_valid = (1..=32).contains(&self.hostname.len())
+            && !self.hostname.starts_with('-')
+            && !self.hostname.ends_with('-')
+            && self
+                .hostname
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-');
+        if !hostname_valid {
+            return Err(SettingsError::InvalidHostname);
+        }
+
+        if !self
+            .languages
+            .iter()
+            .any(|language| language.code == self.default_language)
+        {
+            return Err(SettingsError::InvalidLanguage);
         }
+
+        if let Some(ref regulatory) = self.regulatory {
+            regulatory.validate()?;
+            let last_channel = regulatory.start_channel + regulatory.channel_count - 1;
+            if !(regulatory.start_channel..=last_channel).contains(&self.ap_channel) {
+                return Err(SettingsError::InvalidApConfig);
+            }
+        }
+
+        if !(1..=14).contains(&self.ap_channel) || !(1..=10).contains(&self.ap_max_clients) {
+            return Err(SettingsError::InvalidApConfig);
+        }
+        match self.ap_password {
+            ApPassword::Open => {}
+            ApPassword::Fixed(ref password) if !(8..=63).contains(&password.len()) => {
+                return Err(SettingsError::InvalidApConfig);
+            }
+            _ if self.ap_auth_method == AuthMethod::None => {
+                return Err(SettingsError::InvalidApConfig);
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+impl WmSettings {
+    /// AP subnet mask
+    pub(crate) fn ap_netmask(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::MAX << (32 - self.ap_prefix_len as u32))
+    }
+
+    /// First and last address leased by dhcp server
+    ///
+    /// Pool starts right after gateway, or at the start of subnet if gateway
+    /// is too close to the broadcast address. Returns `None` if subnet is too small.
+    pub(crate) fn dhcp_pool(&self) -> Option<(Ipv4Addr, Ipv4Addr)> {
+        let ip = u32::from(self.gw_ip_addr);
+        let netmask = u32::from(self.ap_netmask());
+        let network = ip & netmask;
+        let broadcast = network | !netmask;
+        let size = self.dhcp_pool_size as u32;
+
+        let (start, end) = if ip + size < broadcast {
+            (ip + 1, ip + size)
+        } else if network + size < ip {
+            (network + 1, network + size)
+        } else {
+            return None;
+        };
+
+        Some((Ipv4Addr::from(start), Ipv4Addr::from(end)))
+    }
+}
+
+pub struct WmSettingsBuilder {
+    settings: WmSettings,
+}
+
+impl WmSettingsBuilder {
+    /// AP SSID and ble name
+    pub fn ssid(mut self, ssid: &str) -> Self {
+        self.settings.ssid = ssid.into();
+        self
+    }
+
+    pub fn wifi_conn_timeout(mut self, timeout: Duration) -> Self {
+        self.settings.wifi_conn_timeout = timeout;
+        self
+    }
+
+    pub fn wifi_reconnect_time(mut self, time: Duration) -> Self {
+        self.settings.wifi_reconnect_time = time;
+        self
+    }
+
+    pub fn wifi_scan_interval(mut self, interval: Duration) -> Self {
+        self.settings.wifi_scan_interval = interval;
+        self
+    }
+
+    /// `None` - wait for wifi setup forever
+    pub fn esp_reset_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.settings.esp_reset_timeout = timeout;
+        self
+    }
+
+    pub fn esp_restart_after_connection(mut self, restart: bool) -> Self {
+        self.settings.esp_restart_after_connection = restart;
+        self
+    }
+
+    /// AP ip address and subnet prefix length (for example `192.168.4.1/24`)
+    pub fn ap_ip(mut self, ip: Ipv4Addr, prefix_len: u8) -> Self {
+        self.settings.gw_ip_addr = ip;
+        self.settings.ap_prefix_len = prefix_len;
+        self
+    }
+
+    pub fn regulatory(mut self, regulatory: RegulatorySettings) -> Self {
+        self.settings.regulatory = Some(regulatory);
+        self
+    }
+
+    pub fn ap_password(mut self, password: ApPassword) -> Self {
+        self.settings.ap_password = password;
+        self
+    }
+
+    pub fn ap_auth_method(mut self, auth_method: AuthMethod) -> Self {
+        self.settings.ap_auth_method = auth_method;
+        self
+    }
+
+    pub fn ap_channel(mut self, channel: u8) -> Self {
+        self.settings.ap_channel = channel;
+        self
+    }
+
+    pub fn ap_max_clients(mut self, max_clients: u16) -> Self {
+        self.settings.ap_max_clients = max_clients;
+        self
+    }
+
+    pub fn ap_hidden(mut self, hidden: bool) -> Self {
+        self.settings.ap_hidden = hidden;
+        self
+    }
+
+    pub fn dhcp_lease_time(mut self, lease_time: Duration) -> Self {
+        self.settings.dhcp_lease_time = lease_time;
+        self
+    }
+
+    pub fn dhcp_pool_size(mut self, pool_size: u8) -> Self {
+        self.settings.dhcp_pool_size = pool_size;
+        self
+    }
+
+    /// DNS server sent to clients (default is gateway ip)
+    pub fn dhcp_dns(mut self, dns: Ipv4Addr) -> Self {
+        self.settings.dhcp_dns = Some(dns);
+        self
+    }
+
+    /// Number of parallel http connections (1..=`HTTP_MAX_WORKERS`)
+    pub fn http_workers(mut self, workers: usize) -> Self {
+        self.settings.http_workers = workers;
+        self
+    }
+
+    pub fn http_timeout(mut self, timeout: Duration) -> Self {
+        self.settings.http_timeout = timeout;
+        self
+    }
+
+    /// `None` disables keep-alive
+    pub fn http_keep_alive(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.settings.http_keep_alive = idle_timeout;
+        self
+    }
+
+    pub fn scan_settings(mut self, scan: ScanSettings) -> Self {
+        self.settings.scan = scan;
+        self
+    }
+
+    pub fn http_limits(mut self, limits: HttpLimits) -> Self {
+        self.settings.http_limits = limits;
+        self
+    }
+
+    pub fn http_router(mut self, router: HttpRouter) -> Self {
+        self.settings.http_router = router;
+        self
+    }
+
+    /// Credentials required by state-changing requests (`HttpAuth::per_device`
+    /// derives password from mac)
+    pub fn http_auth(mut self, auth: HttpAuth) -> Self {
+        self.settings.http_auth = auth;
+        self
+    }
+
+    pub fn auth_lockout(mut self, lockout: AuthLockout) -> Self {
+        self.settings.auth_lockout = lockout;
+        self
+    }
+
+    /// Enables firmware upload on provisioning server
+    pub fn ota(mut self, ota: Rc<Ota>) -> Self {
+        self.settings.ota = Some(ota);
+        self
+    }
+
+    /// Makes records kept by `LogCapture` readable over http and BLE
+    pub fn log_capture(mut self, capture: &'static LogCapture) -> Self {
+        self.settings.log_capture = Some(capture);
+        self
+    }
+
+    /// Own static files (branding), see `StaticAsset`
+    pub fn assets(mut self, assets: &'static [StaticAsset]) -> Self {
+        self.settings.assets = assets;
+        self
+    }
+
+    pub fn firmware_version(mut self, version: &str) -> Self {
+        self.settings.firmware_version = version.into();
+        self
+    }
+
+    /// Station dhcp hostname (letters, digits and `-`, max 32 chars)
+    pub fn hostname(mut self, hostname: &str) -> Self {
+        self.settings.hostname = hostname.into();
+        self
+    }
+
+    pub fn provisioning_grace_period(mut self, grace_period: Duration) -> Self {
+        self.settings.provisioning_grace_period = grace_period;
+        self
+    }
+
+    /// Adds language (replaces one with the same code)
+    pub fn language(mut self, language: &'static Language) -> Self {
+        self.settings.languages.retain(|l| l.code != language.code);
+        self.settings.languages.push(language);
+        self
+    }
+
+    pub fn default_language(mut self, code: &'static str) -> Self {
+        self.settings.default_language = code;
+        self
+    }
+
+    pub fn build(self) -> Result<WmSettings> {
+        self.settings.validate()?;
+        Ok(self.settings)
+    }
+}
+
+/// Access point found by wifi scan
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanResult {
+    pub ssid: String,
+
+    /// Bssid of strongest access point with this ssid
+    pub bssid: [u8; 6],
+    pub channel: u8,
+    pub rssi: i8,
+    pub auth_method: Option<AuthMethod>,
+}
+
+impl ScanResult {
+    /// Network doesnt require password
+    pub fn is_open(&self) -> bool {
+        matches!(self.auth_method, None | Some(AuthMethod::None))
     }
 }
 
@@ -131,6 +864,12 @@ pub struct WmReturn {
     pub ssid: String,
     pub password: String,
     pub(crate) stop_signal: Rc<Signal<CriticalSectionRawMutex, bool>>,
+    pub(crate) task_signals: Rc<WmTaskSignals>,
+
+    /// Values for admin pages
+    pub(crate) asset_vars: AssetVars,
+    /// `false` if radio was initialized by application (`init_wm_with_controller`)
+    pub(crate) owns_radio: bool,
 }
 
 impl WmReturn {
@@ -144,56 +883,525 @@ impl WmReturn {
     pub fn restart_radio(&self) {
         self.stop_signal.signal(false);
     }
+
+    /// Stops all wifimanager tasks, deinitializes wifi and ble and returns
+    /// radio peripherals (so `init_wm` can be called again)
+    ///
+    /// Returns `None` if radio was initialized by application
+    /// (`init_wm_with_controller`), use `release()` to get controller back instead.
+    ///
+    /// `sta_stack` (and all its copies) must not be used after this.
+    /// `wifi_init` stays valid, it is reused by next `init_wm` call.
+    pub async fn deinit(self) -> Option<RadioPeripherals> {
+        let controller = self.stop_tasks().await;
+        // dropping controller deinitializes wifi
+        drop(controller);
+        log::info!("Wifimanager deinitialized");
+
+        // drivers that owned them are dropped now (ble task stopped in `stop_tasks`)
+        self.owns_radio
+            .then(|| unsafe { RadioPeripherals::steal() })
+    }
+
+    /// Stops all wifimanager tasks and returns wifi controller (stopped)
+    /// without deinitializing it
+    pub async fn release(self) -> WifiController<'static> {
+        self.stop_tasks().await
+    }
+
+    /// Scans for access points (site survey) while connected
+    ///
+    /// Scan is performed by connection task, it fails with `WifiError::Disconnected`
+    /// when wifi isnt connected (or radio is stopped). Results are deduplicated
+    /// by ssid and sorted by rssi.
+    pub async fn scan(&self) -> Result<Vec<ScanResult>> {
+        self.scan_with_settings(ScanSettings::default()).await
+    }
+
+    /// Same as `scan()`, with custom scan parameters
+    pub async fn scan_with_settings(&self, settings: ScanSettings) -> Result<Vec<ScanResult>> {
+        settings.validate()?;
+        Ok(self.task_signals.scan(settings).await?)
+    }
+
+    /// Starts admin server on station network (`sta_stack`)
+    ///
+    /// Admin page (`/`) shows connection info and saved networks, allows
+    /// adding/forgetting networks and restarting into provisioning.
+    /// `nvs` is shared with application (use `SharedNvs` for own access too).
+    /// Workers stop on `deinit()` / `release()`.
+    pub fn start_admin(
+        &self,
+        spawner: &Spawner,
+        nvs: SharedNvs,
+        settings: AdminSettings,
+    ) -> Result<()> {
+        if !(1..=HTTP_MAX_WORKERS).contains(&settings.workers) {
+            return Err(SettingsError::InvalidHttpWorkers.into());
+        }
+        if !settings.auth.validate() || settings.auth_lockout.max_failures == 0 {
+            return Err(SettingsError::InvalidAuth.into());
+        }
+        if !ota_allowed(settings.ota.as_deref(), &settings.auth) {
+            return Err(SettingsError::UnauthenticatedOta.into());
+        }
+
+        let site = Rc::new(AdminSite {
+            stack: self.sta_stack,
+            nvs,
+            ssid: self.ssid.clone(),
+            task_signals: self.task_signals.clone(),
+            router: settings.router,
+            vars: self.asset_vars.clone(),
+            auth: Authenticator::new(
+                settings.auth,
+                settings.auth_lockout,
+                &self.asset_vars.hostname,
+            ),
+            ota: settings.ota,
+            log_capture: settings.log_capture,
+        });
+        for worker_id in 0..settings.workers {
+            spawner.spawn(admin_server(
+                worker_id,
+                settings.port,
+                settings.http,
+                site.clone(),
+            ))?;
+            self.task_signals
+                .admin_workers
+                .set(self.task_signals.admin_workers.get() + 1);
+        }
+        log::info!(
+            "Admin server on http://{}:{}/",
+            Ipv4Addr::from(self.ip_address),
+            settings.port
+        );
+        Ok(())
+    }
+
+    /// Forwards records kept by `capture` (including earlier ones) to syslog collector
+    pub fn start_syslog(
+        &self,
+        spawner: &Spawner,
+        capture: &'static LogCapture,
+        settings: SyslogSettings,
+    ) -> Result<()> {
+        spawner.spawn(syslog_task(
+            self.sta_stack,
+            capture,
+            settings,
+            self.asset_vars.hostname.clone(),
+            self.task_signals.clone(),
+        ))?;
+        self.task_signals.syslog_running.set(true);
+        Ok(())
+    }
+
+    async fn stop_tasks(&self) -> WifiController<'static> {
+        // admin server and syslog use station stack
+        self.task_signals.stop_admin().await;
+        self.task_signals.stop_syslog().await;
+
+        // stack runner has to stop before wifi driver is deinitialized
+        self.task_signals.shutdown_sta.signal(());
+        self.task_signals.sta_stopped.wait().await;
+
+        self.task_signals.shutdown_connection.signal(());
+        let controller = self.task_signals.controller_return.wait().await;
+
+        self.task_signals.ble_stopped.wait().await;
+        controller
+    }
+}
+
+/// Wifi interfaces not used by wifimanager (returned by `init_wm_with_controller`)
+pub struct UnusedInterfaces {
+    /// Ap interface is only used for provisioning with `ap` feature
+    #[cfg(not(feature = "ap"))]
+    pub ap: esp_radio::wifi::WifiDevice<'static>,
+
+    #[cfg(feature = "esp-now")]
+    pub esp_now: esp_radio::esp_now::EspNow<'static>,
+}
+
+/// Radio peripherals returned by `WmReturn::deinit()`
+pub struct RadioPeripherals {
+    pub wifi: WIFI<'static>,
+    #[cfg(feature = "ble")]
+    pub bt: esp_hal::peripherals::BT<'static>,
+}
+
+impl RadioPeripherals {
+    /// # Safety
+    ///
+    /// Wifi/ble drivers created from original peripherals must be dropped.
+    unsafe fn steal() -> Self {
+        unsafe {
+            Self {
+                wifi: WIFI::steal(),
+                #[cfg(feature = "ble")]
+                bt: esp_hal::peripherals::BT::steal(),
+            }
+        }
+    }
 }
 
 impl ::core::fmt::Debug for WmReturn {
     #[inline]
     fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        // password isnt printed
         f.debug_struct("WmReturn")
             .field("ssid", &self.ssid)
-            .field("password", &self.password)
             .field("ip_address", &self.ip_address)
-            .finish()
+            .finish_non_exhaustive()
     }
 }
 
+/// Signals used to stop tasks spawned by `init_wm` (see `WmReturn::deinit()`)
+pub(crate) struct WmTaskSignals {
+    pub shutdown_connection: Signal<CriticalSectionRawMutex, ()>,
+    pub controller_return: Signal<CriticalSectionRawMutex, WifiController<'static>>,
+
+    pub shutdown_sta: Signal<CriticalSectionRawMutex, ()>,
+    pub sta_stopped: Signal<CriticalSectionRawMutex, ()>,
+
+    /// Signalled when ble task ends (or immediately if it wasnt started)
+    pub ble_stopped: Signal<CriticalSectionRawMutex, ()>,
+
+    /// Scan request (`scan()`) tagged with its id, handled by connection task
+    pub scan_request: Signal<CriticalSectionRawMutex, (u32, ScanSettings)>,
+    pub scan_done:
+        Signal<CriticalSectionRawMutex, (u32, core::result::Result<Vec<ScanResult>, WifiError>)>,
+    scan_lock: Mutex<CriticalSectionRawMutex, ()>,
+    scan_id: Cell<u32>,
+
+    /// Number of running admin server workers (`WmReturn::start_admin`)
+    pub admin_workers: Cell<usize>,
+    pub admin_stopped: Signal<CriticalSectionRawMutex, ()>,
+    admin_shutdown: Cell<bool>,
+    admin_shutdown_pubsub: PubSubChannel<CriticalSectionRawMutex, (), 1, HTTP_MAX_WORKERS, 1>,
+
+    /// Syslog forwarding (`WmReturn::start_syslog`)
+    pub syslog_running: Cell<bool>,
+    pub shutdown_syslog: Signal<CriticalSectionRawMutex, ()>,
+    pub syslog_stopped: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl WmTaskSignals {
+    pub fn new() -> Self {
+        Self {
+            shutdown_connection: Signal::new(),
+            controller_return: Signal::new(),
+            shutdown_sta: Signal::new(),
+            sta_stopped: Signal::new(),
+            ble_stopped: Signal::new(),
+            scan_request: Signal::new(),
+            scan_done: Signal::new(),
+            scan_lock: Mutex::new(()),
+            scan_id: Cell::new(0),
+            admin_workers: Cell::new(0),
+            admin_stopped: Signal::new(),
+            admin_shutdown: Cell::new(false),
+            admin_shutdown_pubsub: PubSubChannel::new(),
+            syslog_running: Cell::new(false),
+            shutdown_syslog: Signal::new(),
+            syslog_stopped: Signal::new(),
+        }
+    }
+
+    /// Runs scan on connection task (`WmReturn::scan()`, admin `/api/v1/scan`)
+    ///
+    /// Requests run one at a time. Results carry request id, so result of
+    /// request abandoned by its caller (timeout) isnt taken by the next one.
+    /// Connection task answers requests with `WifiError::Disconnected` while
+    /// it isnt connected.
+    pub async fn scan(
+        &self,
+        settings: ScanSettings,
+    ) -> core::result::Result<Vec<ScanResult>, WifiError> {
+        let _lock = self.scan_lock.lock().await;
+        if esp_radio::wifi::sta_state() != WifiStaState::Connected {
+            return Err(WifiError::Disconnected);
+        }
+        let id = self.scan_id.get().wrapping_add(1);
+        self.scan_id.set(id);
+        self.scan_request.signal((id, settings));
+        loop {
+            let (done_id, res) = self.scan_done.wait().await;
+            if done_id == id {
+                return res;
+            }
+        }
+    }
+
+    /// Waits until admin server has to stop
+    pub async fn admin_shutdown_requested(&self) {
+        let mut subscriber = self
+            .admin_shutdown_pubsub
+            .subscriber()
+            .expect("Shouldnt fail getting subscriber");
+        // worker which didnt run yet missed the message
+        if self.admin_shutdown.get() {
+            return;
+        }
+        subscriber.next_message_pure().await;
+    }
+
+    /// Stops admin server workers and waits for them
+    pub async fn stop_admin(&self) {
+        if self.admin_workers.get() == 0 {
+            return;
+        }
+        self.admin_shutdown.set(true);
+        self.admin_shutdown_pubsub
+            .publisher()
+            .expect("Shouldnt fail getting publisher")
+            .publish_immediate(());
+        self.admin_stopped.wait().await;
+    }
+
+    /// Stops syslog forwarding and waits for it
+    pub async fn stop_syslog(&self) {
+        if !self.syslog_running.replace(false) {
+            return;
+        }
+        self.shutdown_syslog.signal(());
+        self.syslog_stopped.wait().await;
+    }
+
+    pub fn admin_worker_stopped(&self) {
+        let workers = self.admin_workers.get().saturating_sub(1);
+        self.admin_workers.set(workers);
+        if workers == 0 {
+            self.admin_stopped.signal(());
+        }
+    }
+}
+
+/// State of connection attempt with provisioned credentials
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ConnState {
+    /// Waiting for credentials
+    Idle,
+
+    /// Credentials received, connection attempt not started yet
+    Pending,
+    Connecting,
+
+    /// Associated, waiting for dhcp address
+    ObtainingIp,
+
+    /// Connected with given station ip
+    Connected([u8; 4]),
+
+    /// Last attempt failed, waiting for new credentials
+    Failed(FailReason),
+}
+
+impl ConnState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Idle => "idle",
+            Self::Pending => "pending",
+            Self::Connecting => "connecting",
+            Self::ObtainingIp => "obtaining_ip",
+            Self::Connected(_) => "connected",
+            Self::Failed(_) => "failed",
+        }
+    }
+}
+
+/// Why provisioning connection attempt failed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum FailReason {
+    /// Network wasnt in last scan results
+    NetworkNotFound,
+
+    /// Association failed (usually wrong password)
+    ConnectFailed,
+
+    /// Dhcp didnt give address in time
+    NoIp,
+
+    /// Wifi driver rejected country / tx power
+    CountryRejected,
+
+    /// Wifi driver rejected ssid / password
+    ConfigRejected,
+}
+
+impl FailReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::NetworkNotFound => "network_not_found",
+            Self::ConnectFailed => "connect_failed",
+            Self::NoIp => "no_ip",
+            Self::CountryRejected => "country_rejected",
+            Self::ConfigRejected => "config_rejected",
+        }
+    }
+
+    /// Key of localized description
+    pub fn text_key(&self) -> &'static str {
+        match self {
+            Self::NetworkNotFound => "fail_network_not_found",
+            Self::ConnectFailed => "fail_connect_failed",
+            Self::NoIp => "fail_no_ip",
+            Self::CountryRejected => "fail_country_rejected",
+            Self::ConfigRejected => "fail_config_rejected",
+        }
+    }
+}
+
+/// Change pushed to websocket clients (they read current state)
+#[derive(Clone, Copy, Debug)]
+pub enum WmEvent {
+    /// Provisioning status or scanning flag changed
+    Status,
+
+    /// New scan results
+    Scan,
+}
+
 pub struct WmInnerSignals {
-    pub wifi_scan_res: Mutex<NoopRawMutex, alloc::string::String>,
-    pub gw_ip_addr_str: Mutex<NoopRawMutex, alloc::string::String>,
+    /// Last provisioning scan (deduplicated, sorted by rssi)
+    pub wifi_scan_res: Mutex<NoopRawMutex, Vec<ScanResult>>,
     /// This is used to tell main task to connect to wifi
     pub wifi_conn_info_sig: Signal<NoopRawMutex, AutoSetupSettings>,
 
-    /// This is used to tell ble task about conn result (return signal)
-    pub wifi_conn_res_sig: Signal<NoopRawMutex, bool>,
+    /// Client fetched final provisioning result (AP can be stopped)
+    pub result_fetched: Signal<NoopRawMutex, ()>,
+
+    /// Set when wifi connection succeeded (captive portal probes get "online" answer)
+    pub provisioned: Cell<bool>,
+
+    /// Provisioning connection state (reported by status api)
+    pub conn_state: Cell<ConnState>,
+
+    /// Scan requested by client (http / ble), handled by connection worker
+    pub scan_request_sig: Signal<NoopRawMutex, ScanSettings>,
+
+    /// Set while provisioning scan is running
+    pub scanning: Cell<bool>,
+
+    /// Published when scan ends (`false` - aborted because credentials arrived)
+    scan_done_pubsub: PubSubChannel<NoopRawMutex, bool, 1, 8, 1>,
+
+    /// State changes pushed to websocket clients
+    pub events: PubSubChannel<NoopRawMutex, WmEvent, 4, WS_MAX_CLIENTS, 1>,
+
+    /// Websocket clients that can still connect (set by http server)
+    pub ws_slots: Cell<usize>,
+
+    /// Latched, so tasks started (or waiting) after the end still see it
+    end: Watch<NoopRawMutex, (), END_WAITERS>,
+
+    /// Running tasks using ap stack (`ApTaskGuard`)
+    ap_tasks: Cell<usize>,
+
+    /// Signalled when last `ApTaskGuard` is dropped
+    ap_tasks_stopped: Signal<NoopRawMutex, ()>,
+}
+
+/// Tasks waiting for end: http workers, dns, dhcp, ap runner and ble
+const END_WAITERS: usize = HTTP_MAX_WORKERS + 4;
 
-    end_signal_pubsub: PubSubChannel<NoopRawMutex, (), 1, 16, 1>,
+/// Counts task as ap stack user while alive
+pub struct ApTaskGuard<'a>(&'a WmInnerSignals);
+
+impl Drop for ApTaskGuard<'_> {
+    fn drop(&mut self) {
+        let running = self.0.ap_tasks.get() - 1;
+        self.0.ap_tasks.set(running);
+        if running == 0 {
+            self.0.ap_tasks_stopped.signal(());
+        }
+    }
 }
 
 impl WmInnerSignals {
     pub fn new() -> Self {
         Self {
-            wifi_scan_res: Mutex::new(alloc::string::String::new()),
-            gw_ip_addr_str: Mutex::new(alloc::string::String::new()),
+            wifi_scan_res: Mutex::new(Vec::new()),
             wifi_conn_info_sig: Signal::new(),
-            wifi_conn_res_sig: Signal::new(),
-            end_signal_pubsub: PubSubChannel::new(),
+            result_fetched: Signal::new(),
+            provisioned: Cell::new(false),
+            conn_state: Cell::new(ConnState::Idle),
+            scan_request_sig: Signal::new(),
+            scanning: Cell::new(false),
+            scan_done_pubsub: PubSubChannel::new(),
+            events: PubSubChannel::new(),
+            ws_slots: Cell::new(0),
+            end: Watch::new(),
+            ap_tasks: Cell::new(0),
+            ap_tasks_stopped: Signal::new(),
         }
     }
 
-    /// Wait for end signal
-    #[allow(dead_code)]
+    /// Wait for end signal (up to `END_WAITERS` tasks at once)
     pub async fn end_signalled(&self) {
-        self.end_signal_pubsub
-            .subscriber()
-            .expect("Shouldnt fail getting subscriber")
-            .next_message_pure()
-            .await;
+        let mut receiver = self.end.receiver().expect("Shouldnt fail getting receiver");
+        receiver.get().await
+    }
+
+    /// Passes credentials to connection worker
+    pub fn submit_credentials(&self, setup: AutoSetupSettings) {
+        self.set_conn_state(ConnState::Pending);
+        self.wifi_conn_info_sig.signal(setup);
+    }
+
+    pub fn set_conn_state(&self, state: ConnState) {
+        self.conn_state.set(state);
+        self.publish(WmEvent::Status);
+    }
+
+    pub fn signal_scan_started(&self) {
+        self.scanning.set(true);
+        self.publish(WmEvent::Status);
+    }
+
+    fn publish(&self, event: WmEvent) {
+        self.events.immediate_publisher().publish_immediate(event);
+    }
+
+    /// Requests scan and waits until it ends
+    ///
+    /// Returns `false` if scan was aborted (or too many clients are waiting),
+    /// `wifi_scan_res` keeps previous results then.
+    pub async fn scan(&self, settings: ScanSettings) -> bool {
+        let Ok(mut subscriber) = self.scan_done_pubsub.subscriber() else {
+            return false;
+        };
+        self.scan_request_sig.signal(settings);
+        subscriber.next_message_pure().await
     }
 
-    pub fn signal_end(&self) {
-        self.end_signal_pubsub
+    pub fn signal_scan_done(&self, completed: bool) {
+        self.scanning.set(false);
+        self.scan_done_pubsub
             .publisher()
             .expect("Shouldnt fail getting publisher")
-            .publish_immediate(());
+            .publish_immediate(completed);
+        if completed {
+            self.publish(WmEvent::Scan);
+        }
+        self.publish(WmEvent::Status);
+    }
+
+    /// Held by tasks using ap stack, so `signal_end` can wait for them
+    pub fn ap_task_running(&self) -> ApTaskGuard<'_> {
+        self.ap_tasks.set(self.ap_tasks.get() + 1);
+        ApTaskGuard(self)
+    }
+
+    /// Signals end and waits until tasks using ap stack stopped
+    /// (its resources are reused by next provisioning)
+    pub async fn signal_end(&self) {
+        self.ap_tasks_stopped.reset();
+        self.end.sender().send(());
+        while self.ap_tasks.get() > 0 {
+            self.ap_tasks_stopped.wait().await;
+        }
     }
 }
@@ -1,12 +1,74 @@
-use crate::{structs::WmInnerSignals, Result, WmSettings};
-use alloc::rc::Rc;
-use core::{net::Ipv4Addr, str::FromStr};
+use crate::{structs::WmInnerSignals, Result, ScanResult, ScanSettings, WmSettings};
+use alloc::{boxed::Box, rc::Rc, string::String, vec::Vec};
 use embassy_executor::Spawner;
 use embassy_net::Stack;
 #[cfg(feature = "ap")]
 use embassy_net::{Config, Ipv4Cidr, StackResources, StaticConfigV4};
 use embassy_time::{with_timeout, Duration, Timer};
-use esp_radio::wifi::{WifiController, WifiDevice};
+use esp_radio::wifi::{
+    AccessPointInfo, ScanConfig, ScanTypeConfig, WifiController, WifiDevice, WifiError,
+};
+use portable_atomic::{AtomicPtr, Ordering};
+
+/// Password for provisioning AP (generates random one if configured)
+#[cfg(feature = "ap")]
+pub fn ap_password(settings: &WmSettings, rng: &mut esp_hal::rng::Rng) -> Option<String> {
+    match settings.ap_password {
+        crate::ApPassword::Open => None,
+        crate::ApPassword::Fixed(ref password) => Some(password.clone()),
+        crate::ApPassword::RandomPerBoot => {
+            // without easily confused chars (0/O, 1/l/I)
+            const CHARSET: &[u8] = b"abcdefghijkmnpqrstuvwxyz23456789";
+            let password: String = (0..10)
+                .map(|_| CHARSET[rng.random() as usize % CHARSET.len()] as char)
+                .collect();
+            // printed to serial only, `LogCapture` / syslog dont get it
+            esp_println::println!("Generated AP password: {password}");
+            Some(password)
+        }
+    }
+}
+
+#[cfg(feature = "ap")]
+pub fn ap_config(
+    settings: &WmSettings,
+    password: Option<String>,
+) -> esp_radio::wifi::AccessPointConfig {
+    let config = esp_radio::wifi::AccessPointConfig::default()
+        .with_ssid(settings.ssid.clone())
+        .with_ssid_hidden(settings.ap_hidden)
+        .with_channel(settings.ap_channel)
+        .with_max_connections(settings.ap_max_clients);
+
+    match password {
+        Some(password) => config
+            .with_auth_method(settings.ap_auth_method)
+            .with_password(password),
+        None => config.with_auth_method(esp_radio::wifi::AuthMethod::None),
+    }
+}
+
+/// Ap stack resources, reused by every provisioning
+#[cfg(feature = "ap")]
+static AP_RESOURCES: AtomicPtr<StackResources<6>> = AtomicPtr::new(core::ptr::null_mut());
+
+/// `value` stored in allocation made by first call with `slot` (so nothing leaks
+/// when `init_wm` is called again after `deinit()`)
+///
+/// # Safety
+///
+/// Reference returned by previous call with `slot` must not be used anymore.
+pub(crate) unsafe fn reused<T>(slot: &AtomicPtr<T>, value: T) -> &'static mut T {
+    let ptr = slot.load(Ordering::Acquire);
+    if ptr.is_null() {
+        let ptr = Box::into_raw(Box::new(value));
+        slot.store(ptr, Ordering::Release);
+        return unsafe { &mut *ptr };
+    }
+    let reused = unsafe { &mut *ptr };
+    *reused = value;
+    reused
+}
 
 #[cfg(feature = "ap")]
 pub async fn spawn_ap(
@@ -16,58 +78,47 @@ pub async fn spawn_ap(
     settings: WmSettings,
     ap_interface: WifiDevice<'static>,
 ) -> Result<()> {
-    let ap_ip =
-        Ipv4Addr::from_str(settings.gw_ip_addr_str.as_str()).expect("failed to parse gateway ip");
-    // let ap_ip = embassy_net::Ipv4Address::new(192, 168, 4, 1);
+    let ap_ip = settings.gw_ip_addr;
     let ap_ip_config = Config::ipv4_static(StaticConfigV4 {
-        address: Ipv4Cidr::new(ap_ip, 24),
+        address: Ipv4Cidr::new(ap_ip, settings.ap_prefix_len),
         gateway: Some(ap_ip),
         dns_servers: Default::default(),
     });
 
-    let mut gw_ip_addr_str = wm_signals.gw_ip_addr_str.lock().await;
-    gw_ip_addr_str.clear();
-    gw_ip_addr_str.push_str(settings.gw_ip_addr_str.as_str());
-    log::info!("AP IP: {}", gw_ip_addr_str);
-
-    let (ap_stack, ap_runner) = embassy_net::new(
-        ap_interface,
-        ap_ip_config,
-        {
-            static STATIC_CELL: static_cell::StaticCell<StackResources<6>> =
-                static_cell::StaticCell::new();
-            STATIC_CELL.uninit().write(StackResources::<6>::new())
-        },
-        rng.random() as u64,
-    );
+    // ap tasks of previous provisioning stopped before it returned (`WmInnerSignals::signal_end`)
+    let resources = unsafe { reused(&AP_RESOURCES, StackResources::new()) };
+    let (ap_stack, ap_runner) =
+        embassy_net::new(ap_interface, ap_ip_config, resources, rng.random() as u64);
     log::info!("AP IP: {}", ap_ip);
     spawner.spawn(crate::ap::ap_task(ap_runner, wm_signals.clone()))?;
-    log::info!("dhcp server: {}", ap_ip);
-    spawner.spawn(crate::ap::run_dhcp_server(ap_stack))?;
-    log::info!("http server: {}", ap_ip);
-    crate::http::run_http_server(spawner, ap_stack, wm_signals.clone()).await;
+    spawner.spawn(crate::ap::run_dhcp_server(
+        ap_stack,
+        settings.clone(),
+        wm_signals.clone(),
+    ))?;
+    spawner.spawn(crate::dns::run_dns_server(
+        ap_stack,
+        ap_ip,
+        wm_signals.clone(),
+    ))?;
+    crate::http::run_http_server(spawner, ap_stack, wm_signals.clone(), &settings).await?;
 
     Ok(())
 }
 
 pub async fn try_to_wifi_connect(
     controller: &mut WifiController<'static>,
-    wifi_conn_timeout: u64,
+    wifi_conn_timeout: Duration,
 ) -> bool {
     let start_time = embassy_time::Instant::now();
 
     loop {
-        if start_time.elapsed().as_millis() > wifi_conn_timeout {
+        if start_time.elapsed() > wifi_conn_timeout {
             log::warn!("Connect timeout (1)!");
             return false;
         }
 
-        match with_timeout(
-            Duration::from_millis(wifi_conn_timeout),
-            controller.connect_async(),
-        )
-        .await
-        {
+        match with_timeout(wifi_conn_timeout, controller.connect_async()).await {
             Ok(res) => match res {
                 Ok(_) => {
                     log::info!("Wifi connected!");
@@ -109,3 +160,107 @@ pub fn get_efuse_mac() -> u64 {
         .iter()
         .fold(0u64, |acc, &x| (acc << 8) + x as u64)
 }
+
+/// `AA:BB:CC:DD:EE:FF`
+pub(crate) fn mac_str(mac: &[u8]) -> String {
+    let mut out = String::with_capacity(17);
+    for (i, b) in mac.iter().enumerate() {
+        if i > 0 {
+            out.push(':');
+        }
+        _ = core::fmt::write(&mut out, format_args!("{b:02X}"));
+    }
+    out
+}
+
+// esp-radio 0.17.0 has no api to stop scan (dropping scan future leaves it running),
+// so driver function is declared here: `esp_err_t esp_wifi_scan_stop(void)` from
+// wifi blobs linked by esp-radio 0.17.0 (pinned in Cargo.toml). Recheck it when updating esp-radio.
+extern "C" {
+    fn esp_wifi_scan_stop() -> i32;
+}
+
+/// Runs scan with given parameters
+///
+/// Channel subset is scanned channel by channel (driver scans either one or all channels).
+pub async fn scan(
+    controller: &mut WifiController<'static>,
+    settings: &ScanSettings,
+) -> core::result::Result<Vec<ScanResult>, WifiError> {
+    let dwell_time = settings
+        .dwell_time
+        .map(|d| core::time::Duration::from_micros(d.as_micros()));
+    let scan_type = match (settings.passive, dwell_time) {
+        (true, Some(dwell_time)) => ScanTypeConfig::Passive(dwell_time),
+        // esp-idf default passive dwell time
+        (true, None) => ScanTypeConfig::Passive(core::time::Duration::from_millis(360)),
+        (false, Some(dwell_time)) => ScanTypeConfig::Active {
+            min: dwell_time,
+            max: dwell_time,
+        },
+        (false, None) => ScanTypeConfig::default(),
+    };
+    let config = ScanConfig::default()
+        .with_show_hidden(settings.show_hidden)
+        .with_scan_type(scan_type);
+
+    let aps = if settings.channels == 0 {
+        controller.scan_with_config_async(config).await?
+    } else {
+        let mut aps = Vec::new();
+        for channel in settings.channel_list() {
+            let config = config.with_channel(channel);
+            aps.extend(controller.scan_with_config_async(config).await?);
+        }
+        aps
+    };
+
+    Ok(scan_results(aps, settings.show_hidden))
+}
+
+/// Stops scan abandoned in the middle (its future was dropped)
+pub fn abort_scan() {
+    // returns error only if wifi isnt started
+    _ = unsafe { esp_wifi_scan_stop() };
+}
+
+/// Converts raw scan to list of networks
+///
+/// Every ssid is listed once (with its strongest bssid), sorted from strongest.
+/// Hidden networks (empty ssid) are skipped unless `show_hidden` is set,
+/// then they are listed per bssid.
+pub fn scan_results(aps: Vec<AccessPointInfo>, show_hidden: bool) -> Vec<ScanResult> {
+    let mut results: Vec<ScanResult> = Vec::with_capacity(aps.len());
+    for ap in aps {
+        if ap.ssid.is_empty() && !show_hidden {
+            continue;
+        }
+
+        let same_network = |r: &&mut ScanResult| {
+            if ap.ssid.is_empty() {
+                r.ssid.is_empty() && r.bssid == ap.bssid
+            } else {
+                r.ssid == ap.ssid
+            }
+        };
+        match results.iter_mut().find(same_network) {
+            Some(r) if r.rssi >= ap.signal_strength => {}
+            Some(r) => {
+                r.bssid = ap.bssid;
+                r.channel = ap.channel;
+                r.rssi = ap.signal_strength;
+                r.auth_method = ap.auth_method;
+            }
+            None => results.push(ScanResult {
+                ssid: ap.ssid,
+                bssid: ap.bssid,
+                channel: ap.channel,
+                rssi: ap.signal_strength,
+                auth_method: ap.auth_method,
+            }),
+        }
+    }
+
+    results.sort_unstable_by(|a, b| b.rssi.cmp(&a.rssi));
+    results
+}
@@ -0,0 +1,207 @@
+use alloc::{rc::Rc, string::ToString, vec::Vec};
+use embassy_futures::select::{select3, Either3};
+use embassy_net::tcp::TcpWriter;
+use embassy_sync::pubsub::WaitResult;
+use embassy_time::{Duration, Instant, Timer};
+use embedded_io_async::Write;
+use esp_wifi_manager_core::ws::{
+    accept_key, frame_header, is_upgrade, parse_frame, CLOSE_NORMAL, CLOSE_PROTOCOL_ERROR,
+    OP_BINARY, OP_CLOSE, OP_CONTINUATION, OP_PING, OP_PONG, OP_TEXT,
+};
+
+use crate::api::{error_json, networks_json, status_json, write_error};
+use crate::assets::AssetVars;
+use crate::http_request::HttpRequest;
+use crate::http_server::ResponseWriter;
+use crate::structs::{ConnState, WmEvent, WmInnerSignals};
+
+/// Max websocket clients, at least one http worker is always left for requests
+pub(crate) const WS_MAX_CLIENTS: usize = 2;
+
+/// Client has to answer ping (or send anything) within this time
+const PING_INTERVAL: Duration = Duration::from_secs(20);
+
+/// Live provisioning updates (`/api/v1/events`)
+///
+/// Pushes `{"type":"status",..}` (same as `/api/v1/status`) on every state change
+/// and `{"type":"scan","networks":[..]}` after every scan, both are sent on connect too.
+pub(crate) async fn serve_events(
+    request: &HttpRequest,
+    writer: &mut ResponseWriter<'_, '_>,
+    states: &Rc<WmInnerSignals>,
+    vars: &AssetVars,
+) {
+    let key = request.header("Sec-WebSocket-Key").map(str::trim);
+    let version = request.header("Sec-WebSocket-Version").map(str::trim);
+    let Some(key) = key.filter(|_| is_upgrade(request) && version == Some("13")) else {
+        let body = error_json("upgrade_required", "Websocket upgrade is required").to_string();
+        writer
+            .write_response_with_headers(
+                426,
+                "application/json",
+                &[("Upgrade", "websocket"), ("Sec-WebSocket-Version", "13")],
+                body.as_bytes(),
+            )
+            .await;
+        return;
+    };
+
+    // slots are set by http server (`ws_slots`)
+    let slot = WsSlot::take(states).zip(states.events.subscriber().ok());
+    let Some((_slot, mut events)) = slot else {
+        let message = "Too many websocket clients";
+        write_error(writer, 503, "too_many_clients", message).await;
+        return;
+    };
+
+    let socket = writer.upgrade();
+    let response = alloc::format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key),
+    );
+    if socket.write_all(response.as_bytes()).await.is_err() {
+        return;
+    }
+    log::info!("[ws] client connected");
+
+    let (mut reader, mut writer) = socket.split();
+    let mut conn = WsConnection {
+        writer: &mut writer,
+        states,
+        vars,
+    };
+    if conn.send_status().await.is_err() || conn.send_scan().await.is_err() {
+        return;
+    }
+
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 128];
+    let mut last_seen = Instant::now();
+    let mut ping_sent = false;
+    loop {
+        let res = select3(
+            reader.read(&mut chunk),
+            events.next_message(),
+            Timer::at(last_seen + PING_INTERVAL),
+        )
+        .await;
+        let sent = match res {
+            Either3::First(Ok(0)) | Either3::First(Err(_)) => break,
+            Either3::First(Ok(len)) => {
+                last_seen = Instant::now();
+                ping_sent = false;
+                buffer.extend_from_slice(&chunk[..len]);
+                match conn.handle_frames(&mut buffer).await {
+                    Ok(true) => continue,
+                    Ok(false) | Err(_) => break,
+                }
+            }
+            Either3::Second(WaitResult::Message(WmEvent::Status)) => conn.send_status().await,
+            Either3::Second(WaitResult::Message(WmEvent::Scan)) => conn.send_scan().await,
+            // some events were dropped, send whole state
+            Either3::Second(WaitResult::Lagged(_)) => match conn.send_status().await {
+                Ok(()) => conn.send_scan().await,
+                Err(e) => Err(e),
+            },
+            Either3::Third(()) if ping_sent => {
+                log::info!("[ws] client doesnt answer ping");
+                break;
+            }
+            Either3::Third(()) => {
+                ping_sent = true;
+                // next timer fires one interval later
+                last_seen = Instant::now();
+                write_frame(conn.writer, OP_PING, &[]).await
+            }
+        };
+        if sent.is_err() {
+            break;
+        }
+    }
+    log::info!("[ws] client disconnected");
+}
+
+struct WsConnection<'a, 'w> {
+    writer: &'a mut TcpWriter<'w>,
+    states: &'a Rc<WmInnerSignals>,
+    vars: &'a AssetVars,
+}
+
+impl WsConnection<'_, '_> {
+    async fn send_status(&mut self) -> Result<(), ()> {
+        let status = status_json(self.states, self.vars).with("type", "status");
+        write_frame(self.writer, OP_TEXT, status.to_string().as_bytes()).await?;
+        if let ConnState::Connected(_) = self.states.conn_state.get() {
+            // AP can go down now
+            self.states.result_fetched.signal(());
+        }
+        Ok(())
+    }
+
+    async fn send_scan(&mut self) -> Result<(), ()> {
+        let networks = networks_json(&self.states.wifi_scan_res.lock().await);
+        let scan = networks.with("type", "scan");
+        write_frame(self.writer, OP_TEXT, scan.to_string().as_bytes()).await
+    }
+
+    /// Handles complete frames in `buffer`, returns `false` if connection should close
+    ///
+    /// Client text / binary messages are ignored (updates go one way).
+    async fn handle_frames(&mut self, buffer: &mut Vec<u8>) -> Result<bool, ()> {
+        loop {
+            let frame = match parse_frame(buffer) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => return Ok(true),
+                Err(code) => {
+                    close(self.writer, code).await;
+                    return Ok(false);
+                }
+            };
+
+            match frame.opcode {
+                OP_PING => write_frame(self.writer, OP_PONG, &frame.payload).await?,
+                OP_CLOSE => {
+                    close(self.writer, CLOSE_NORMAL).await;
+                    return Ok(false);
+                }
+                OP_PONG | OP_TEXT | OP_BINARY | OP_CONTINUATION => {}
+                _ => {
+                    close(self.writer, CLOSE_PROTOCOL_ERROR).await;
+                    return Ok(false);
+                }
+            }
+        }
+    }
+}
+
+/// Writes unmasked final frame
+async fn write_frame(writer: &mut TcpWriter<'_>, opcode: u8, payload: &[u8]) -> Result<(), ()> {
+    let header = frame_header(opcode, payload.len());
+    writer.write_all(&header).await.map_err(|_| ())?;
+    writer.write_all(payload).await.map_err(|_| ())?;
+    writer.flush().await.map_err(|_| ())
+}
+
+async fn close(writer: &mut TcpWriter<'_>, code: u16) {
+    _ = write_frame(writer, OP_CLOSE, &code.to_be_bytes()).await;
+}
+
+/// Websocket slot, returned on drop
+struct WsSlot<'a>(&'a WmInnerSignals);
+
+impl<'a> WsSlot<'a> {
+    fn take(states: &'a WmInnerSignals) -> Option<Self> {
+        let free = states.ws_slots.get();
+        if free == 0 {
+            return None;
+        }
+        states.ws_slots.set(free - 1);
+        Some(Self(states))
+    }
+}
+
+impl Drop for WsSlot<'_> {
+    fn drop(&mut self) {
+        self.0.ws_slots.set(self.0.ws_slots.get() + 1);
+    }
+}